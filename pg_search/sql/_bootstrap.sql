@@ -99,6 +99,11 @@ END $$;
 --   numeric_fields: JSON object representing the numeric fields for the index.
 --   boolean_fields: JSON object representing the boolean fields for the index.
 --   json_fields: JSON object representing the json fields for the index.
+--   predicates: A WHERE clause condition, to only index the rows that satisfy it.
+--   expressions: JSON object mapping field names to SQL expressions, whose values are indexed under those names.
+--   merge_policy: JSON object configuring how the index's segments are merged in the background.
+--   writer_memory_budget: Memory budget of the index's writer in megabytes, or 0 for the server's default.
+--   writer_threads: Number of threads that the index's writer indexes with, or 0 for the server's default.
 CREATE OR REPLACE PROCEDURE paradedb.create_bm25(
     index_name text DEFAULT '',
     table_name text DEFAULT '',
@@ -107,11 +112,22 @@ CREATE OR REPLACE PROCEDURE paradedb.create_bm25(
     text_fields text DEFAULT '{}',
     numeric_fields text DEFAULT '{}',
     boolean_fields text DEFAULT '{}',
-    json_fields text DEFAULT '{}'
+    json_fields text DEFAULT '{}',
+    predicates text DEFAULT '',
+    expressions text DEFAULT '{}',
+    merge_policy text DEFAULT '',
+    writer_memory_budget integer DEFAULT 0,
+    writer_threads integer DEFAULT 0
 )
 LANGUAGE plpgsql AS $$
 DECLARE
     index_json JSONB;
+    index_predicates TEXT;
+    search_predicates TEXT;
+    index_expressions TEXT := '';
+    expression_fields JSONB := '[]';
+    expression RECORD;
+    table_partition RECORD;
     original_client_min_messages TEXT;
 BEGIN
     SELECT INTO original_client_min_messages current_setting('client_min_messages');
@@ -146,10 +162,47 @@ BEGIN
     -- Create the new, empty schema.
     EXECUTE format('CREATE SCHEMA %s', index_name);
 
-    -- Create a new BM25 index on the specified table.
-    -- The index is created dynamically based on the function parameters.
-    EXECUTE format('CREATE INDEX %s_bm25_index ON %I.%I USING bm25 ((%I.*)) WITH (key_field=%L, text_fields=%L, numeric_fields=%L, boolean_fields=%L, json_fields=%L);',
-                   index_name, schema_name, table_name, table_name, key_field, text_fields, numeric_fields, boolean_fields, json_fields);
+    -- Only rows satisfying the predicates are indexed. The search function repeats the
+    -- predicates, so that the planner knows it can use the partial index.
+    IF predicates IS NULL OR predicates = '' THEN
+        index_predicates := '';
+        search_predicates := '';
+    ELSE
+        index_predicates := format(' WHERE %s', predicates);
+        search_predicates := format(' AND (%s)', predicates);
+    END IF;
+
+    -- Each expression becomes an index column after the indexed row. The index learns which
+    -- field each column is indexed as from the expression_fields option, in the same order.
+    FOR expression IN SELECT * FROM jsonb_each_text(COALESCE(NULLIF(expressions, ''), '{}')::jsonb) LOOP
+        index_expressions := index_expressions || format(', (%s)', expression.value);
+        expression_fields := expression_fields || to_jsonb(expression.key);
+    END LOOP;
+
+    IF EXISTS (SELECT FROM pg_catalog.pg_class WHERE oid = format('%I.%I', schema_name, table_name)::regclass AND relkind = 'p') THEN
+        -- Postgres can't create an index with a whole-row expression on a partitioned table, so
+        -- each partition gets its own BM25 index, named by partition_of as part of this index.
+        -- Partitions index their rows as rows of the partitioned table, so that the planner can
+        -- use their indexes when the partitioned table is queried.
+        FOR table_partition IN
+            SELECT partition_class.relname, partition_namespace.nspname
+            FROM pg_partition_tree(format('%I.%I', schema_name, table_name)::regclass) tree
+            JOIN pg_catalog.pg_class partition_class ON partition_class.oid = tree.relid
+            JOIN pg_catalog.pg_namespace partition_namespace ON partition_namespace.oid = partition_class.relnamespace
+            WHERE tree.isleaf
+        LOOP
+            EXECUTE format('CREATE INDEX %I ON %I.%I USING bm25 (((%I.*)::%I.%I)%s) WITH (key_field=%L, text_fields=%L, numeric_fields=%L, boolean_fields=%L, json_fields=%L, expression_fields=%L, partition_of=%L, merge_policy=%L, writer_memory_budget=%s, writer_threads=%s)%s;',
+                           format('%s_%s_bm25_index', index_name, table_partition.relname), table_partition.nspname, table_partition.relname, table_partition.relname, schema_name, table_name, index_expressions,
+                           key_field, text_fields, numeric_fields, boolean_fields, json_fields, expression_fields, format('%s_bm25_index', index_name), COALESCE(merge_policy, ''),
+                           COALESCE(writer_memory_budget, 0), COALESCE(writer_threads, 0), index_predicates);
+        END LOOP;
+    ELSE
+        -- Create a new BM25 index on the specified table.
+        -- The index is created dynamically based on the function parameters.
+        EXECUTE format('CREATE INDEX %s_bm25_index ON %I.%I USING bm25 ((%I.*)%s) WITH (key_field=%L, text_fields=%L, numeric_fields=%L, boolean_fields=%L, json_fields=%L, expression_fields=%L, merge_policy=%L, writer_memory_budget=%s, writer_threads=%s)%s;',
+                       index_name, schema_name, table_name, table_name, index_expressions, key_field, text_fields, numeric_fields, boolean_fields, json_fields, expression_fields, COALESCE(merge_policy, ''),
+                       COALESCE(writer_memory_budget, 0), COALESCE(writer_threads, 0), index_predicates);
+    END IF;
 
     -- Dynamically create a new function for performing searches on the indexed table.
     -- The variable '__paradedb_search_config__' is available to the function_body parameter.
@@ -159,7 +212,7 @@ BEGIN
     EXECUTE paradedb.format_bm25_function(
         function_name => format('%I.search', index_name),        	
         return_type => format('SETOF %I.%I', schema_name, table_name),
-        function_body => format('RETURN QUERY SELECT * FROM %I.%I WHERE %I @@@ __paradedb_search_config__', schema_name, table_name, table_name),
+        function_body => format('RETURN QUERY SELECT * FROM %I.%I WHERE %I @@@ __paradedb_search_config__%s', schema_name, table_name, table_name, search_predicates),
         index_json => index_json
     );
 
@@ -366,14 +419,45 @@ CREATE OR REPLACE PROCEDURE paradedb.drop_bm25(
 LANGUAGE plpgsql AS $$
 DECLARE 
     original_client_min_messages TEXT;
+    partition_index RECORD;
 BEGIN
     SELECT INTO original_client_min_messages current_setting('client_min_messages');
     SET client_min_messages TO WARNING;
 
+    -- The data of a bm25 index is removed when the transaction that drops it commits.
     EXECUTE format('DROP INDEX IF EXISTS %s.%s_bm25_index', schema_name, index_name); 
     EXECUTE format('DROP SCHEMA IF EXISTS %s CASCADE', index_name);
-    PERFORM paradedb.drop_bm25_internal(format('%s_bm25_index', index_name));
+
+    -- The index of a partitioned table is made of an index on each of its partitions.
+    FOR partition_index IN
+        SELECT oid::regclass AS index_oid FROM pg_catalog.pg_class
+        WHERE relkind = 'i' AND reloptions @> ARRAY[format('partition_of=%s_bm25_index', index_name)]
+    LOOP
+        EXECUTE format('DROP INDEX IF EXISTS %s', partition_index.index_oid);
+    END LOOP;
 
     EXECUTE 'SET client_min_messages TO ' || quote_literal(original_client_min_messages);
   END;
 $$;
+
+-- The progress of bm25 index builds, with the number of bytes written to the index so far, which
+-- is reported in a progress parameter that pg_stat_progress_create_index doesn't show.
+CREATE OR REPLACE VIEW paradedb.bm25_build_progress AS
+SELECT
+    progress.pid,
+    progress.datname,
+    progress.relid,
+    progress.index_relid,
+    progress.command,
+    progress.phase,
+    progress.blocks_total,
+    progress.blocks_done,
+    progress.tuples_total,
+    progress.tuples_done,
+    info.param18 AS bytes_written
+FROM pg_catalog.pg_stat_progress_create_index progress
+JOIN pg_catalog.pg_stat_get_progress_info('CREATE INDEX') info ON info.pid = progress.pid
+-- The index being built isn't visible in pg_class until its transaction commits, so its access
+-- method is read from the progress parameters instead.
+JOIN pg_catalog.pg_am am ON am.oid = info.param9::oid
+WHERE am.amname = 'bm25';
@@ -0,0 +1,52 @@
+\echo Use "ALTER EXTENSION pg_search UPDATE TO '0.6.3'" to load this file. \quit
+
+-- The data of a bm25 index is now removed when the index is dropped, however it's dropped, so
+-- drop_bm25 no longer removes it itself.
+CREATE OR REPLACE PROCEDURE paradedb.drop_bm25(
+    index_name text,
+    schema_name text DEFAULT CURRENT_SCHEMA
+)
+LANGUAGE plpgsql AS $$
+DECLARE 
+    original_client_min_messages TEXT;
+    partition_index RECORD;
+BEGIN
+    SELECT INTO original_client_min_messages current_setting('client_min_messages');
+    SET client_min_messages TO WARNING;
+
+    -- The data of a bm25 index is removed when the transaction that drops it commits.
+    EXECUTE format('DROP INDEX IF EXISTS %s.%s_bm25_index', schema_name, index_name); 
+    EXECUTE format('DROP SCHEMA IF EXISTS %s CASCADE', index_name);
+
+    -- The index of a partitioned table is made of an index on each of its partitions.
+    FOR partition_index IN
+        SELECT oid::regclass AS index_oid FROM pg_catalog.pg_class
+        WHERE relkind = 'i' AND reloptions @> ARRAY[format('partition_of=%s_bm25_index', index_name)]
+    LOOP
+        EXECUTE format('DROP INDEX IF EXISTS %s', partition_index.index_oid);
+    END LOOP;
+
+    EXECUTE 'SET client_min_messages TO ' || quote_literal(original_client_min_messages);
+  END;
+$$;
+
+DROP FUNCTION IF EXISTS paradedb.drop_bm25_internal(text);
+
+-- The <=> operator gives the distance of a row from a search, which is its negated score, so
+-- that a bm25 index can return rows in score order for an ORDER BY.
+CREATE FUNCTION paradedb.search_tantivy_distance(
+    "element" anyelement,
+    "config_json" jsonb
+) RETURNS real
+STRICT
+LANGUAGE c
+AS 'MODULE_PATHNAME', 'search_tantivy_distance_wrapper';
+
+CREATE OPERATOR pg_catalog.<=> (
+    PROCEDURE = paradedb.search_tantivy_distance,
+    LEFTARG = anyelement,
+    RIGHTARG = jsonb
+);
+
+ALTER OPERATOR FAMILY paradedb.anyelement_bm25_ops USING bm25
+    ADD OPERATOR 2 pg_catalog.<=>(anyelement, jsonb) FOR ORDER BY pg_catalog.float_ops;
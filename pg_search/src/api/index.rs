@@ -1,7 +1,8 @@
 use pgrx::{iter::TableIterator, *};
 use tantivy::schema::*;
 
-use crate::postgres::utils::get_search_index;
+use crate::globals::WriterGlobal;
+use crate::postgres::utils::get_search_indexes;
 use crate::query::SearchQueryInput;
 use crate::schema::ToString;
 use core::panic;
@@ -24,7 +25,11 @@ pub fn schema_bm25(
     name!(normalizer, Option<String>),
 )> {
     let bm25_index_name = format!("{}_bm25_index", index_name);
-    let search_index = get_search_index(&bm25_index_name);
+    // The indexes on the partitions of a partitioned table all share the same schema.
+    let search_index = get_search_indexes(&bm25_index_name)
+        .into_iter()
+        .next()
+        .unwrap_or_else(|| panic!("index {bm25_index_name} has no partitions"));
     let schema = search_index.schema.schema.clone();
     let mut field_entries: Vec<_> = schema.fields().collect();
 
@@ -90,6 +95,24 @@ pub fn schema_bm25(
     TableIterator::new(field_rows)
 }
 
+/// Merge the segments of a bm25 index, so that it has at most `max_segments` of them. Searches
+/// have fewer segments to read afterwards, and the space of deleted documents is reclaimed.
+/// The index of a partitioned table is optimized one partition at a time.
+#[pg_extern]
+pub fn optimize_bm25(index_name: &str, max_segments: default!(i32, 1)) {
+    if max_segments < 1 {
+        panic!("max_segments must be at least 1, not {max_segments}");
+    }
+
+    let bm25_index_name = format!("{}_bm25_index", index_name);
+    let writer_client = WriterGlobal::client();
+    for search_index in get_search_indexes(&bm25_index_name) {
+        search_index
+            .optimize(&writer_client, max_segments as usize)
+            .unwrap_or_else(|err| panic!("error optimizing index {bm25_index_name}: {err:?}"));
+    }
+}
+
 #[pg_extern(immutable, parallel_safe)]
 pub fn all() -> SearchQueryInput {
     SearchQueryInput::All
@@ -1,3 +1,5 @@
 mod index;
 mod operator;
 mod search;
+mod stats;
+mod verify;
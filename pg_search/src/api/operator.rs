@@ -1,9 +1,10 @@
-use crate::env::needs_commit;
 use crate::index::state::SearchStateManager;
+use crate::index::SearchIndex;
+use crate::postgres::utils::search_states;
 use crate::schema::SearchConfig;
-use crate::{globals::WriterGlobal, postgres::utils::get_search_index};
 use pgrx::{prelude::PgHeapTuple, *};
-use rustc_hash::FxHashSet;
+use rustc_hash::{FxHashMap, FxHashSet};
+use tantivy::Score;
 
 #[pg_extern]
 fn search_tantivy(
@@ -16,19 +17,19 @@ fn search_tantivy(
         let search_config: SearchConfig = serde_json::from_value(search_config_json.clone())
             .expect("could not parse search config");
 
-        let writer_client = WriterGlobal::client();
-        let search_index = get_search_index(&search_config.index_name);
-        let scan_state = search_index
-            .search_state(&writer_client, &search_config, needs_commit())
-            .unwrap();
-        let top_docs = scan_state.search(search_index.executor);
         let mut hs = FxHashSet::default();
 
-        for (_score, _doc_address, key, _ctid) in top_docs {
-            hs.insert(key);
+        // A search through the index on a partitioned table reads each partition's index.
+        for scan_state in search_states(&search_config) {
+            let top_docs = scan_state.search(SearchIndex::executor());
+            for (_score, _doc_address, key, _ctid) in top_docs {
+                hs.insert(key);
+            }
+
+            SearchStateManager::set_state(scan_state)
+                .expect("could not store search state in manager");
         }
 
-        SearchStateManager::set_state(scan_state).expect("could not store search state in manager");
         (search_config, hs)
     };
 
@@ -36,12 +37,52 @@ fn search_tantivy(
     let search_config = &cached.0;
     let hash_set = &cached.1;
 
+    let key_field_value = key_field_value(&element, &search_config.key_field);
+    hash_set.contains(&key_field_value)
+}
+
+/// The ordering operator for bm25 indexes. It returns the negated BM25 score of a row, so
+/// that `ORDER BY row <=> config` lists the best matches first, and NULL for rows that don't
+/// match the query. When the planner uses the index for the ORDER BY, this function isn't
+/// called, and rows are returned in score order straight from the index.
+#[pg_extern]
+fn search_tantivy_distance(
+    element: AnyElement,
+    config_json: JsonB,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<f32> {
+    let default_hash_map = || {
+        let JsonB(search_config_json) = &config_json;
+        let search_config: SearchConfig = serde_json::from_value(search_config_json.clone())
+            .expect("could not parse search config");
+
+        let mut hm: FxHashMap<i64, Score> = FxHashMap::default();
+
+        for scan_state in search_states(&search_config) {
+            let top_docs = scan_state.search(SearchIndex::executor());
+            for (score, _doc_address, key, _ctid) in top_docs {
+                hm.insert(key, score);
+            }
+        }
+
+        (search_config, hm)
+    };
+
+    let cached = unsafe { pg_func_extra(fcinfo, default_hash_map) };
+    let search_config = &cached.0;
+    let hash_map = &cached.1;
+
+    let key_field_value = key_field_value(&element, &search_config.key_field);
+
+    hash_map.get(&key_field_value).map(|score| -score)
+}
+
+fn key_field_value(element: &AnyElement, key_field_name: &str) -> i64 {
     let heap_tuple = unsafe { PgHeapTuple::from_composite_datum(element.datum()) };
-    let key_field_name = &search_config.key_field;
 
     // Only i64 values (bigint in Postgres) are currently supported for the key_field.
     // We'll panic below if what's passed is anything other than an i64.
-    let key_field_value: i64 = match heap_tuple.get_by_name(key_field_name) {
+    match heap_tuple.get_by_name(key_field_name) {
         Err(TryFromDatumError::NoSuchAttributeName(_))
         | Err(TryFromDatumError::NoSuchAttributeNumber(_)) => {
             panic!("no key_field '{key_field_name}' found on tuple");
@@ -53,9 +94,7 @@ fn search_tantivy(
             panic!("no value present in key_field {key_field_name} in tuple")
         }
         Ok(Some(value)) => value,
-    };
-
-    hash_set.contains(&key_field_value)
+    }
 }
 
 extension_sql!(
@@ -66,8 +105,15 @@ CREATE OPERATOR pg_catalog.@@@ (
     RIGHTARG = jsonb
 );
 
+CREATE OPERATOR pg_catalog.<=> (
+    PROCEDURE = search_tantivy_distance,
+    LEFTARG = anyelement,
+    RIGHTARG = jsonb
+);
+
 CREATE OPERATOR CLASS anyelement_bm25_ops DEFAULT FOR TYPE anyelement USING bm25 AS
     OPERATOR 1 pg_catalog.@@@(anyelement, jsonb),
+    OPERATOR 2 pg_catalog.<=>(anyelement, jsonb) FOR ORDER BY pg_catalog.float_ops,
     STORAGE anyelement;
 
 "#,
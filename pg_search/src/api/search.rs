@@ -1,8 +1,7 @@
-use crate::env::needs_commit;
 use crate::index::state::{SearchAlias, SearchStateManager};
+use crate::index::SearchIndex;
+use crate::postgres::utils::search_states;
 use crate::schema::SearchConfig;
-use crate::writer::{WriterClient, WriterDirectory};
-use crate::{globals::WriterGlobal, index::SearchIndex, postgres::utils::get_search_index};
 use pgrx::{prelude::TableIterator, *};
 
 const DEFAULT_SNIPPET_PREFIX: &str = "<b>";
@@ -53,20 +52,23 @@ pub fn minmax_bm25(
     let JsonB(search_config_json) = config_json;
     let search_config: SearchConfig =
         serde_json::from_value(search_config_json).expect("could not parse search config");
-    let search_index = get_search_index(&search_config.index_name);
 
-    let writer_client = WriterGlobal::client();
-    let mut scan_state = search_index
-        .search_state(&writer_client, &search_config, needs_commit())
-        .unwrap();
-
-    // Collect into a Vec to allow multiple iterations
-    let top_docs: Vec<_> = scan_state.search_dedup(search_index.executor).collect();
+    // Collect into a Vec to allow multiple iterations. A search through the index on a
+    // partitioned table collects the results of each partition, along with its state.
+    let mut scan_states = search_states(&search_config);
+    let mut top_docs = Vec::new();
+    for (partition, scan_state) in scan_states.iter_mut().enumerate() {
+        top_docs.extend(
+            scan_state
+                .search_dedup(SearchIndex::executor())
+                .map(|(score, doc_address)| (score, doc_address, partition)),
+        );
+    }
 
     // Calculate min and max scores
     let (min_score, max_score) = top_docs
         .iter()
-        .map(|(score, _)| score)
+        .map(|(score, _, _)| score)
         .fold((f32::MAX, f32::MIN), |(min, max), bm25| {
             (min.min(*bm25), max.max(*bm25))
         });
@@ -74,8 +76,8 @@ pub fn minmax_bm25(
 
     // Now that we have min and max, iterate over the collected results
     let mut field_rows = Vec::new();
-    for (score, doc_address) in top_docs {
-        let key = scan_state.key_value(doc_address);
+    for (score, doc_address, partition) in top_docs {
+        let key = scan_states[partition].key_value(doc_address);
         let normalized_score = if score_range == 0.0 {
             1.0 // Avoid division by zero
         } else {
@@ -86,21 +88,3 @@ pub fn minmax_bm25(
     }
     TableIterator::new(field_rows)
 }
-
-#[pg_extern]
-fn drop_bm25_internal(index_name: &str) {
-    let writer_client = WriterGlobal::client();
-    let writer_directory = WriterDirectory::from_index_name(index_name);
-    if needs_commit() {
-        writer_client
-            .lock()
-            .expect("could not lock writer on drop_bm25")
-            .request(crate::writer::WriterRequest::Commit {
-                directory: writer_directory,
-            })
-            .expect("error committing existing transaction during drop_bm25");
-    }
-    // Drop the Tantivy data directory.
-    SearchIndex::drop_index(&writer_client, index_name)
-        .unwrap_or_else(|err| panic!("error dropping index {index_name}: {err}"));
-}
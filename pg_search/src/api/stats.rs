@@ -0,0 +1,97 @@
+use pgrx::{iter::TableIterator, *};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::postgres::utils::{get_search_index, lookup_index_oid, lookup_partition_index_oids};
+
+type StatsRow = (
+    String,
+    i64,
+    i64,
+    i64,
+    i64,
+    i64,
+    i64,
+    i64,
+    i64,
+    i64,
+    i64,
+    Option<TimestampWithTimeZone>,
+);
+
+/// The segments, documents and on-disk size of bm25 indexes, as of their last commit. Without
+/// an index name, every bm25 index in the database is listed, including those of partitions.
+#[allow(clippy::type_complexity)]
+#[pg_extern]
+pub fn bm25_index_stats(
+    index_name: default!(Option<&str>, "NULL"),
+) -> TableIterator<(
+    name!(index_name, String),
+    name!(num_segments, i64),
+    name!(live_docs, i64),
+    name!(deleted_docs, i64),
+    name!(postings_bytes, i64),
+    name!(positions_bytes, i64),
+    name!(fast_fields_bytes, i64),
+    name!(store_bytes, i64),
+    name!(other_bytes, i64),
+    name!(total_bytes, i64),
+    name!(last_commit_opstamp, i64),
+    name!(last_merge_time, Option<TimestampWithTimeZone>),
+)> {
+    let index_oids = match index_name {
+        Some(index_name) => {
+            let bm25_index_name = format!("{}_bm25_index", index_name);
+            lookup_partition_index_oids(&bm25_index_name)
+                .unwrap_or_else(|| vec![lookup_index_oid(&bm25_index_name)])
+        }
+        None => lookup_all_index_oids(),
+    };
+
+    let rows: Vec<StatsRow> = index_oids.into_iter().map(index_stats).collect();
+    TableIterator::new(rows)
+}
+
+fn index_stats(index_oid: pg_sys::Oid) -> StatsRow {
+    let index_relation =
+        unsafe { PgRelation::with_lock(index_oid, pg_sys::AccessShareLock as pg_sys::LOCKMODE) };
+    let index_name = index_relation.name().to_string();
+
+    let stats = get_search_index(index_oid)
+        .stats()
+        .unwrap_or_else(|err| panic!("could not read stats of index {index_name}: {err}"));
+
+    (
+        index_name,
+        stats.num_segments as i64,
+        stats.live_docs as i64,
+        stats.deleted_docs as i64,
+        stats.postings_bytes as i64,
+        stats.positions_bytes as i64,
+        stats.fast_fields_bytes as i64,
+        stats.store_bytes as i64,
+        stats.other_bytes as i64,
+        stats.total_bytes() as i64,
+        stats.opstamp as i64,
+        stats.last_merge_time.and_then(to_timestamp),
+    )
+}
+
+/// The oids of every bm25 index in the database, ordered by name.
+fn lookup_all_index_oids() -> Vec<pg_sys::Oid> {
+    Spi::get_one::<Vec<pg_sys::Oid>>(
+        "SELECT ARRAY(
+            SELECT pg_class.oid FROM pg_class
+            JOIN pg_am ON pg_am.oid = pg_class.relam
+            WHERE relkind = 'i' AND amname = 'bm25'
+            ORDER BY relname
+        )",
+    )
+    .unwrap_or_else(|err| panic!("could not look up bm25 indexes: {err}"))
+    .unwrap_or_default()
+}
+
+fn to_timestamp(time: SystemTime) -> Option<TimestampWithTimeZone> {
+    let seconds = time.duration_since(UNIX_EPOCH).ok()?.as_secs();
+    let timestamp = unsafe { pg_sys::time_t_to_timestamptz(seconds as pg_sys::pg_time_t) };
+    TimestampWithTimeZone::try_from(timestamp).ok()
+}
@@ -0,0 +1,415 @@
+use pgrx::{iter::TableIterator, *};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+use crate::env::{current_xid, register_commit_callback};
+use crate::globals::WriterGlobal;
+use crate::postgres::insert::reinsert_heap_rows;
+use crate::postgres::utils::{
+    get_search_index, lookup_index_expressions, lookup_index_oid, lookup_index_tupdesc,
+    lookup_partition_index_oids,
+};
+use crate::schema::SearchFieldConfig;
+
+/// A way in which a bm25 index doesn't match its table.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Problem {
+    /// A row of the table that has no document in the index.
+    MissingRow,
+    /// A document whose row isn't in the table, or isn't visible to the check.
+    OrphanedDocument,
+    /// A key that more than one document has.
+    DuplicateKey,
+    /// A document whose key isn't the key of its row.
+    KeyMismatch,
+    /// A field of the index that doesn't match its configuration, or its table.
+    SchemaMismatch,
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Problem::MissingRow => "missing_row",
+            Problem::OrphanedDocument => "orphaned_document",
+            Problem::DuplicateKey => "duplicate_key",
+            Problem::KeyMismatch => "key_mismatch",
+            Problem::SchemaMismatch => "schema_mismatch",
+        };
+        write!(f, "{name}")
+    }
+}
+
+type VerifyRow = (String, String, Option<String>, Option<i64>, String, bool);
+
+/// Checks that a bm25 index matches its table, like `amcheck` does for Postgres' own indexes.
+/// Every document's ctid is looked up in the table, and with `heapallindexed`, every row of the
+/// table is looked up in the index too. With `repair`, the documents that don't match their
+/// rows are removed, and the rows that are missing or were removed are indexed again. Schema
+/// mismatches can't be repaired, and need the index to be rebuilt with REINDEX.
+#[allow(clippy::type_complexity)]
+#[pg_extern]
+pub fn verify_bm25(
+    index_name: &str,
+    heapallindexed: default!(bool, false),
+    repair: default!(bool, false),
+) -> TableIterator<(
+    name!(index_name, String),
+    name!(problem, String),
+    name!(ctid, Option<String>),
+    name!(key, Option<i64>),
+    name!(detail, String),
+    name!(repaired, bool),
+)> {
+    let bm25_index_name = format!("{}_bm25_index", index_name);
+    // The indexes on the partitions of a partitioned table are each checked against their own
+    // partition.
+    let index_oids = lookup_partition_index_oids(&bm25_index_name)
+        .unwrap_or_else(|| vec![lookup_index_oid(&bm25_index_name)]);
+
+    let rows: Vec<VerifyRow> = index_oids
+        .into_iter()
+        .flat_map(|index_oid| verify_index(index_oid, heapallindexed, repair))
+        .collect();
+
+    TableIterator::new(rows)
+}
+
+fn verify_index(index_oid: pg_sys::Oid, heapallindexed: bool, repair: bool) -> Vec<VerifyRow> {
+    // A repair takes a ShareLock on the table, which waits for the transactions that are
+    // writing to it to finish, and keeps new ones out until the repair commits. Otherwise the
+    // rows of transactions that are still in progress would be taken for orphans and removed.
+    // The table is locked before its index, as Postgres does.
+    let (heap_lock_mode, index_lock_mode) = if repair {
+        (pg_sys::ShareLock, pg_sys::RowExclusiveLock)
+    } else {
+        (pg_sys::AccessShareLock, pg_sys::AccessShareLock)
+    };
+    let heap_oid = unsafe { pg_sys::IndexGetRelation(index_oid, false) };
+    let heap_relation =
+        unsafe { PgRelation::with_lock(heap_oid, heap_lock_mode as pg_sys::LOCKMODE) };
+    let index_relation =
+        unsafe { PgRelation::with_lock(index_oid, index_lock_mode as pg_sys::LOCKMODE) };
+    let index_name = index_relation.name().to_string();
+
+    // The transactions that committed while the lock was awaited must be visible to the
+    // check, so it takes a snapshot of its own.
+    if repair {
+        unsafe { pg_sys::PushActiveSnapshot(pg_sys::GetLatestSnapshot()) };
+    }
+
+    let search_index = get_search_index(index_oid);
+    search_index
+        .reader
+        .reload()
+        .unwrap_or_else(|err| panic!("could not reload index {index_name}: {err}"));
+
+    let mut problems: Vec<(Problem, Option<u64>, Option<i64>, String)> = vec![];
+
+    // The fields that Tantivy stored with the index must match the fields it was configured
+    // with, and each must be a column of the table, or one of the index's expressions.
+    let tantivy_schema = search_index.tantivy_schema();
+    let mut columns: HashSet<String> = lookup_index_tupdesc(&index_relation)
+        .iter()
+        .filter(|attribute| !attribute.is_dropped())
+        .map(|attribute| attribute.name().to_string())
+        .collect();
+    columns.extend(
+        lookup_index_expressions(&index_relation)
+            .into_iter()
+            .map(|expression| expression.name.0),
+    );
+    for field in &search_index.schema.fields {
+        let name: &str = field.name.as_ref();
+        let configured = search_index.schema.schema.get_field_entry(field.id.0);
+        match tantivy_schema.get_field(name) {
+            Ok(stored) if tantivy_schema.get_field_entry(stored) == configured => {}
+            Ok(_) => problems.push((
+                Problem::SchemaMismatch,
+                None,
+                None,
+                format!(
+                    "field '{name}' is stored with different options than it's configured with"
+                ),
+            )),
+            Err(_) => problems.push((
+                Problem::SchemaMismatch,
+                None,
+                None,
+                format!("field '{name}' is configured, but not stored in the index"),
+            )),
+        }
+        if !matches!(field.config, SearchFieldConfig::Ctid) && !columns.contains(name) {
+            problems.push((
+                Problem::SchemaMismatch,
+                None,
+                None,
+                format!("field '{name}' is not a column of the table"),
+            ));
+        }
+    }
+    for (_, entry) in tantivy_schema.fields() {
+        if search_index.schema.schema.get_field(entry.name()).is_err() {
+            problems.push((
+                Problem::SchemaMismatch,
+                None,
+                None,
+                format!(
+                    "field '{}' is stored in the index, but not configured",
+                    entry.name()
+                ),
+            ));
+        }
+    }
+
+    let documents = search_index.ctids_and_keys();
+    let key_field = search_index.schema.key_field().name.0;
+    let ctids = (!heapallindexed).then(|| documents.iter().map(|(ctid, _)| *ctid).collect());
+    let heap_rows = lookup_heap_rows(index_oid, &heap_relation, &key_field, ctids);
+
+    let mut keys: HashMap<i64, Vec<u64>> = HashMap::new();
+    let mut indexed_ctids = HashSet::new();
+    for (ctid, key) in &documents {
+        indexed_ctids.insert(*ctid);
+        if let Some(key) = key {
+            keys.entry(*key).or_default().push(*ctid);
+        }
+
+        match heap_rows.get(ctid) {
+            None => problems.push((
+                Problem::OrphanedDocument,
+                Some(*ctid),
+                *key,
+                "the document's row is not in the table".into(),
+            )),
+            Some(row_key) if row_key != key => problems.push((
+                Problem::KeyMismatch,
+                Some(*ctid),
+                *key,
+                format!(
+                    "the document's row has key {}",
+                    row_key.map_or("NULL".into(), |key| key.to_string())
+                ),
+            )),
+            Some(_) => {}
+        }
+    }
+
+    let mut duplicate_keys: Vec<_> = keys
+        .into_iter()
+        .filter(|(_, ctids)| ctids.len() > 1)
+        .collect();
+    duplicate_keys.sort();
+    for (key, ctids) in duplicate_keys {
+        for ctid in &ctids {
+            problems.push((
+                Problem::DuplicateKey,
+                Some(*ctid),
+                Some(key),
+                format!("{} documents have this key", ctids.len()),
+            ));
+        }
+    }
+
+    if heapallindexed {
+        let mut missing: Vec<_> = heap_rows
+            .iter()
+            .filter(|(ctid, _)| !indexed_ctids.contains(ctid))
+            .collect();
+        missing.sort();
+        for (ctid, key) in missing {
+            problems.push((
+                Problem::MissingRow,
+                Some(*ctid),
+                *key,
+                "the row has no document in the index".into(),
+            ));
+        }
+    }
+
+    let repaired = repair && repair_index(&index_relation, &heap_relation, &problems, &heap_rows);
+    if repair {
+        unsafe { pg_sys::PopActiveSnapshot() };
+    }
+
+    problems
+        .into_iter()
+        .map(|(problem, ctid, key, detail)| {
+            (
+                index_name.clone(),
+                problem.to_string(),
+                ctid.map(format_ctid),
+                key,
+                detail,
+                repaired && problem != Problem::SchemaMismatch,
+            )
+        })
+        .collect()
+}
+
+/// The ctid and key of the rows of the index's table that satisfy its predicate, and are
+/// visible to the current snapshot. If `ctids` is given, only the rows of those documents are
+/// looked up.
+///
+/// A row that was updated without changing its indexed columns has a heap-only version that
+/// isn't indexed, and its document keeps the ctid of the root of its HOT chain, as in Postgres'
+/// own indexes. So rows are keyed by the ctid of their root.
+fn lookup_heap_rows(
+    index_oid: pg_sys::Oid,
+    heap_relation: &PgRelation,
+    key_field: &str,
+    ctids: Option<Vec<u64>>,
+) -> HashMap<u64, Option<i64>> {
+    let predicate = Spi::get_one_with_args::<String>(
+        "SELECT pg_get_expr(indpred, indrelid) FROM pg_index WHERE indexrelid = $1",
+        vec![(PgBuiltInOids::OIDOID.oid(), index_oid.into_datum())],
+    )
+    .unwrap_or_else(|err| panic!("could not look up predicate of index {index_oid:?}: {err}"));
+
+    let mut conditions = vec![];
+    if let Some(predicate) = predicate {
+        conditions.push(format!("({predicate})"));
+    }
+    let args = ctids.map(|ctids| {
+        // A HOT chain doesn't leave its page, so the current version of a document's row is on
+        // the same page as the document's ctid.
+        conditions.push("(ctid::text::point)[0]::bigint = ANY($1)".to_string());
+        let blocks: Vec<i64> = ctids
+            .into_iter()
+            .map(|ctid| {
+                let mut item_pointer = pg_sys::ItemPointerData::default();
+                u64_to_item_pointer(ctid, &mut item_pointer);
+                item_pointer_get_both(item_pointer).0 as i64
+            })
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .collect();
+        vec![(PgBuiltInOids::INT8ARRAYOID.oid(), blocks.into_datum())]
+    });
+
+    let query = format!(
+        "SELECT ctid, {}::bigint FROM ONLY {}{}",
+        spi::quote_identifier(key_field),
+        spi::quote_qualified_identifier(heap_relation.namespace(), heap_relation.name()),
+        if conditions.is_empty() {
+            String::new()
+        } else {
+            format!(" WHERE {}", conditions.join(" AND "))
+        }
+    );
+
+    let rows = Spi::connect(|client| {
+        let mut rows = vec![];
+        for row in client.select(&query, None, args)? {
+            let ctid = row
+                .get::<pg_sys::ItemPointerData>(1)?
+                .expect("ctid should not be null");
+            rows.push((ctid, row.get::<i64>(2)?));
+        }
+        Ok::<_, spi::Error>(rows)
+    })
+    .unwrap_or_else(|err| panic!("could not look up rows of index {index_oid:?}: {err}"));
+
+    let roots = unsafe { hot_chain_roots(heap_relation, rows.iter().map(|(ctid, _)| *ctid)) };
+    rows.into_iter()
+        .map(|(ctid, key)| (roots[&item_pointer_to_u64(ctid)], key))
+        .collect()
+}
+
+/// Map each of the given tuples to the root of its HOT chain, which is the tuple itself if it
+/// isn't heap-only. The roots are found with `heap_get_root_tuples`, as `amcheck` does.
+unsafe fn hot_chain_roots(
+    heap_relation: &PgRelation,
+    ctids: impl Iterator<Item = pg_sys::ItemPointerData>,
+) -> HashMap<u64, u64> {
+    let mut pages: HashMap<pg_sys::BlockNumber, Vec<pg_sys::OffsetNumber>> = HashMap::new();
+    for ctid in ctids {
+        let (block, offset) = item_pointer_get_both(ctid);
+        pages.entry(block).or_default().push(offset);
+    }
+
+    // Large enough for the line pointers of any page.
+    let mut root_offsets: Vec<pg_sys::OffsetNumber> =
+        vec![0; pg_sys::BLCKSZ as usize / std::mem::size_of::<pg_sys::ItemIdData>()];
+    let mut roots = HashMap::new();
+    for (block, offsets) in pages {
+        let buffer = pg_sys::ReadBuffer(heap_relation.as_ptr(), block);
+        pg_sys::LockBuffer(buffer, pg_sys::BUFFER_LOCK_SHARE as i32);
+        pg_sys::heap_get_root_tuples(pg_sys::BufferGetPage(buffer), root_offsets.as_mut_ptr());
+        pg_sys::UnlockReleaseBuffer(buffer);
+
+        for offset in offsets {
+            let mut ctid = pg_sys::ItemPointerData::default();
+            item_pointer_set_all(&mut ctid, block, offset);
+            let mut root = ctid;
+            // An invalid offset of 0 means that no root was found, which happens when the
+            // chain was pruned after the row was read.
+            let root_offset = root_offsets[offset as usize - 1];
+            if root_offset != 0 {
+                item_pointer_set_all(&mut root, block, root_offset);
+            }
+            roots.insert(item_pointer_to_u64(ctid), item_pointer_to_u64(root));
+        }
+    }
+    roots
+}
+
+/// Remove the documents that don't match their rows, and index the rows that are missing or
+/// whose documents were removed. Returns whether there was anything to repair.
+fn repair_index(
+    index_relation: &PgRelation,
+    heap_relation: &PgRelation,
+    problems: &[(Problem, Option<u64>, Option<i64>, String)],
+    heap_rows: &HashMap<u64, Option<i64>>,
+) -> bool {
+    let mut to_delete = HashSet::new();
+    let mut to_insert = HashSet::new();
+    for (problem, ctid, _, _) in problems {
+        let Some(ctid) = ctid else { continue };
+        match problem {
+            Problem::OrphanedDocument => {
+                to_delete.insert(*ctid);
+            }
+            Problem::DuplicateKey | Problem::KeyMismatch => {
+                to_delete.insert(*ctid);
+                if heap_rows.contains_key(ctid) {
+                    to_insert.insert(*ctid);
+                }
+            }
+            Problem::MissingRow => {
+                to_insert.insert(*ctid);
+            }
+            Problem::SchemaMismatch => {}
+        }
+    }
+    if to_delete.is_empty() && to_insert.is_empty() {
+        return false;
+    }
+
+    let search_index = get_search_index(index_relation.oid());
+    let writer_client = WriterGlobal::client();
+    register_commit_callback(&writer_client, search_index.directory.clone())
+        .expect("could not register commit callbacks for repair operation");
+
+    // The deletes are handled before the inserts, so they don't remove the documents that
+    // are indexed again.
+    search_index
+        .delete_ctids(
+            &writer_client,
+            current_xid(),
+            to_delete.into_iter().collect(),
+        )
+        .unwrap_or_else(|err| panic!("error deleting documents during repair: {err:?}"));
+
+    let mut to_insert: Vec<_> = to_insert.into_iter().collect();
+    to_insert.sort();
+    unsafe { reinsert_heap_rows(index_relation, heap_relation, &to_insert) };
+
+    true
+}
+
+fn format_ctid(ctid: u64) -> String {
+    let mut item_pointer = pg_sys::ItemPointerData::default();
+    u64_to_item_pointer(ctid, &mut item_pointer);
+    let (block, offset) = item_pointer_get_both(item_pointer);
+    format!("({block},{offset})")
+}
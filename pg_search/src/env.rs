@@ -6,7 +6,8 @@ use std::{
     sync::{Arc, Mutex},
 };
 
-use crate::writer::{WriterClient, WriterDirectory, WriterRequest};
+use crate::writer::{TransactionStatus, WriterClient, WriterDirectory, WriterRequest};
+use pgrx::{pg_sys, PgTryBuilder};
 
 const TRANSACTION_CALLBACK_CACHE_ID: &str = "parade_search_index";
 
@@ -63,6 +64,7 @@ pub fn register_commit_callback<W: WriterClient<WriterRequest> + Send + Sync + '
                 Ok(mut client) => {
                     if let Err(err) = client.request(WriterRequest::Commit {
                         directory: commit_directory.clone(),
+                        xid: current_xid(),
                     }) {
                         error = Some(Box::new(err));
                     }
@@ -75,9 +77,59 @@ pub fn register_commit_callback<W: WriterClient<WriterRequest> + Send + Sync + '
         }
     })?;
 
+    // A prepared transaction's documents are committed and journaled like at precommit.
+    // They're kept until COMMIT PREPARED clears its journal entry, or removed by ROLLBACK
+    // PREPARED, which happen in `postgres::prepared`, or when the writer next starts.
+    let writer_client = writer.clone();
+    let prepare_directory = directory.clone();
+    Transaction::call_once_on_preprepare(TRANSACTION_CALLBACK_CACHE_ID, move || {
+        let result = match writer_client.lock() {
+            Err(err) => Err(err.to_string()),
+            Ok(mut client) => client
+                .request(WriterRequest::Commit {
+                    directory: prepare_directory,
+                    xid: current_xid(),
+                })
+                .map_err(|err| err.to_string()),
+        };
+
+        if let Err(err) = result {
+            panic!("error sending commit request in prepare callback: {err}")
+        }
+    })?;
+
+    let writer_client = writer.clone();
+    let journal_directory = directory.clone();
+    Transaction::call_once_on_commit(TRANSACTION_CALLBACK_CACHE_ID, move || {
+        // Postgres has committed, so the documents journaled at precommit are here to stay.
+        // The transaction can't fail anymore, so a leftover entry is only warned about, and
+        // is cleared when the writer next starts.
+        let result = match writer_client.lock() {
+            Err(err) => Err(err.to_string()),
+            Ok(mut client) => client
+                .request(WriterRequest::ClearJournal {
+                    directory: journal_directory,
+                    xid: current_xid(),
+                })
+                .map_err(|err| err.to_string()),
+        };
+
+        if let Err(err) = result {
+            pgrx::warning!("error clearing bm25 index journal in commit callback: {err}")
+        }
+    })?;
+
     let writer_client = writer.clone();
     let abort_directory = directory.clone();
     Transaction::call_once_on_abort(TRANSACTION_CALLBACK_CACHE_ID, move || {
+        // Only this transaction's documents are removed, since the writer holds the documents
+        // of every transaction that's writing to the index. A transaction without an xid
+        // hasn't added any.
+        let xid = current_xid();
+        if xid == 0 {
+            return;
+        }
+
         let mut error: Option<Box<dyn std::error::Error>> = None;
         {
             // This lock must happen in an enclosing block so it is dropped and
@@ -90,6 +142,7 @@ pub fn register_commit_callback<W: WriterClient<WriterRequest> + Send + Sync + '
                 Ok(mut client) => {
                     if let Err(err) = client.request(WriterRequest::Abort {
                         directory: abort_directory,
+                        xid,
                     }) {
                         error = Some(Box::new(err));
                     }
@@ -105,6 +158,28 @@ pub fn register_commit_callback<W: WriterClient<WriterRequest> + Send + Sync + '
     Ok(())
 }
 
+/// The id of the current top-level transaction, or 0 if it hasn't been assigned one.
+pub fn current_xid() -> u32 {
+    unsafe { pgrx::pg_sys::GetTopTransactionIdIfAny() }
+}
+
+/// The outcome of a transaction, according to the transaction log.
+pub fn transaction_status(xid: u32) -> TransactionStatus {
+    PgTryBuilder::new(|| unsafe {
+        if pg_sys::TransactionIdIsInProgress(xid) {
+            TransactionStatus::InProgress
+        } else if pg_sys::TransactionIdDidCommit(xid) {
+            TransactionStatus::Committed
+        } else {
+            TransactionStatus::Aborted
+        }
+    })
+    // The log is truncated once a transaction is older than any that could still be
+    // looked up, so looking up a transaction that old raises an error.
+    .catch_others(|_| TransactionStatus::Unknown)
+    .execute()
+}
+
 pub fn needs_commit() -> bool {
     Transaction::needs_commit(TRANSACTION_CALLBACK_CACHE_ID)
         .expect("error performing commit check in transaction cache")
@@ -21,7 +21,7 @@ impl MockWriterDirectory {
         Self {
             temp_dir,
             writer_dir: WriterDirectory {
-                index_name: index_name.to_string(),
+                index_oid: 0,
                 database_oid: 0,
                 postgres_data_dir_path: temp_path,
             },
@@ -1,5 +1,5 @@
 use crate::{
-    index::SearchIndex,
+    index::{SearchIndex, SearchIndexSettings},
     schema::{SearchFieldConfig, SearchFieldName},
 };
 
@@ -15,7 +15,12 @@ impl MockSearchIndex {
         // We must store the TempDir instance on the struct, because it gets deleted when the
         // instance is dropped.
         let directory = MockWriterDirectory::new("mock_parade_search_index");
-        let index = SearchIndex::new(directory.writer_dir.clone(), fields).unwrap();
+        let index = SearchIndex::new(
+            directory.writer_dir.clone(),
+            fields,
+            SearchIndexSettings::default(),
+        )
+        .unwrap();
         Self { directory, index }
     }
 }
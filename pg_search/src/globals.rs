@@ -1,10 +1,11 @@
 use once_cell::sync::Lazy;
-use pgrx::{PGRXSharedMemory, PgLwLock};
+use pgrx::{pg_sys, PGRXSharedMemory, PgLwLock};
 use std::{
-    net::SocketAddr,
+    path::PathBuf,
     sync::{Arc, Mutex},
 };
 
+use crate::postgres::wal::WalClient;
 use crate::writer::{self, WriterRequest};
 
 // This is global shared state for the writer background worker.
@@ -14,25 +15,32 @@ pub static WRITER_GLOBAL: PgLwLock<WriterGlobal> = PgLwLock::new();
 /// The client is agnotistic to which index we're writing to, so keeping a global one
 /// ensures that the instance can be re-used if a single transaction needs to write to
 /// multiple indexes. Note this must NOT be accesssed before the server is started.
-pub static mut SEARCH_INDEX_WRITER_CLIENT: Lazy<Arc<Mutex<writer::Client<writer::WriterRequest>>>> =
-    Lazy::new(|| Arc::new(Mutex::new(writer::Client::from_global())));
+/// The requests it sends are logged to the write-ahead log, so that standbys replay them.
+pub static mut SEARCH_INDEX_WRITER_CLIENT: Lazy<Arc<Mutex<WriterGlobalClient>>> =
+    Lazy::new(|| Arc::new(Mutex::new(WalClient::new(writer::Client::from_global()))));
+
+pub type WriterGlobalClient = WalClient<writer::Client<WriterRequest>>;
 
 #[derive(Copy, Clone, Default)]
 pub struct WriterGlobal {
-    pub addr: Option<SocketAddr>,
+    pub listening: bool,
 }
 
 impl WriterGlobal {
-    pub fn addr(&self) -> SocketAddr {
-        self.addr
-            .expect("could not access writer status, writer server may not have started.")
+    pub fn socket_path(&self) -> PathBuf {
+        // A standby has no writer until it's promoted, but it can still be searched, which
+        // needs a client. Writes aren't allowed there, so the client never connects.
+        if !self.listening && !unsafe { pg_sys::RecoveryInProgress() } {
+            panic!("could not access writer status, writer server may not have started.");
+        }
+        writer::writer_socket_path()
     }
 
-    pub fn set_addr(&mut self, addr: SocketAddr) {
-        self.addr = Some(addr);
+    pub fn set_listening(&mut self) {
+        self.listening = true;
     }
 
-    pub fn client() -> Arc<Mutex<writer::Client<WriterRequest>>> {
+    pub fn client() -> Arc<Mutex<WriterGlobalClient>> {
         unsafe { SEARCH_INDEX_WRITER_CLIENT.clone() }
     }
 }
@@ -0,0 +1,110 @@
+use pgrx::*;
+use shared::gucs::{GlobalGucSettings, PostgresGlobalGucSettings};
+
+// Writer memory budget, in megabytes
+const DEFAULT_WRITER_MEMORY_BUDGET_MB: i32 = 500;
+const MIN_WRITER_MEMORY_BUDGET_MB: i32 = 15;
+const MAX_WRITER_MEMORY_BUDGET_MB: i32 = 32_000;
+
+// Writer indexing threads, where 0 picks a number from the number of CPUs
+const DEFAULT_WRITER_THREADS: i32 = 0;
+const MAX_WRITER_THREADS: i32 = 8;
+
+// Search threads per connection, where 0 picks a number from the number of CPUs. Every
+// connection starts its own, so by default searches run on the connection's own process.
+const DEFAULT_SEARCH_THREADS: i32 = 1;
+const MAX_SEARCH_THREADS: i32 = 64;
+const AUTO_MAX_SEARCH_THREADS: usize = 4;
+
+pub struct PostgresPgSearchGucSettings {
+    pub writer_memory_budget_mb: GucSetting<i32>,
+    pub writer_threads: GucSetting<i32>,
+    pub search_threads: GucSetting<i32>,
+    pub globals: PostgresGlobalGucSettings,
+}
+
+impl Default for PostgresPgSearchGucSettings {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PostgresPgSearchGucSettings {
+    pub const fn new() -> Self {
+        Self {
+            writer_memory_budget_mb: GucSetting::<i32>::new(DEFAULT_WRITER_MEMORY_BUDGET_MB),
+            writer_threads: GucSetting::<i32>::new(DEFAULT_WRITER_THREADS),
+            search_threads: GucSetting::<i32>::new(DEFAULT_SEARCH_THREADS),
+            globals: PostgresGlobalGucSettings::new(),
+        }
+    }
+
+    /// You must call this `init` function in the extension's `_PG_init()`.
+    pub fn init(&self, extension_name: &str) {
+        // Initialize global settings first.
+        self.globals.init(extension_name);
+
+        // The writer settings are read by the insert worker, which doesn't reload the server's
+        // configuration, so they can only be changed by restarting the server.
+        GucRegistry::define_int_guc(
+            "paradedb.pg_search_writer_memory_budget",
+            "The memory that the bm25 writer buffers each index's new documents in.",
+            "Shared between the index's indexing threads. Indexes can override it with the writer_memory_budget option. Defaults to 500MB.",
+            &self.writer_memory_budget_mb,
+            MIN_WRITER_MEMORY_BUDGET_MB,
+            MAX_WRITER_MEMORY_BUDGET_MB,
+            GucContext::Postmaster,
+            GucFlags::UNIT_MB,
+        );
+
+        GucRegistry::define_int_guc(
+            "paradedb.pg_search_writer_threads",
+            "The number of threads that the bm25 writer indexes each index's documents with.",
+            "Indexes can override it with the writer_threads option. Defaults to 0, which uses one per CPU, up to 8.",
+            &self.writer_threads,
+            0,
+            MAX_WRITER_THREADS,
+            GucContext::Postmaster,
+            GucFlags::default(),
+        );
+
+        GucRegistry::define_int_guc(
+            "paradedb.pg_search_search_threads",
+            "The number of threads that each connection searches bm25 indexes with.",
+            "The threads are started by a connection's first search, and shared by all of its searches. Defaults to 1, which searches on the connection's own process. 0 uses one per CPU, up to 4.",
+            &self.search_threads,
+            0,
+            MAX_SEARCH_THREADS,
+            GucContext::Sighup,
+            GucFlags::default(),
+        );
+    }
+
+    pub fn writer_memory_budget_mb(&self) -> usize {
+        self.writer_memory_budget_mb.get() as usize
+    }
+
+    pub fn writer_threads(&self) -> usize {
+        match self.writer_threads.get() {
+            0 => num_cpus::get().min(MAX_WRITER_THREADS as usize),
+            threads => threads as usize,
+        }
+    }
+
+    pub fn search_threads(&self) -> usize {
+        match self.search_threads.get() {
+            0 => num_cpus::get().min(AUTO_MAX_SEARCH_THREADS),
+            threads => threads as usize,
+        }
+    }
+}
+
+impl GlobalGucSettings for PostgresPgSearchGucSettings {
+    fn telemetry_enabled(&self) -> bool {
+        self.globals.telemetry_enabled()
+    }
+
+    fn logs_enabled(&self) -> bool {
+        self.globals.logs_enabled()
+    }
+}
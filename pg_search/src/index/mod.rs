@@ -1,5 +1,9 @@
 pub mod score;
 pub mod search;
+pub mod settings;
 pub mod state;
+pub mod stats;
 
 pub use search::*;
+pub use settings::*;
+pub use stats::*;
@@ -4,7 +4,7 @@ use pgrx::{
     PgTupleDesc,
 };
 use serde::{Deserialize, Deserializer, Serialize};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::sync::{Arc, Mutex, PoisonError};
 use tantivy::{query::QueryParser, Executor, Index, IndexSettings, Searcher};
 use tantivy::{IndexReader, IndexSortByField, IndexWriter, Order, TantivyError};
@@ -12,8 +12,9 @@ use thiserror::Error;
 use tokenizers::{create_normalizer_manager, create_tokenizer_manager};
 use tracing::{error, info};
 
+use super::settings::SearchIndexSettings;
 use super::state::SearchState;
-use crate::postgres::utils::row_to_search_document;
+use crate::postgres::utils::{row_to_search_document, IndexExpression};
 use crate::schema::{
     SearchConfig, SearchDocument, SearchFieldConfig, SearchFieldName, SearchIndexSchema,
     SearchIndexSchemaError,
@@ -22,9 +23,8 @@ use crate::writer::{
     self, SearchDirectoryError, SearchFs, TantivyDirPath, WriterClient, WriterDirectory,
     WriterRequest, WriterTransferPipeFilePath,
 };
+use crate::GUCS;
 
-// Must be at least 15,000,000 or Tantivy will panic.
-const INDEX_TANTIVY_MEMORY_BUDGET: usize = 500_000_000;
 const CACHE_NUM_BLOCKS: usize = 10;
 
 /// PostgreSQL operates in a process-per-client model, meaning every client connection
@@ -45,15 +45,20 @@ const CACHE_NUM_BLOCKS: usize = 10;
 pub static mut SEARCH_INDEX_MEMORY: Lazy<HashMap<WriterDirectory, SearchIndex>> =
     Lazy::new(HashMap::new);
 
-pub static mut SEARCH_EXECUTOR: Lazy<Executor> = Lazy::new(|| {
-    let num_threads = num_cpus::get();
-    Executor::multi_thread(num_threads, "prefix-here").expect("could not create search executor")
+/// The threads that a backend searches with, which are shared by all of its indexes. They're
+/// started by its first search. Every connection has its own, so by default a backend searches
+/// on its own process, and `paradedb.pg_search_search_threads` opts into more threads.
+pub static mut SEARCH_EXECUTOR: Lazy<Executor> = Lazy::new(|| match GUCS.search_threads() {
+    1 => Executor::single_thread(),
+    num_threads => Executor::multi_thread(num_threads, "pg_search_searcher-")
+        .expect("could not create search executor"),
 });
 
 #[derive(Serialize)]
 pub struct SearchIndex {
     pub schema: SearchIndexSchema,
     pub directory: WriterDirectory,
+    pub settings: SearchIndexSettings,
     #[serde(skip_serializing)]
     pub reader: IndexReader,
     #[serde(skip_serializing)]
@@ -66,6 +71,7 @@ impl SearchIndex {
     pub fn new(
         directory: WriterDirectory,
         fields: Vec<(SearchFieldName, SearchFieldConfig)>,
+        settings: SearchIndexSettings,
     ) -> Result<&'static mut Self, SearchIndexError> {
         // If the writer directory exists, remove it. We need a fresh directory to
         // create an index. This can happen after a VACUUM FULL, where the index needs
@@ -73,7 +79,7 @@ impl SearchIndex {
         directory.remove().map_err(SearchIndexError::from)?;
 
         let schema = SearchIndexSchema::new(fields)?;
-        let settings = IndexSettings {
+        let index_settings = IndexSettings {
             // Fields should be returned in the order of their key_field (if their bm25 scores match).
             // Pre-sorting these fields at insert time saves work at query time.
             sort_by_field: Some(IndexSortByField {
@@ -87,17 +93,18 @@ impl SearchIndex {
         let tantivy_dir_path = directory.tantivy_dir_path(true)?;
         let mut underlying_index = Index::builder()
             .schema(schema.schema.clone())
-            .settings(settings.clone())
+            .settings(index_settings)
             .create_in_dir(tantivy_dir_path)
             .expect("failed to create index");
 
-        Self::setup_tokenizers(&mut underlying_index, &schema);
+        Self::setup_tokenizers(&mut underlying_index, &schema)?;
 
         let new_self = Self {
             reader: Self::reader(&underlying_index)?,
             underlying_index,
             directory: directory.clone(),
             schema,
+            settings,
             executor: Self::executor(),
         };
 
@@ -117,11 +124,14 @@ impl SearchIndex {
     }
 
     #[allow(static_mut_refs)]
-    fn executor() -> &'static Executor {
+    pub fn executor() -> &'static Executor {
         unsafe { &SEARCH_EXECUTOR }
     }
 
-    fn setup_tokenizers(underlying_index: &mut Index, schema: &SearchIndexSchema) {
+    fn setup_tokenizers(
+        underlying_index: &mut Index,
+        schema: &SearchIndexSchema,
+    ) -> Result<(), SearchIndexError> {
         let tokenizers = schema
             .fields
             .iter()
@@ -130,15 +140,22 @@ impl SearchIndex {
                 let field_name: &str = field.name.as_ref();
                 info!(field_name, "attempting to create tokenizer");
                 match field_config {
-                    SearchFieldConfig::Text { tokenizer, .. }
-                    | SearchFieldConfig::Json { tokenizer, .. } => Some(tokenizer),
+                    SearchFieldConfig::Text {
+                        tokenizer, filters, ..
+                    }
+                    | SearchFieldConfig::Json {
+                        tokenizer, filters, ..
+                    } => Some((tokenizer, filters.as_slice())),
                     _ => None,
                 }
             })
             .collect();
 
-        underlying_index.set_tokenizers(create_tokenizer_manager(tokenizers));
+        underlying_index.set_tokenizers(
+            create_tokenizer_manager(tokenizers).map_err(SearchIndexError::Tokenizer)?,
+        );
         underlying_index.set_fast_field_tokenizers(create_normalizer_manager());
+        Ok(())
     }
 
     fn reader(index: &Index) -> Result<IndexReader, TantivyError> {
@@ -159,6 +176,11 @@ impl SearchIndex {
             }
         }
 
+        // An index that couldn't be recovered after a crash is out of sync with its table.
+        if let Some(reason) = directory.invalid_reason() {
+            return Err(SearchIndexError::Invalid(reason));
+        }
+
         let new_self: Self = directory.load_index()?;
 
         // Since we've re-fetched the index, save it to the cache.
@@ -184,12 +206,14 @@ impl SearchIndex {
         &self,
         writer: &Arc<Mutex<W>>,
         config: &SearchConfig,
-        needs_commit: bool,
+        commit_xid: Option<u32>,
     ) -> Result<SearchState, SearchIndexError> {
-        // Commit any inserts or deletes that have occured during this transaction.
-        if needs_commit {
+        // Commit any inserts or deletes that have occured during this transaction,
+        // `commit_xid`, which is given if there are any.
+        if let Some(xid) = commit_xid {
             writer.lock()?.request(WriterRequest::Commit {
                 directory: self.directory.clone(),
+                xid,
             })?
         }
 
@@ -201,6 +225,44 @@ impl SearchIndex {
         Ok(SearchState::new(self, config))
     }
 
+    /// The fraction of documents that a search is estimated to match, from the document
+    /// frequencies of its query's terms. A document is assumed to match if it has any of the
+    /// terms, so queries that need several of them, like phrases, are overestimated. Returns
+    /// None for queries without terms, like range queries, which can't be estimated this way.
+    pub fn estimate_selectivity(&self, config: &SearchConfig) -> Option<f64> {
+        let mut parser = self.query_parser();
+        let query = config
+            .query
+            .clone()
+            .into_tantivy_query(&self.schema, &mut parser)
+            .ok()?;
+
+        let searcher = self.searcher();
+        let num_docs = searcher.num_docs();
+        if num_docs == 0 {
+            return None;
+        }
+
+        let mut terms = HashSet::new();
+        query.query_terms(&mut |term, _| {
+            terms.insert(term.clone());
+        });
+        if terms.is_empty() {
+            return None;
+        }
+
+        let mut matches: u64 = terms
+            .iter()
+            .map(|term| searcher.doc_freq(term).unwrap_or(0))
+            .sum();
+        // A search returns no more than its limit.
+        if let Some(limit) = config.limit_rows {
+            matches = matches.min(limit as u64);
+        }
+
+        Some((matches as f64 / num_docs as f64).min(1.0))
+    }
+
     pub fn searcher(&self) -> Searcher {
         self.reader.searcher()
     }
@@ -210,20 +272,25 @@ impl SearchIndex {
     /// be entirely owned by the new process, with no references.
     pub fn writer(directory: &WriterDirectory) -> Result<IndexWriter, SearchIndexError> {
         let search_index: Self = directory.load_index()?;
+        let (num_threads, memory_budget) = search_index.settings.writer_resources();
         let index_writer = search_index
             .underlying_index
-            .writer(INDEX_TANTIVY_MEMORY_BUDGET)?;
+            .writer_with_num_threads(num_threads, memory_budget)?;
+        index_writer.set_merge_policy(search_index.settings.merge_policy.merge_policy());
         Ok(index_writer)
     }
 
+    /// Add a document on behalf of transaction `xid`.
     pub fn insert<W: WriterClient<WriterRequest> + Send + Sync + 'static>(
         &mut self,
         writer: &Arc<Mutex<W>>,
+        xid: u32,
         document: SearchDocument,
     ) -> Result<(), SearchIndexError> {
         // Send the insert requests to the writer server.
         let request = WriterRequest::Insert {
             directory: self.directory.clone(),
+            xid,
             document,
         };
 
@@ -237,6 +304,7 @@ impl SearchIndex {
     pub fn delete<W: WriterClient<WriterRequest> + Send + Sync + 'static>(
         &mut self,
         writer: &Arc<Mutex<W>>,
+        xid: u32,
         should_delete: impl Fn(*mut ItemPointerData) -> bool,
     ) -> Result<(u32, u32), SearchIndexError> {
         let mut deleted: u32 = 0;
@@ -267,21 +335,69 @@ impl SearchIndex {
             }
         }
 
+        self.delete_ctids(writer, xid, ctids_to_delete)?;
+
+        Ok((deleted, not_deleted))
+    }
+
+    /// Delete the documents of the given ctids on behalf of transaction `xid`.
+    pub fn delete_ctids<W: WriterClient<WriterRequest> + Send + Sync + 'static>(
+        &mut self,
+        writer: &Arc<Mutex<W>>,
+        xid: u32,
+        ctids: Vec<u64>,
+    ) -> Result<(), SearchIndexError> {
         let request = WriterRequest::Delete {
+            xid,
             field: self.schema.ctid_field().id.0,
-            ctids: ctids_to_delete,
+            ctids,
             directory: self.directory.clone(),
         };
         writer.lock()?.request(request)?;
+        Ok(())
+    }
 
-        Ok((deleted, not_deleted))
+    /// The ctid and key of every document in the index, as of its last reload.
+    pub fn ctids_and_keys(&self) -> Vec<(u64, Option<i64>)> {
+        let ctid_field = self.schema.ctid_field().id.0;
+        let key_field = self.schema.key_field().id.0;
+        let mut documents = vec![];
+
+        for segment_reader in self.searcher().segment_readers() {
+            let store_reader = segment_reader
+                .get_store_reader(CACHE_NUM_BLOCKS)
+                .expect("Failed to get store reader");
+
+            for doc in segment_reader
+                .doc_ids_alive()
+                .filter_map(|id| store_reader.get(id).ok())
+            {
+                let Some(ctid) = doc.get_first(ctid_field).and_then(|value| value.as_u64()) else {
+                    continue;
+                };
+                let key = doc.get_first(key_field).and_then(|value| value.as_i64());
+                documents.push((ctid, key));
+            }
+        }
+
+        documents
+    }
+
+    /// The metadata of the index's last commit, including its segments.
+    pub fn index_metas(&self) -> Result<tantivy::IndexMeta, SearchIndexError> {
+        Ok(self.underlying_index.load_metas()?)
+    }
+
+    /// The schema that Tantivy has stored with the index, which should match `self.schema`.
+    pub fn tantivy_schema(&self) -> tantivy::schema::Schema {
+        self.underlying_index.schema()
     }
 
     pub fn drop_index<W: WriterClient<WriterRequest>>(
         writer: &Arc<Mutex<W>>,
-        index_name: &str,
+        index_oid: u32,
     ) -> Result<(), SearchIndexError> {
-        let directory = WriterDirectory::from_index_name(index_name);
+        let directory = WriterDirectory::from_index_oid(index_oid);
         let request = WriterRequest::DropIndex { directory };
 
         writer.lock()?.request(request)?;
@@ -300,14 +416,41 @@ impl SearchIndex {
         Ok(())
     }
 
+    pub fn optimize<W: WriterClient<WriterRequest>>(
+        &self,
+        writer: &Arc<Mutex<W>>,
+        max_segments: usize,
+    ) -> Result<(), SearchIndexError> {
+        let request = WriterRequest::Optimize {
+            directory: self.directory.clone(),
+            max_segments,
+        };
+        writer.lock()?.request(request)?;
+        Ok(())
+    }
+
+    pub fn prepare_commit<W: WriterClient<WriterRequest>>(
+        &self,
+        writer: &Arc<Mutex<W>>,
+    ) -> Result<(), SearchIndexError> {
+        let request = WriterRequest::PrepareCommit {
+            directory: self.directory.clone(),
+        };
+        writer.lock()?.request(request)?;
+        Ok(())
+    }
+
     pub fn row_to_search_document(
         &self,
         ctid: ItemPointerData,
         tupdesc: &PgTupleDesc,
         values: *mut Datum,
+        isnull: *mut bool,
+        expressions: &[IndexExpression],
     ) -> Result<SearchDocument, SearchIndexError> {
-        // Create a vector of index entries from the postgres row.
-        let mut search_document = unsafe { row_to_search_document(tupdesc, values, &self.schema) }?;
+        // Create a vector of index entries from the postgres row and index expressions.
+        let mut search_document =
+            unsafe { row_to_search_document(tupdesc, values, isnull, expressions, &self.schema) }?;
 
         // Insert the ctid value into the entries.
         let ctid_index_value = pgrx::item_pointer_to_u64(ctid);
@@ -327,10 +470,17 @@ impl<'de> Deserialize<'de> for SearchIndex {
         struct SearchIndexHelper {
             schema: SearchIndexSchema,
             directory: WriterDirectory,
+            // Indexes built before there were settings have the default ones.
+            #[serde(default)]
+            settings: SearchIndexSettings,
         }
 
         // Deserialize into the struct with automatic handling for most fields
-        let SearchIndexHelper { schema, directory } = SearchIndexHelper::deserialize(deserializer)?;
+        let SearchIndexHelper {
+            schema,
+            directory,
+            settings,
+        } = SearchIndexHelper::deserialize(deserializer)?;
 
         let TantivyDirPath(tantivy_dir_path) = directory.tantivy_dir_path(true).unwrap();
 
@@ -338,7 +488,7 @@ impl<'de> Deserialize<'de> for SearchIndex {
             Index::open_in_dir(tantivy_dir_path).expect("failed to open index");
 
         // We need to setup tokenizers again after retrieving an index from disk.
-        Self::setup_tokenizers(&mut underlying_index, &schema);
+        Self::setup_tokenizers(&mut underlying_index, &schema).map_err(serde::de::Error::custom)?;
 
         let reader = Self::reader(&underlying_index)
             .unwrap_or_else(|_| panic!("failed to create index reader while retrieving index"));
@@ -349,6 +499,7 @@ impl<'de> Deserialize<'de> for SearchIndex {
             underlying_index,
             directory,
             schema,
+            settings,
             executor: Self::executor(),
         })
     }
@@ -382,6 +533,12 @@ pub enum SearchIndexError {
 
     #[error("mutex lock on writer client failed: {0}")]
     WriterClientRace(String),
+
+    #[error("index could not be recovered after a crash and must be rebuilt with REINDEX: {0}")]
+    Invalid(String),
+
+    #[error("could not create tokenizer: {0}")]
+    Tokenizer(String),
 }
 
 impl<T> From<PoisonError<T>> for SearchIndexError {
@@ -422,7 +579,7 @@ mod tests {
         doc.insert(author_field.id, Value::Str("张伟".into()));
 
         // Insert document into index.
-        index.insert(&client, doc.clone()).unwrap();
+        index.insert(&client, 0, doc.clone()).unwrap();
 
         // Search in index
         let search_config = SearchConfig {
@@ -432,7 +589,9 @@ mod tests {
             key_field: "id".into(),
             ..Default::default()
         };
-        let state = index.search_state(&client, &search_config, true).unwrap();
+        let state = index
+            .search_state(&client, &search_config, Some(0))
+            .unwrap();
 
         let (_, doc_address, _, _) = *state
             .search(index.executor)
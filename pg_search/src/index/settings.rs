@@ -0,0 +1,160 @@
+use serde::{Deserialize, Serialize};
+use tantivy::merge_policy::{LogMergePolicy, MergePolicy, NoMergePolicy};
+
+use crate::GUCS;
+
+const BYTES_IN_MB: usize = 1_048_576;
+
+// Tantivy splits a writer's memory budget evenly between its indexing threads, and needs each
+// thread's share to be at least 15,000,000 bytes, and less than 4GB.
+const MIN_THREAD_MEMORY_BUDGET: usize = 15_000_000;
+const MAX_THREAD_MEMORY_BUDGET: usize = 4_000_000_000;
+
+/// The settings of an index that aren't part of its schema. They're given as index options when
+/// the index is built, and saved along with it, because the writer process can't read them from
+/// the catalog.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct SearchIndexSettings {
+    #[serde(default)]
+    pub merge_policy: MergePolicyConfig,
+    /// The writer's memory budget in megabytes, instead of the
+    /// `paradedb.pg_search_writer_memory_budget` setting.
+    #[serde(default)]
+    pub writer_memory_budget_mb: Option<usize>,
+    /// The writer's number of indexing threads, instead of the
+    /// `paradedb.pg_search_writer_threads` setting.
+    #[serde(default)]
+    pub writer_threads: Option<usize>,
+}
+
+impl SearchIndexSettings {
+    /// The number of indexing threads and the memory budget in bytes to open the index's
+    /// writer with.
+    pub fn writer_resources(&self) -> (usize, usize) {
+        let threads = self.writer_threads.unwrap_or_else(|| GUCS.writer_threads());
+        let memory_budget_mb = self
+            .writer_memory_budget_mb
+            .unwrap_or_else(|| GUCS.writer_memory_budget_mb());
+        writer_resources(threads, memory_budget_mb * BYTES_IN_MB)
+    }
+}
+
+/// Fit a number of indexing threads and a memory budget to what Tantivy accepts. Threads are
+/// dropped until each one's share of the budget is big enough, and the budget is capped at
+/// what the threads can use.
+fn writer_resources(threads: usize, memory_budget: usize) -> (usize, usize) {
+    let threads = threads.min(memory_budget / MIN_THREAD_MEMORY_BUDGET).max(1);
+    let memory_budget =
+        memory_budget.clamp(MIN_THREAD_MEMORY_BUDGET, MAX_THREAD_MEMORY_BUDGET * threads);
+    (threads, memory_budget)
+}
+
+/// How the writer merges an index's segments in the background, with Tantivy's log merge
+/// policy. Segments are grouped into levels by their number of documents, and a level's
+/// segments are merged once there are enough of them. Settings that aren't given keep
+/// Tantivy's defaults.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MergePolicyConfig {
+    /// The number of segments that a level needs before they're merged.
+    pub min_num_segments: Option<usize>,
+    /// Segments with more documents than this aren't merged.
+    pub max_docs_before_merge: Option<usize>,
+    /// Segments with fewer documents than this are all in the lowest level.
+    pub min_layer_size: Option<u32>,
+    /// The ratio between the sizes of the segments of successive levels, as a logarithm.
+    pub level_log_size: Option<f64>,
+    /// Segments with a greater fraction of deleted documents than this are merged, whatever
+    /// their level, so that the space of the deleted documents is reclaimed.
+    pub del_docs_ratio_before_merge: Option<f32>,
+    /// Never merge segments in the background, so they're only merged by `optimize_bm25`.
+    #[serde(default)]
+    pub no_merge: bool,
+}
+
+impl MergePolicyConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if let Some(ratio) = self.del_docs_ratio_before_merge {
+            if ratio <= 0.0 || ratio > 1.0 {
+                return Err(format!(
+                    "del_docs_ratio_before_merge must be greater than 0 and at most 1, not {ratio}"
+                ));
+            }
+        }
+        if self.min_num_segments == Some(0) {
+            return Err("min_num_segments must be at least 1".into());
+        }
+        Ok(())
+    }
+
+    pub fn merge_policy(&self) -> Box<dyn MergePolicy> {
+        if self.no_merge {
+            return Box::new(NoMergePolicy);
+        }
+
+        let mut policy = LogMergePolicy::default();
+        if let Some(min_num_segments) = self.min_num_segments {
+            policy.set_min_num_segments(min_num_segments);
+        }
+        if let Some(max_docs_before_merge) = self.max_docs_before_merge {
+            policy.set_max_docs_before_merge(max_docs_before_merge);
+        }
+        if let Some(min_layer_size) = self.min_layer_size {
+            policy.set_min_layer_size(min_layer_size);
+        }
+        if let Some(level_log_size) = self.level_log_size {
+            policy.set_level_log_size(level_log_size);
+        }
+        if let Some(ratio) = self.del_docs_ratio_before_merge {
+            policy.set_del_docs_ratio_before_merge(ratio);
+        }
+        Box::new(policy)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::*;
+
+    use super::{writer_resources, MergePolicyConfig};
+
+    #[rstest]
+    fn test_merge_policy_config() {
+        let config: MergePolicyConfig =
+            json5::from_str("{min_num_segments: 4, del_docs_ratio_before_merge: 0.5}").unwrap();
+        assert_eq!(config.min_num_segments, Some(4));
+        assert_eq!(config.max_docs_before_merge, None);
+        assert!(config.validate().is_ok());
+
+        let config: MergePolicyConfig = json5::from_str("{}").unwrap();
+        assert_eq!(config, MergePolicyConfig::default());
+    }
+
+    #[rstest]
+    #[case::ratio_too_low("{del_docs_ratio_before_merge: 0}")]
+    #[case::ratio_too_high("{del_docs_ratio_before_merge: 1.5}")]
+    #[case::no_segments("{min_num_segments: 0}")]
+    fn test_invalid_merge_policy_config(#[case] json: &str) {
+        let config: MergePolicyConfig = json5::from_str(json).unwrap();
+        assert!(config.validate().is_err());
+    }
+
+    #[rstest]
+    fn test_unknown_merge_policy_setting() {
+        assert!(json5::from_str::<MergePolicyConfig>("{max_segments: 4}").is_err());
+    }
+
+    #[rstest]
+    #[case::fits(4, 500_000_000, (4, 500_000_000))]
+    #[case::too_many_threads(8, 45_000_000, (3, 45_000_000))]
+    #[case::too_little_memory(2, 10_000_000, (1, 15_000_000))]
+    #[case::no_threads(0, 500_000_000, (1, 500_000_000))]
+    #[case::too_much_memory(1, 5_000_000_000, (1, 4_000_000_000))]
+    fn test_writer_resources(
+        #[case] threads: usize,
+        #[case] memory_budget: usize,
+        #[case] expected: (usize, usize),
+    ) {
+        assert_eq!(writer_resources(threads, memory_budget), expected);
+    }
+}
@@ -1,15 +1,17 @@
 use super::score::SearchIndexScore;
 use super::SearchIndex;
 use crate::schema::{SearchConfig, SearchFieldName, SearchIndexSchema};
+use crate::writer::WriterDirectory;
 use derive_more::{AsRef, Display, From};
 use once_cell::sync::Lazy;
 use serde::{Deserialize, Serialize};
 use shared::postgres::transaction::{Transaction, TransactionError};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::sync::{Arc, Mutex, PoisonError};
 use tantivy::collector::TopDocs;
-use tantivy::schema::FieldType;
-use tantivy::{query::Query, DocAddress, Score, Searcher};
+use tantivy::query::{Bm25StatisticsProvider, Query};
+use tantivy::schema::{Field, FieldType};
+use tantivy::{DocAddress, Score, Searcher, Term};
 use tantivy::{Executor, Snippet, SnippetGenerator};
 use thiserror::Error;
 
@@ -23,8 +25,10 @@ static SEARCH_STATE_MANAGER: Lazy<Arc<Mutex<SearchStateManager>>> = Lazy::new(||
 const TRANSACTION_CALLBACK_CACHE_ID: &str = "parade_current_search";
 
 pub struct SearchStateManager {
-    state_map: HashMap<SearchAlias, SearchState>,
-    result_map: HashMap<SearchAlias, HashMap<i64, (Score, DocAddress)>>,
+    /// The states of each search, keyed by the directory of the index they read. A search
+    /// through a partitioned index has a state for each partition.
+    state_map: HashMap<SearchAlias, HashMap<WriterDirectory, SearchState>>,
+    result_map: HashMap<SearchAlias, HashMap<i64, (Score, DocAddress, WriterDirectory)>>,
 }
 
 impl SearchStateManager {
@@ -47,13 +51,18 @@ impl SearchStateManager {
         Ok(())
     }
 
-    fn get_state_default(&self) -> Result<&SearchState, SearchStateError> {
+    fn get_states_default(
+        &self,
+    ) -> Result<&HashMap<WriterDirectory, SearchState>, SearchStateError> {
         self.state_map
             .get(&SearchAlias::default())
             .ok_or(SearchStateError::NoQuery)
     }
 
-    fn get_state_alias(&self, alias: SearchAlias) -> Result<&SearchState, SearchStateError> {
+    fn get_states_alias(
+        &self,
+        alias: SearchAlias,
+    ) -> Result<&HashMap<WriterDirectory, SearchState>, SearchStateError> {
         self.state_map
             .get(&alias)
             .ok_or(SearchStateError::AliasLookup(alias))
@@ -63,7 +72,7 @@ impl SearchStateManager {
         let manager = SEARCH_STATE_MANAGER
             .lock()
             .map_err(SearchStateError::from)?;
-        let (score, _) = manager
+        let (score, _, _) = manager
             .result_map
             .get(&alias.unwrap_or_default())
             .and_then(|inner_map| inner_map.get(&key))
@@ -81,19 +90,23 @@ impl SearchStateManager {
         let manager = SEARCH_STATE_MANAGER
             .lock()
             .map_err(SearchStateError::from)?;
-        let state = manager.get_state(alias.clone())?;
+        let states = manager.get_states(alias.clone())?;
+
+        let (_, doc_address, directory) = manager
+            .result_map
+            .get(&alias.unwrap_or_default())
+            .and_then(|inner_map| inner_map.get(&key))
+            .ok_or(SearchStateError::DocLookup(key))?;
+
+        // The document was found by the state that searched its index.
+        let state = states
+            .get(directory)
+            .ok_or(SearchStateError::DocLookup(key))?;
         let mut snippet_generator = state.snippet_generator(field_name);
         if let Some(max_num_chars) = max_num_chars {
             snippet_generator.set_max_num_chars(max_num_chars)
         }
 
-        let alias = alias.unwrap_or_default();
-
-        let (_, doc_address) = manager
-            .result_map
-            .get(&alias)
-            .and_then(|inner_map| inner_map.get(&key))
-            .ok_or(SearchStateError::DocLookup(key))?;
         let doc = state
             .searcher
             .doc(*doc_address)
@@ -101,18 +114,37 @@ impl SearchStateManager {
         Ok(snippet_generator.snippet_from_doc(&doc))
     }
 
-    pub fn get_state(&self, alias: Option<SearchAlias>) -> Result<&SearchState, SearchStateError> {
+    pub fn get_states(
+        &self,
+        alias: Option<SearchAlias>,
+    ) -> Result<&HashMap<WriterDirectory, SearchState>, SearchStateError> {
         if let Some(alias) = alias {
-            self.get_state_alias(alias)
+            self.get_states_alias(alias)
         } else {
-            self.get_state_default()
+            self.get_states_default()
+        }
+    }
+
+    /// Adds a state to the search with the given alias. Only the partitions of a partitioned
+    /// index can share a search, so the search can't already have a state for the same index,
+    /// or a state with a different config.
+    fn insert_state(&mut self, state: SearchState, alias: SearchAlias) -> bool {
+        let states = self.state_map.entry(alias).or_default();
+        if states.contains_key(&state.directory)
+            || states
+                .values()
+                .any(|existing| existing.config != state.config)
+        {
+            return false;
         }
+        states.insert(state.directory.clone(), state);
+        true
     }
 
     fn set_state_default(&mut self, state: SearchState) -> Result<(), SearchStateError> {
-        match self.state_map.insert(SearchAlias::default(), state) {
-            None => Ok(()),
-            Some(_) => Err(SearchStateError::AliasRequired),
+        match self.insert_state(state, SearchAlias::default()) {
+            true => Ok(()),
+            false => Err(SearchStateError::AliasRequired),
         }
     }
 
@@ -124,7 +156,7 @@ impl SearchStateManager {
         if alias == SearchAlias::default() {
             Err(SearchStateError::EmptyAlias)
         } else {
-            if self.state_map.insert(alias.clone(), state).is_some() {
+            if !self.insert_state(state, alias.clone()) {
                 return Err(SearchStateError::DuplicateAlias(alias));
             }
             Ok(())
@@ -149,6 +181,7 @@ impl SearchStateManager {
         key: i64,
         score: Score,
         doc_address: DocAddress,
+        directory: WriterDirectory,
         alias: Option<SearchAlias>,
     ) -> Result<(), SearchStateError> {
         let mut manager = SEARCH_STATE_MANAGER
@@ -159,7 +192,7 @@ impl SearchStateManager {
             .result_map
             .entry(alias.unwrap_or_default())
             .or_insert_with(HashMap::new)
-            .insert(key, (score, doc_address));
+            .insert(key, (score, doc_address, directory));
         Ok(())
     }
 }
@@ -206,12 +239,131 @@ impl Default for SearchAlias {
     }
 }
 
+/// BM25 statistics summed over the searchers of every partition of a partitioned index.
+/// Scoring each partition with them, rather than with the partition's own statistics, makes
+/// scores comparable across partitions. The partitions share their schema, so a field is
+/// the same in each of them.
+#[derive(Clone)]
+pub struct PartitionStatistics {
+    searchers: Vec<Searcher>,
+}
+
+impl Bm25StatisticsProvider for PartitionStatistics {
+    fn total_num_tokens(&self, field: Field) -> tantivy::Result<u64> {
+        self.searchers
+            .iter()
+            .map(|searcher| searcher.total_num_tokens(field))
+            .sum()
+    }
+
+    fn total_num_docs(&self) -> tantivy::Result<u64> {
+        self.searchers
+            .iter()
+            .map(|searcher| searcher.total_num_docs())
+            .sum()
+    }
+
+    fn doc_freq(&self, term: &Term) -> tantivy::Result<u64> {
+        self.searchers
+            .iter()
+            .map(|searcher| searcher.doc_freq(term))
+            .sum()
+    }
+}
+
+/// The number of results in the first batch that `SearchResults` collects. Each batch after it
+/// is twice as large as the one before.
+const FIRST_BATCH_SIZE: usize = 100;
+
+/// The results of a search in descending score order, collected from the index in batches as
+/// they're reached. Each batch continues where the one before it ended, within the search's
+/// own offset and limit, and the key and ctid of a result are only looked up when it's reached.
+pub struct SearchResults<'a> {
+    state: SearchState,
+    executor: &'a Executor,
+    batch: std::vec::IntoIter<(Score, DocAddress, Option<i64>)>,
+    /// The offset of the next batch among all the results of the query.
+    offset: usize,
+    /// The number of results that the search's limit leaves, if it has one.
+    remaining: Option<usize>,
+    /// The size of the next batch, or `None` to collect every result that's left at once.
+    batch_size: Option<usize>,
+    exhausted: bool,
+}
+
+impl<'a> SearchResults<'a> {
+    fn new(state: SearchState, executor: &'a Executor, batch_size: Option<usize>) -> Self {
+        Self {
+            offset: state.config.offset_rows.unwrap_or(0),
+            remaining: state.config.limit_rows,
+            state,
+            executor,
+            batch: vec![].into_iter(),
+            batch_size,
+            exhausted: false,
+        }
+    }
+
+    /// Collect every result that's left with the next batch, for callers that read them all,
+    /// like a bitmap scan.
+    pub fn collect_rest(&mut self) {
+        self.batch_size = None;
+    }
+
+    fn next_batch(&mut self) {
+        let limit = match (self.batch_size, self.remaining) {
+            (Some(batch_size), Some(remaining)) => batch_size.min(remaining),
+            (Some(batch_size), None) => batch_size,
+            (None, Some(remaining)) => remaining,
+            (None, None) => self.state.searcher.num_docs() as usize,
+        };
+        let top_docs = self.state.top_docs_range(self.executor, limit, self.offset);
+
+        self.offset += top_docs.len();
+        self.remaining = self
+            .remaining
+            .map(|remaining| remaining.saturating_sub(top_docs.len()));
+        // A batch that's smaller than asked for holds the last results of the query.
+        self.exhausted =
+            self.batch_size.is_none() || top_docs.len() < limit || self.remaining == Some(0);
+        self.batch_size = self.batch_size.map(|batch_size| batch_size * 2);
+        self.batch = top_docs.into_iter();
+    }
+}
+
+impl Iterator for SearchResults<'_> {
+    type Item = (Score, DocAddress, i64, u64);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            match self.batch.next() {
+                Some((_, doc_address, _))
+                    if self
+                        .state
+                        .partition_window
+                        .as_ref()
+                        .is_some_and(|window| !window.contains(&doc_address)) => {}
+                Some((score, doc_address, key)) => {
+                    return Some(self.state.result(score, doc_address, key))
+                }
+                None if self.exhausted => return None,
+                None => self.next_batch(),
+            }
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct SearchState {
     pub query: Arc<dyn Query>,
     pub searcher: Searcher,
     pub config: SearchConfig,
     pub schema: SearchIndexSchema,
+    pub directory: WriterDirectory,
+    pub statistics: Option<PartitionStatistics>,
+    /// The results of this partition that are within the offset and limit of a search through
+    /// a partitioned index, which apply to the results of all the partitions together.
+    pub partition_window: Option<Arc<HashSet<DocAddress>>>,
 }
 
 impl SearchState {
@@ -228,6 +380,35 @@ impl SearchState {
             config: config.clone(),
             searcher: search_index.searcher(),
             schema: schema.clone(),
+            directory: search_index.directory.clone(),
+            statistics: None,
+            partition_window: None,
+        }
+    }
+
+    /// Scores documents with the BM25 statistics of all the given partitions, which must
+    /// include this state's own index, instead of the statistics of this index alone.
+    pub fn with_partition_statistics(mut self, partitions: &[SearchState]) -> Self {
+        self.statistics = Some(PartitionStatistics {
+            searchers: partitions
+                .iter()
+                .map(|partition| partition.searcher.clone())
+                .collect(),
+        });
+        self
+    }
+
+    /// Only returns the given results of this partition, which are those that are within the
+    /// offset and limit of the partitions' results merged by score.
+    pub fn with_partition_window(mut self, window: HashSet<DocAddress>) -> Self {
+        self.partition_window = Some(Arc::new(window));
+        self
+    }
+
+    fn statistics_provider(&self) -> &dyn Bm25StatisticsProvider {
+        match &self.statistics {
+            Some(statistics) => statistics,
+            None => &self.searcher,
         }
     }
 
@@ -251,20 +432,64 @@ impl SearchState {
     /// the Tantivy index without a Postgres deduplication, you should use the `search_dedup`
     /// method instead.
     pub fn search(&self, executor: &Executor) -> Vec<(Score, DocAddress, i64, u64)> {
+        SearchResults::new(self.clone(), executor, None).collect()
+    }
+
+    /// Like `search`, but the results are collected from the index in batches, as the returned
+    /// iterator reaches them. Callers that stop early, like an index scan ordered by score
+    /// under a LIMIT, only collect the top results they read, and Tantivy can skip the
+    /// documents that can't score high enough to be among them.
+    pub fn search_lazy<'a>(&self, executor: &'a Executor) -> SearchResults<'a> {
+        SearchResults::new(self.clone(), executor, Some(FIRST_BATCH_SIZE))
+    }
+
+    /// Look up the key and ctid of a result, and keep its score for the search's functions.
+    fn result(
+        &self,
+        score: Score,
+        doc_address: DocAddress,
+        key: Option<i64>,
+    ) -> (Score, DocAddress, i64, u64) {
+        let (key, ctid) = match key {
+            Some(key) => (key, self.ctid_value(doc_address)),
+            None => self.key_and_ctid_value(doc_address),
+        };
+        SearchStateManager::set_result(
+            key,
+            score,
+            doc_address,
+            self.directory.clone(),
+            self.config.alias.clone(),
+        )
+        .expect("could not store search result in state manager");
+        (score, doc_address, key, ctid)
+    }
+
+    /// The score and address of each result after limit + offset are applied, along with its
+    /// key when the collector already had to read it for a stable sort.
+    pub fn top_docs(&self, executor: &Executor) -> Vec<(Score, DocAddress, Option<i64>)> {
         // Extract limit and offset from the query config or set defaults.
         let limit = self.config.limit_rows.unwrap_or_else(|| {
             // We use unwrap_or_else here so this block doesn't run unless
             // we actually need the default value. This is important, because there can
             // be some cost to Tantivy API calls.
-            let num_docs = self.searcher.num_docs() as usize;
-            if num_docs > 0 {
-                num_docs // The collector will panic if it's passed a limit of 0.
-            } else {
-                1 // Since there's no docs to return anyways, just use 1.
-            }
+            self.searcher.num_docs() as usize
         });
-
         let offset = self.config.offset_rows.unwrap_or(0);
+        self.top_docs_range(executor, limit, offset)
+    }
+
+    /// Like `top_docs`, but with the given limit and offset instead of the search's own.
+    fn top_docs_range(
+        &self,
+        executor: &Executor,
+        limit: usize,
+        offset: usize,
+    ) -> Vec<(Score, DocAddress, Option<i64>)> {
+        // The collector will panic if it's passed a limit of 0.
+        if limit == 0 {
+            return vec![];
+        }
 
         if self.config.stable_sort.is_some_and(|stable| stable) {
             // If the user requires a stable sort, we'll use tweak_score. This allows us to retrieve
@@ -295,23 +520,12 @@ impl SearchState {
                     executor,
                     tantivy::query::EnableScoring::Enabled {
                         searcher: &self.searcher,
-                        statistics_provider: &self.searcher,
+                        statistics_provider: self.statistics_provider(),
                     },
                 )
                 .expect("failed to search")
                 .into_iter()
-                .map(|(score, doc_address)| {
-                    // This iterator contains the results after limit + offset are applied.
-                    let ctid = self.ctid_value(doc_address);
-                    SearchStateManager::set_result(
-                        score.key,
-                        score.bm25,
-                        doc_address,
-                        self.config.alias.clone(),
-                    )
-                    .expect("could not store search result in state manager");
-                    (score.bm25, doc_address, score.key, ctid)
-                })
+                .map(|(score, doc_address)| (score.bm25, doc_address, Some(score.key)))
                 .collect()
         } else {
             let collector = TopDocs::with_limit(limit).and_offset(offset);
@@ -322,23 +536,12 @@ impl SearchState {
                     executor,
                     tantivy::query::EnableScoring::Enabled {
                         searcher: &self.searcher,
-                        statistics_provider: &self.searcher,
+                        statistics_provider: self.statistics_provider(),
                     },
                 )
                 .expect("failed to search")
                 .into_iter()
-                .map(|(score, doc_address)| {
-                    // This iterator contains the results after limit + offset are applied.
-                    let (key, ctid) = self.key_and_ctid_value(doc_address);
-                    SearchStateManager::set_result(
-                        key,
-                        score,
-                        doc_address,
-                        self.config.alias.clone(),
-                    )
-                    .expect("could not store search result in state manager");
-                    (score, doc_address, key, ctid)
-                })
+                .map(|(score, doc_address)| (score, doc_address, None))
                 .collect()
         }
     }
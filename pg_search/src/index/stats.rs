@@ -0,0 +1,102 @@
+use std::time::SystemTime;
+use tantivy::SegmentComponent;
+
+use super::{SearchIndex, SearchIndexError};
+
+/// The size and shape of an index as of its last commit, from the metadata of its segments and
+/// the sizes of their files.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SearchIndexStats {
+    pub num_segments: usize,
+    pub live_docs: u64,
+    pub deleted_docs: u64,
+    pub postings_bytes: u64,
+    pub positions_bytes: u64,
+    pub fast_fields_bytes: u64,
+    pub store_bytes: u64,
+    /// The bytes of the term dictionaries, fieldnorms and deletes.
+    pub other_bytes: u64,
+    pub opstamp: u64,
+    pub last_merge_time: Option<SystemTime>,
+}
+
+impl SearchIndexStats {
+    pub fn total_bytes(&self) -> u64 {
+        self.postings_bytes
+            + self.positions_bytes
+            + self.fast_fields_bytes
+            + self.store_bytes
+            + self.other_bytes
+    }
+}
+
+impl SearchIndex {
+    pub fn stats(&self) -> Result<SearchIndexStats, SearchIndexError> {
+        let metas = self.index_metas()?;
+        let mut stats = SearchIndexStats {
+            num_segments: metas.segments.len(),
+            opstamp: metas.opstamp,
+            last_merge_time: self.directory.last_merge_time(),
+            ..Default::default()
+        };
+
+        for segment in &metas.segments {
+            stats.live_docs += segment.num_docs() as u64;
+            stats.deleted_docs += segment.num_deleted_docs() as u64;
+            for component in SegmentComponent::iterator() {
+                let bytes = self
+                    .directory
+                    .tantivy_file_size(&segment.relative_path(*component))?;
+                match component {
+                    SegmentComponent::Postings => stats.postings_bytes += bytes,
+                    SegmentComponent::Positions => stats.positions_bytes += bytes,
+                    SegmentComponent::FastFields => stats.fast_fields_bytes += bytes,
+                    SegmentComponent::Store | SegmentComponent::TempStore => {
+                        stats.store_bytes += bytes
+                    }
+                    SegmentComponent::Terms
+                    | SegmentComponent::FieldNorms
+                    | SegmentComponent::Delete => stats.other_bytes += bytes,
+                }
+            }
+        }
+
+        Ok(stats)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        fixtures::*,
+        writer::{WriterClient, WriterRequest},
+    };
+    use anyhow::Result;
+    use rstest::*;
+
+    #[rstest]
+    fn test_index_stats(default_index: MockSearchIndex) -> Result<()> {
+        let client = TestClient::new_arc();
+        let index = default_index.index;
+
+        let stats = index.stats()?;
+        assert_eq!(stats.num_segments, 0);
+        assert_eq!(stats.total_bytes(), 0);
+
+        index.insert(&client, 7, simple_doc(simple_schema(default_fields())))?;
+        client.lock().unwrap().request(WriterRequest::Commit {
+            directory: index.directory.clone(),
+            xid: 0,
+        })?;
+
+        let stats = index.stats()?;
+        assert_eq!(stats.num_segments, 1);
+        assert_eq!(stats.live_docs, 1);
+        assert_eq!(stats.deleted_docs, 0);
+        assert!(stats.postings_bytes > 0);
+        assert!(stats.store_bytes > 0);
+        assert_eq!(stats.last_merge_time, None);
+
+        Ok(())
+    }
+}
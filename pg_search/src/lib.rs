@@ -1,6 +1,7 @@
 mod api;
 mod env;
 mod globals;
+mod guc;
 mod index;
 mod postgres;
 mod query;
@@ -11,15 +12,15 @@ mod writer;
 pub mod fixtures;
 
 use crate::globals::WRITER_GLOBAL;
+use crate::guc::PostgresPgSearchGucSettings;
 use pgrx::bgworkers::{BackgroundWorker, BackgroundWorkerBuilder, SignalWakeFlags};
 use pgrx::*;
-use shared::gucs::PostgresGlobalGucSettings;
 use shared::telemetry::setup_telemetry_background_worker;
 use std::process;
 use std::time::Duration;
 
 // A static variable is required to host grand unified configuration settings.
-pub static GUCS: PostgresGlobalGucSettings = PostgresGlobalGucSettings::new();
+pub static GUCS: PostgresPgSearchGucSettings = PostgresPgSearchGucSettings::new();
 
 pgrx::pg_module_magic!();
 
@@ -31,6 +32,10 @@ extension_sql_file!("../sql/_bootstrap.sql");
 #[pg_guard]
 pub unsafe extern "C" fn _PG_init() {
     postgres::options::init();
+    postgres::custom_tokenizers::init();
+    postgres::drop::init();
+    postgres::prepared::init();
+    postgres::wal::init();
     GUCS.init("pg_search");
 
     // Set up the writer bgworker shared state.
@@ -84,15 +89,29 @@ pub fn setup_background_workers() {
 #[no_mangle]
 pub extern "C" fn pg_search_insert_worker(_arg: pg_sys::Datum) {
     pgrx::log!("starting pg_search insert worker at PID {}", process::id());
-    let writer = writer::Writer::new();
-    let mut server = writer::Server::new(writer).expect("error starting writer server");
 
-    // Retrieve the assigned port and assign to global state.
+    // Reconcile the indexes with the transactions that were in flight when Postgres last
+    // stopped, before any new writes can reach them. Standbys are told to do the same.
+    postgres::wal::log_recover();
+    match writer::WriterDirectory::all(&crate::env::postgres_data_dir_path()) {
+        Ok(directories) => {
+            for directory in directories {
+                writer::recover(&directory, env::transaction_status);
+            }
+        }
+        Err(err) => log!("could not list bm25 indexes to recover: {err}"),
+    }
+
+    // Each index gets its own writer, so that indexes can be written to concurrently.
+    let mut server = writer::Server::new(writer::writer_socket_path(), writer::Writer::new)
+        .expect("error starting writer server");
+
+    // Mark the server as listening in global state, so that clients know they can connect.
     // Note that we do not derefence the WRITER to mutate it, due to PGRX shared struct rules.
     // We also acquire its lock with `.exclusive` inside an enclosing block to ensure that
     // it is dropped after we are done with it.
     {
-        WRITER_GLOBAL.exclusive().set_addr(server.addr());
+        WRITER_GLOBAL.exclusive().set_listening();
     }
 
     // Handle an edge case where Postgres has been shut down very quickly. In this case, the
@@ -123,9 +142,9 @@ pub extern "C" fn pg_search_shutdown_worker(_arg: pg_sys::Datum) {
     // Check every second to see if we've received SIGTERM.
     while BackgroundWorker::wait_latch(Some(Duration::from_secs(1))) {}
 
-    // We've received SIGTERM. Send a shutdown message to the HTTP server.
+    // We've received SIGTERM. Send a shutdown message to the writer server.
     let mut writer_client: writer::Client<writer::WriterRequest> =
-        writer::Client::new(WRITER_GLOBAL.share().addr());
+        writer::Client::new(WRITER_GLOBAL.share().socket_path());
 
     writer_client
         .stop_server()
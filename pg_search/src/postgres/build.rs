@@ -1,29 +1,113 @@
-use crate::env::register_commit_callback;
+use crate::env::{current_xid, register_commit_callback};
 use crate::globals::WriterGlobal;
-use crate::index::SearchIndex;
+use crate::index::{SearchIndex, SearchIndexSettings};
 use crate::postgres::options::SearchIndexCreateOptions;
-use crate::postgres::utils::{get_search_index, lookup_index_tupdesc};
+use crate::postgres::utils::{get_search_index, lookup_index_expressions, lookup_index_tupdesc};
+use crate::postgres::wal;
 use crate::schema::{SearchFieldConfig, SearchFieldName, SearchFieldType};
-use crate::writer::WriterDirectory;
+use crate::writer::{
+    SearchFs, WriterDirectory, WriterRequest, WriterTransferPipeFilePath, WriterTransferProducer,
+};
 use pgrx::*;
 use std::collections::HashMap;
+use std::mem::size_of;
 use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicI32, AtomicU64, Ordering};
 
 // For now just pass the count on the build callback state
 struct BuildState {
     count: usize,
+    // The number of rows in the heap, which for a partial index includes those
+    // that don't satisfy the index predicate.
+    heap_tuples: f64,
     memctx: PgMemoryContexts,
+    directory: WriterDirectory,
+    // The participants of a parallel build each stream their documents to the writer
+    // server through their own pipe, rather than through the writer client.
+    producer: Option<WriterTransferProducer<WriterRequest>>,
+    // The state shared by the participants of a parallel build, or null for a serial build.
+    shared: *mut ParallelBuildShared,
+    // Whether this backend reports the progress of the build, which the workers of a
+    // parallel build don't.
+    reports_progress: bool,
 }
 
 impl BuildState {
-    fn new() -> Self {
+    fn new(directory: WriterDirectory) -> Self {
         BuildState {
             count: 0,
+            heap_tuples: 0.0,
             memctx: PgMemoryContexts::new("pg_search_index_build"),
+            directory,
+            producer: None,
+            shared: std::ptr::null_mut(),
+            reports_progress: true,
         }
     }
 }
 
+/// The phases of a build, which `pg_stat_progress_create_index` shows as the subphase of its
+/// "building index" phase.
+const BM25_PHASE_INITIALIZE: i64 = pg_sys::PROGRESS_CREATEIDX_SUBPHASE_INITIALIZE as i64;
+const BM25_PHASE_SCAN_TABLE: i64 = 2;
+const BM25_PHASE_WRITE_SEGMENTS: i64 = 3;
+
+/// The progress parameter that the size of the Tantivy index is reported in. Postgres doesn't
+/// use it for index builds, and `paradedb.bm25_build_progress` shows it as `bytes_written`.
+const PROGRESS_CREATEIDX_TANTIVY_BYTES: u32 = 17;
+
+/// How many documents a backend indexes between reports of the size of the Tantivy index,
+/// which means listing its directory.
+const PROGRESS_BYTES_INTERVAL: usize = 10_000;
+
+fn report_progress(param: u32, value: i64) {
+    unsafe { pg_sys::pgstat_progress_update_param(param as i32, value) }
+}
+
+fn report_bytes_written(directory: &WriterDirectory) {
+    // Progress is informational, so a failure to read the directory isn't an error.
+    if let Ok(size) = directory.tantivy_dir_size() {
+        report_progress(PROGRESS_CREATEIDX_TANTIVY_BYTES, size as i64);
+    }
+}
+
+/// The key of the state shared by the participants of a parallel build in the DSM segment.
+const PARALLEL_KEY_BM25_SHARED: u64 = 0xB250_0000_0000_0001;
+
+/// The key of the process ids of the workers of a parallel build that have created their
+/// transfer pipes, one per worker, or 0 until a worker has created its pipe.
+const PARALLEL_KEY_BM25_PIPES: u64 = 0xB250_0000_0000_0002;
+
+/// Postgres' ALIGNOF_BUFFER, the alignment of the parallel heap scan in the DSM segment.
+const ALIGNOF_BUFFER: usize = 32;
+
+/// The state shared by the participants of a parallel build. It's followed in the DSM segment
+/// by the parallel heap scan that they all take part in.
+#[repr(C)]
+struct ParallelBuildShared {
+    heap_oid: pg_sys::Oid,
+    index_oid: pg_sys::Oid,
+    is_concurrent: bool,
+    // The totals of every participant, including the leader. Documents are counted as they're
+    // indexed, so that the leader can report the progress of the whole build.
+    heap_tuples: AtomicU64,
+    index_tuples: AtomicU64,
+}
+
+impl ParallelBuildShared {
+    fn size_with_parallel_scan(parallel_scan_size: usize) -> usize {
+        buffer_align(size_of::<Self>()) + parallel_scan_size
+    }
+
+    unsafe fn parallel_scan(shared: *mut Self) -> pg_sys::ParallelTableScanDesc {
+        (shared as *mut u8).add(buffer_align(size_of::<Self>())) as pg_sys::ParallelTableScanDesc
+    }
+}
+
+fn buffer_align(len: usize) -> usize {
+    (len + ALIGNOF_BUFFER - 1) & !(ALIGNOF_BUFFER - 1)
+}
+
 #[pg_guard]
 // TODO: remove the unsafe
 pub extern "C" fn ambuild(
@@ -33,7 +117,6 @@ pub extern "C" fn ambuild(
 ) -> *mut pg_sys::IndexBuildResult {
     let heap_relation = unsafe { PgRelation::from_pg(heaprel) };
     let index_relation = unsafe { PgRelation::from_pg(indexrel) };
-    let index_name = index_relation.name().to_string();
 
     let rdopts: PgBox<SearchIndexCreateOptions> = if !index_relation.rd_options.is_null() {
         unsafe { PgBox::from_pg(index_relation.rd_options as *mut SearchIndexCreateOptions) }
@@ -44,26 +127,39 @@ pub extern "C" fn ambuild(
 
     // Create a map from column name to column type. We'll use this to verify that index
     // configurations passed by the user reference the correct types for each column.
-    let name_type_map: HashMap<SearchFieldName, SearchFieldType> = heap_relation
+    let mut name_type_map: HashMap<SearchFieldName, SearchFieldType> = heap_relation
         .tuple_desc()
         .into_iter()
         .filter_map(|attribute| {
-            let attname = attribute.name();
-            let attribute_type_oid = attribute.type_oid();
-            let array_type = unsafe { pg_sys::get_element_type(attribute_type_oid.value()) };
-            let base_oid = if array_type != pg_sys::InvalidOid {
-                PgOid::from(array_type)
-            } else {
-                attribute_type_oid
-            };
-            if let Ok(search_field_type) = SearchFieldType::try_from(&base_oid) {
-                Some((attname.into(), search_field_type))
-            } else {
-                None
-            }
+            let search_field_type = search_field_type(attribute.type_oid())?;
+            Some((attribute.name().into(), search_field_type))
         })
         .collect();
 
+    // Expression columns are indexed under the names given by the `expression_fields`
+    // option, which must not shadow a column of the table.
+    for expression in lookup_index_expressions(&index_relation) {
+        if heap_relation
+            .tuple_desc()
+            .iter()
+            .any(|attribute| !attribute.is_dropped() && attribute.name() == expression.name.0)
+        {
+            panic!(
+                "expression field '{}' has the same name as a column of the table",
+                expression.name
+            );
+        }
+        match search_field_type(expression.type_oid) {
+            Some(search_field_type) => {
+                name_type_map.insert(expression.name, search_field_type);
+            }
+            None => panic!(
+                "expression field '{}' has a type that can't be indexed",
+                expression.name
+            ),
+        }
+    }
+
     // Parse and validate the index configurations for each column.
     let text_fields =
         rdopts
@@ -101,6 +197,15 @@ pub extern "C" fn ambuild(
 
     let key_field = rdopts.get_key_field().expect("must specify key field");
 
+    if let Some(partition_of) = rdopts.get_partition_of() {
+        if !unsafe { (*heap_relation.rd_rel).relispartition } {
+            panic!(
+                "'{}' is not a partition, so its index can't be part of index '{partition_of}'",
+                heap_relation.name()
+            );
+        }
+    }
+
     match name_type_map.get(&key_field) {
         Some(SearchFieldType::I64) => {}
         None => panic!("key field does not exist"),
@@ -124,37 +229,347 @@ pub extern "C" fn ambuild(
         panic!("no fields specified")
     }
 
-    let directory = WriterDirectory::from_index_name(&index_name);
-    SearchIndex::new(directory, fields).expect("could not build search index");
+    let settings = SearchIndexSettings {
+        merge_policy: rdopts.get_merge_policy(),
+        writer_memory_budget_mb: rdopts.get_writer_memory_budget_mb(),
+        writer_threads: rdopts.get_writer_threads(),
+    };
+
+    let directory = WriterDirectory::from_index_oid(index_relation.oid().as_u32());
+    wal::log_create(&directory, &fields, &settings)
+        .expect("could not log creation of search index");
+    SearchIndex::new(directory.clone(), fields, settings).expect("could not build search index");
 
-    let state = do_heap_scan(index_info, &heap_relation, &index_relation);
+    // The planner's estimate of the number of rows, which is -1 if the table has never been
+    // vacuumed or analyzed.
+    let estimated_tuples = unsafe { (*heap_relation.rd_rel).reltuples };
+    report_progress(pg_sys::PROGRESS_CREATEIDX_SUBPHASE, BM25_PHASE_SCAN_TABLE);
+    report_progress(
+        pg_sys::PROGRESS_CREATEIDX_TUPLES_TOTAL,
+        estimated_tuples.max(0.0) as i64,
+    );
+
+    let (heap_tuples, index_tuples) =
+        match unsafe { do_parallel_heap_scan(index_info, &heap_relation, &index_relation) } {
+            Some(tuples) => tuples,
+            None => {
+                let mut state = BuildState::new(directory.clone());
+                do_heap_scan(
+                    index_info,
+                    &heap_relation,
+                    &index_relation,
+                    std::ptr::null_mut(),
+                    &mut state,
+                );
+                (state.heap_tuples, state.count as f64)
+            }
+        };
+
+    report_progress(pg_sys::PROGRESS_CREATEIDX_TUPLES_DONE, index_tuples as i64);
+    report_bytes_written(&directory);
+
+    // Most of the work of committing a Tantivy index is writing out the segments of the
+    // documents added to it, which we do now so that it's part of the reported progress,
+    // leaving little to do at commit time.
+    report_progress(
+        pg_sys::PROGRESS_CREATEIDX_SUBPHASE,
+        BM25_PHASE_WRITE_SEGMENTS,
+    );
+    let writer_client = WriterGlobal::client();
+    get_search_index(index_relation.oid())
+        .prepare_commit(&writer_client)
+        .expect("could not prepare commit for index build");
+    register_commit_callback(&writer_client, directory.clone())
+        .expect("could not register commit callbacks for build operation");
+    report_bytes_written(&directory);
 
     let mut result = unsafe { PgBox::<pg_sys::IndexBuildResult>::alloc0() };
-    result.heap_tuples = state.count as f64;
-    result.index_tuples = state.count as f64;
+    result.heap_tuples = heap_tuples;
+    result.index_tuples = index_tuples;
 
     result.into_pg()
 }
 
+/// The type of search field that values of a Postgres type are indexed as. Arrays are indexed
+/// as multiple values of their element type.
+fn search_field_type(type_oid: PgOid) -> Option<SearchFieldType> {
+    let array_type = unsafe { pg_sys::get_element_type(type_oid.value()) };
+    let base_oid = if array_type != pg_sys::InvalidOid {
+        PgOid::from(array_type)
+    } else {
+        type_oid
+    };
+    SearchFieldType::try_from(&base_oid).ok()
+}
+
+#[pg_guard]
+pub extern "C" fn ambuildphasename(phasenum: i64) -> *mut std::os::raw::c_char {
+    match phasenum {
+        BM25_PHASE_INITIALIZE => "initializing".as_pg_cstr(),
+        BM25_PHASE_SCAN_TABLE => "scanning table".as_pg_cstr(),
+        BM25_PHASE_WRITE_SEGMENTS => "writing segments".as_pg_cstr(),
+        _ => std::ptr::null_mut(),
+    }
+}
+
 #[pg_guard]
 pub extern "C" fn ambuildempty(_index_relation: pg_sys::Relation) {}
 
+/// Scan the heap and index every row that's returned. The scan is either a participant's
+/// share of a parallel heap scan, or null to scan the whole heap.
 fn do_heap_scan<'a>(
     index_info: *mut pg_sys::IndexInfo,
     heap_relation: &'a PgRelation,
     index_relation: &'a PgRelation,
-) -> BuildState {
-    let mut state = BuildState::new();
+    scan: pg_sys::TableScanDesc,
+    state: &mut BuildState,
+) {
     let _ = panic::catch_unwind(AssertUnwindSafe(|| unsafe {
-        pg_sys::IndexBuildHeapScan(
+        // We call the table access method directly, rather than through IndexBuildHeapScan,
+        // to learn how many rows were scanned. The scan evaluates the index predicate of a
+        // partial index, so the callback is only called for rows that satisfy it.
+        let table_am = heap_relation
+            .rd_tableam
+            .as_ref()
+            .expect("heap relation has no table access method");
+        let index_build_range_scan = table_am
+            .index_build_range_scan
+            .expect("table access method does not support index builds");
+        state.heap_tuples = index_build_range_scan(
             heap_relation.as_ptr(),
             index_relation.as_ptr(),
             index_info,
+            true,
+            false,
+            true,
+            0,
+            pg_sys::InvalidBlockNumber,
             Some(build_callback),
-            &mut state,
+            state as *mut BuildState as *mut std::os::raw::c_void,
+            scan,
         );
     }));
-    state
+}
+
+/// Build the index with parallel workers, if the planner thinks it's worth it. Each worker
+/// scans a share of the heap and streams its documents to the writer server through its own
+/// pipe, while the leader does the same. Returns the number of heap and index tuples, or None
+/// if no workers could be used, in which case the caller should scan the heap itself.
+///
+/// Postgres only plans parallel builds for btree indexes before version 17, which added
+/// `amcanbuildparallel`, so we set up the parallel context ourselves, just like btree does.
+unsafe fn do_parallel_heap_scan(
+    index_info: *mut pg_sys::IndexInfo,
+    heap_relation: &PgRelation,
+    index_relation: &PgRelation,
+) -> Option<(f64, f64)> {
+    let nworkers = pg_sys::plan_create_index_workers(heap_relation.oid(), index_relation.oid());
+    if nworkers <= 0 {
+        return None;
+    }
+
+    // A concurrent build only indexes the rows visible to its snapshot, while other builds
+    // see every row, and work out which ones to index as they go.
+    let is_concurrent = (*index_info).ii_Concurrent;
+    let snapshot = if is_concurrent {
+        pg_sys::RegisterSnapshot(pg_sys::GetTransactionSnapshot())
+    } else {
+        std::ptr::addr_of_mut!(pg_sys::SnapshotAnyData)
+    };
+
+    pg_sys::EnterParallelMode();
+    let pcxt = pg_sys::CreateParallelContext(
+        "pg_search".as_pg_cstr(),
+        "bm25_parallel_build_main".as_pg_cstr(),
+        nworkers,
+    );
+
+    let shared_size = ParallelBuildShared::size_with_parallel_scan(
+        pg_sys::table_parallelscan_estimate(heap_relation.as_ptr(), snapshot),
+    );
+    let pipes_size = size_of::<AtomicI32>() * nworkers as usize;
+    (*pcxt).estimator.space_for_chunks += buffer_align(shared_size) + buffer_align(pipes_size);
+    (*pcxt).estimator.number_of_keys += 2;
+
+    let end_parallel = |pcxt: *mut pg_sys::ParallelContext| {
+        if is_concurrent {
+            pg_sys::UnregisterSnapshot(snapshot);
+        }
+        pg_sys::DestroyParallelContext(pcxt);
+        pg_sys::ExitParallelMode();
+    };
+
+    // Without a DSM segment, there's nowhere to put the shared state.
+    pg_sys::InitializeParallelDSM(pcxt);
+    if (*pcxt).seg.is_null() {
+        end_parallel(pcxt);
+        return None;
+    }
+
+    let shared = pg_sys::shm_toc_allocate((*pcxt).toc, shared_size) as *mut ParallelBuildShared;
+    shared.write(ParallelBuildShared {
+        heap_oid: heap_relation.oid(),
+        index_oid: index_relation.oid(),
+        is_concurrent,
+        heap_tuples: AtomicU64::new(0),
+        index_tuples: AtomicU64::new(0),
+    });
+    pg_sys::table_parallelscan_initialize(
+        heap_relation.as_ptr(),
+        ParallelBuildShared::parallel_scan(shared),
+        snapshot,
+    );
+    pg_sys::shm_toc_insert((*pcxt).toc, PARALLEL_KEY_BM25_SHARED, shared as *mut _);
+    let pipes = pg_sys::shm_toc_allocate((*pcxt).toc, pipes_size) as *mut AtomicI32;
+    for i in 0..nworkers as usize {
+        pipes.add(i).write(AtomicI32::new(0));
+    }
+    pg_sys::shm_toc_insert((*pcxt).toc, PARALLEL_KEY_BM25_PIPES, pipes as *mut _);
+
+    pg_sys::LaunchParallelWorkers(pcxt);
+    if (*pcxt).nworkers_launched == 0 {
+        end_parallel(pcxt);
+        return None;
+    }
+    pg_sys::WaitForParallelWorkersToAttach(pcxt);
+
+    // Transfer pipes are named after the process that writes to them, so we need the process
+    // ids of the workers to tell the writer server where to read from. Each worker shares its
+    // process id once it has created its pipe, so the writer server is only told about pipes
+    // that exist, and doesn't wait for one that a failed worker never creates.
+    let directory = WriterDirectory::from_index_oid(index_relation.oid().as_u32());
+    let WriterTransferPipeFilePath(leader_pipe_path) = directory
+        .writer_transfer_pipe_path(true)
+        .expect("could not create transfer pipe for parallel index build");
+    let mut pipe_paths = vec![leader_pipe_path.clone()];
+    for i in 0..(*pcxt).nworkers_launched as usize {
+        loop {
+            let pid = (*pipes.add(i)).load(Ordering::SeqCst);
+            if pid != 0 {
+                pipe_paths.push(leader_pipe_path.with_file_name(pid.to_string()));
+                break;
+            }
+            // A worker that has stopped without creating its pipe failed before it could
+            // write anything, and its error is raised when we wait for the workers to finish.
+            let mut bgw_pid = 0;
+            let status =
+                pg_sys::GetBackgroundWorkerPid((*(*pcxt).worker.add(i)).bgwhandle, &mut bgw_pid);
+            if status != pg_sys::BgwHandleStatus::BGWH_STARTED {
+                break;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(10));
+            check_for_interrupts!();
+        }
+    }
+
+    let writer_client = WriterGlobal::client();
+    writer_client
+        .lock()
+        .expect("could not lock writer client for parallel index build")
+        .parallel_transfer(&pipe_paths)
+        .expect("could not start parallel transfer for index build");
+
+    // The leader takes part in the scan alongside the workers.
+    let producer = WriterTransferProducer::new(&leader_pipe_path)
+        .expect("could not open transfer pipe for parallel index build");
+    let mut state = BuildState {
+        producer: Some(producer),
+        shared,
+        ..BuildState::new(directory)
+    };
+    let scan = pg_sys::table_beginscan_parallel(
+        heap_relation.as_ptr(),
+        ParallelBuildShared::parallel_scan(shared),
+    );
+    do_heap_scan(index_info, heap_relation, index_relation, scan, &mut state);
+
+    (*shared)
+        .heap_tuples
+        .fetch_add(state.heap_tuples as u64, Ordering::SeqCst);
+
+    // Closing our pipe lets the writer server finish the transfer once the workers are done.
+    if let Some(producer) = state.producer.take() {
+        producer
+            .finish()
+            .expect("could not finish transfer for parallel index build");
+    }
+    pg_sys::WaitForParallelWorkersToFinish(pcxt);
+
+    let heap_tuples = (*shared).heap_tuples.load(Ordering::SeqCst) as f64;
+    let index_tuples = (*shared).index_tuples.load(Ordering::SeqCst) as f64;
+    end_parallel(pcxt);
+
+    Some((heap_tuples, index_tuples))
+}
+
+/// The entrypoint of a parallel build worker, called by Postgres with the DSM segment set up
+/// by `do_parallel_heap_scan`.
+#[pg_guard]
+#[no_mangle]
+pub extern "C" fn bm25_parallel_build_main(
+    _seg: *mut pg_sys::dsm_segment,
+    toc: *mut pg_sys::shm_toc,
+) {
+    unsafe {
+        let shared = pg_sys::shm_toc_lookup(toc, PARALLEL_KEY_BM25_SHARED, false)
+            as *mut ParallelBuildShared;
+
+        // Take the same locks as the leader, which are already held by its lock group.
+        let (heap_lockmode, index_lockmode) = if (*shared).is_concurrent {
+            (pg_sys::ShareUpdateExclusiveLock, pg_sys::RowExclusiveLock)
+        } else {
+            (pg_sys::ShareLock, pg_sys::AccessExclusiveLock)
+        };
+        let heaprel = pg_sys::table_open((*shared).heap_oid, heap_lockmode as pg_sys::LOCKMODE);
+        let indexrel = pg_sys::index_open((*shared).index_oid, index_lockmode as pg_sys::LOCKMODE);
+        let heap_relation = PgRelation::from_pg(heaprel);
+        let index_relation = PgRelation::from_pg(indexrel);
+
+        let index_info = pg_sys::BuildIndexInfo(indexrel);
+        (*index_info).ii_Concurrent = (*shared).is_concurrent;
+
+        let directory = WriterDirectory::from_index_oid(index_relation.oid().as_u32());
+        let WriterTransferPipeFilePath(pipe_path) = directory
+            .writer_transfer_pipe_path(true)
+            .expect("could not create transfer pipe for parallel index build");
+        WriterTransferProducer::<WriterRequest>::create_pipe(&pipe_path)
+            .expect("could not create transfer pipe for parallel index build");
+        // The leader tells the writer server to read from our pipe once we've shared our
+        // process id, which the pipe is named after.
+        let pipes = pg_sys::shm_toc_lookup(toc, PARALLEL_KEY_BM25_PIPES, false) as *mut AtomicI32;
+        (*pipes.add(pg_sys::ParallelWorkerNumber as usize))
+            .store(pg_sys::MyProcPid, Ordering::SeqCst);
+        let producer = WriterTransferProducer::open(pipe_path)
+            .expect("could not open transfer pipe for parallel index build");
+        let mut state = BuildState {
+            producer: Some(producer),
+            shared,
+            reports_progress: false,
+            ..BuildState::new(directory)
+        };
+
+        let scan =
+            pg_sys::table_beginscan_parallel(heaprel, ParallelBuildShared::parallel_scan(shared));
+        do_heap_scan(
+            index_info,
+            &heap_relation,
+            &index_relation,
+            scan,
+            &mut state,
+        );
+
+        (*shared)
+            .heap_tuples
+            .fetch_add(state.heap_tuples as u64, Ordering::SeqCst);
+        if let Some(producer) = state.producer.take() {
+            producer
+                .finish()
+                .expect("could not finish transfer for parallel index build");
+        }
+
+        pg_sys::index_close(indexrel, index_lockmode as pg_sys::LOCKMODE);
+        pg_sys::table_close(heaprel, heap_lockmode as pg_sys::LOCKMODE);
+    }
 }
 
 #[cfg(feature = "pg12")]
@@ -163,13 +578,13 @@ unsafe extern "C" fn build_callback(
     index: pg_sys::Relation,
     htup: pg_sys::HeapTuple,
     values: *mut pg_sys::Datum,
-    _isnull: *mut bool,
+    isnull: *mut bool,
     _tuple_is_alive: bool,
     state: *mut std::os::raw::c_void,
 ) {
     let htup = htup.as_ref().unwrap();
 
-    build_callback_internal(htup.t_self, values, state, index);
+    build_callback_internal(htup.t_self, values, isnull, state, index);
 }
 
 #[cfg(any(feature = "pg13", feature = "pg14", feature = "pg15", feature = "pg16"))]
@@ -178,17 +593,18 @@ unsafe extern "C" fn build_callback(
     index: pg_sys::Relation,
     ctid: pg_sys::ItemPointer,
     values: *mut pg_sys::Datum,
-    _isnull: *mut bool,
+    isnull: *mut bool,
     _tuple_is_alive: bool,
     state: *mut std::os::raw::c_void,
 ) {
-    build_callback_internal(*ctid, values, state, index);
+    build_callback_internal(*ctid, values, isnull, state, index);
 }
 
 #[inline(always)]
 unsafe fn build_callback_internal(
     ctid: pg_sys::ItemPointerData,
     values: *mut pg_sys::Datum,
+    isnull: *mut bool,
     state: *mut std::os::raw::c_void,
     index: pg_sys::Relation,
 ) {
@@ -209,25 +625,59 @@ unsafe fn build_callback_internal(
         state.memctx.switch_to(|_| {
             let index_relation_ref: PgRelation = PgRelation::from_pg(index);
             let tupdesc = lookup_index_tupdesc(&index_relation_ref);
+            let expressions = lookup_index_expressions(&index_relation_ref);
             let index_name = index_relation_ref.name();
-            let search_index = get_search_index(index_name);
+            let search_index = get_search_index(index_relation_ref.oid());
             let search_document = search_index
-                .row_to_search_document(ctid, &tupdesc, values)
+                .row_to_search_document(ctid, &tupdesc, values, isnull, &expressions)
                 .unwrap_or_else(|err| {
                     panic!("error creating index entries for index '{index_name}': {err:?}",)
                 });
 
-            let writer_client = WriterGlobal::client();
+            match state.producer.as_mut() {
+                // A parallel build participant writes straight to its own pipe. The leader
+                // registers the commit callbacks once every participant is done.
+                Some(producer) => {
+                    let request = WriterRequest::Insert {
+                        directory: search_index.directory.clone(),
+                        xid: current_xid(),
+                        document: search_document,
+                    };
+                    producer.write_message(&request).unwrap_or_else(|err| {
+                        panic!("error inserting document during parallel build: {err:?}")
+                    });
+                    wal::log_request(&request).unwrap_or_else(|err| {
+                        panic!("error logging document during parallel build: {err:?}")
+                    });
+                }
+                None => {
+                    let writer_client = WriterGlobal::client();
 
-            search_index
-                .insert(&writer_client, search_document)
-                .unwrap_or_else(|err| {
-                    panic!("error inserting document during build callback: {err:?}")
-                });
+                    search_index
+                        .insert(&writer_client, current_xid(), search_document)
+                        .unwrap_or_else(|err| {
+                            panic!("error inserting document during build callback: {err:?}")
+                        });
 
-            register_commit_callback(&writer_client, search_index.directory.clone())
-                .expect("could not register commit callbacks for build operation");
+                    register_commit_callback(&writer_client, search_index.directory.clone())
+                        .expect("could not register commit callbacks for build operation");
+                }
+            }
         });
         state.memctx.reset();
     }
+
+    state.count += 1;
+
+    // The documents indexed by every participant of a parallel build are counted together.
+    let index_tuples = match state.shared.as_ref() {
+        Some(shared) => shared.index_tuples.fetch_add(1, Ordering::SeqCst) + 1,
+        None => state.count as u64,
+    };
+    if state.reports_progress {
+        report_progress(pg_sys::PROGRESS_CREATEIDX_TUPLES_DONE, index_tuples as i64);
+        if state.count % PROGRESS_BYTES_INTERVAL == 0 {
+            report_bytes_written(&state.directory);
+        }
+    }
 }
@@ -1,3 +1,6 @@
+use crate::index::SearchIndex;
+use crate::postgres::utils::get_search_index;
+use crate::schema::SearchConfig;
 use pgrx::*;
 
 #[allow(clippy::too_many_arguments)]
@@ -14,21 +17,7 @@ pub unsafe extern "C" fn amcostestimate(
 ) {
     let path = path.as_ref().expect("path argument is NULL");
     let indexinfo = path.indexinfo.as_ref().expect("indexinfo in path is NULL");
-    let index_relation = unsafe {
-        PgRelation::with_lock(
-            indexinfo.indexoid,
-            pg_sys::AccessShareLock as pg_sys::LOCKMODE,
-        )
-    };
-    let heap_relation = index_relation
-        .heap_relation()
-        .expect("failed to get heap relation for index");
-
-    *index_correlation = 1.0;
-    *index_startup_cost = 0.0;
-    *index_pages = 0.0;
-    *index_total_cost = 0.0;
-    *index_selectivity = 1.0;
+    let search_index = get_search_index(indexinfo.indexoid);
 
     #[cfg(any(
         feature = "pg12",
@@ -39,6 +28,9 @@ pub unsafe extern "C" fn amcostestimate(
     ))]
     let index_clauses = PgList::<pg_sys::IndexClause>::from_pg(path.indexclauses);
 
+    // The clauses of a scan are combined into a single search, but we estimate them as if
+    // they matched independently, like Postgres does for the clauses of other indexes.
+    let mut selectivity = 1.0;
     for clause in index_clauses.iter_ptr() {
         #[cfg(any(
             feature = "pg12",
@@ -54,12 +46,65 @@ pub unsafe extern "C" fn amcostestimate(
             .as_ref()
             .expect("restrict info in index clause is NULL");
 
-        if ri.norm_selec > 0f64 {
-            *index_selectivity = ri.norm_selec.min(*index_selectivity);
-        }
+        // Fall back to the planner's own estimate for clauses that we can't estimate, like
+        // those whose search config is only known when the query runs.
+        let clause_selectivity = estimate_clause_selectivity(search_index, ri.clause)
+            .or_else(|| (ri.norm_selec > 0f64).then_some(ri.norm_selec))
+            .unwrap_or(1.0);
+        selectivity *= clause_selectivity;
     }
 
-    let reltuples = heap_relation.reltuples().unwrap_or(1f32) as f64;
-    *index_total_cost += *index_selectivity * reltuples * pg_sys::cpu_index_tuple_cost;
-    *index_total_cost -= pg_sys::random_page_cost;
+    let index_tuples = selectivity * (*indexinfo.rel).tuples.max(0.0);
+
+    // Every match is found and scored when the scan starts, before any of them are returned.
+    *index_startup_cost = index_tuples * pg_sys::cpu_operator_cost;
+    *index_total_cost = *index_startup_cost + index_tuples * pg_sys::cpu_index_tuple_cost;
+    *index_selectivity = selectivity;
+    // Matches are returned by score, in no particular order of the heap.
+    *index_correlation = 0.0;
+    // The index is stored outside of Postgres' pages.
+    *index_pages = 0.0;
+}
+
+/// The fraction of rows that an `@@@` index clause is estimated to match, if its search
+/// config is a constant. `@@@ ANY(array)` matches the rows that any config in its array does.
+unsafe fn estimate_clause_selectivity(
+    search_index: &SearchIndex,
+    clause: *mut pg_sys::Expr,
+) -> Option<f64> {
+    let clause = clause as *mut pg_sys::Node;
+    let (args, is_array) = if is_a(clause, pg_sys::NodeTag::T_OpExpr) {
+        ((*(clause as *mut pg_sys::OpExpr)).args, false)
+    } else if is_a(clause, pg_sys::NodeTag::T_ScalarArrayOpExpr) {
+        ((*(clause as *mut pg_sys::ScalarArrayOpExpr)).args, true)
+    } else {
+        return None;
+    };
+
+    let config_arg = PgList::<pg_sys::Node>::from_pg(args).get_ptr(1)?;
+    if !is_a(config_arg, pg_sys::NodeTag::T_Const) {
+        return None;
+    }
+    let config_const = &*(config_arg as *mut pg_sys::Const);
+    if config_const.constisnull {
+        // The @@@ operator is strict, so a NULL search config matches nothing.
+        return Some(0.0);
+    }
+
+    if is_array {
+        let configs = Array::<JsonB>::from_datum(config_const.constvalue, false)?;
+        let selectivity = configs
+            .iter()
+            .flatten()
+            .map(|config_jsonb| {
+                let config = SearchConfig::from_jsonb(config_jsonb).ok()?;
+                search_index.estimate_selectivity(&config)
+            })
+            .sum::<Option<f64>>()?;
+        Some(selectivity.min(1.0))
+    } else {
+        let config_jsonb = JsonB::from_datum(config_const.constvalue, false)?;
+        let config = SearchConfig::from_jsonb(config_jsonb).ok()?;
+        search_index.estimate_selectivity(&config)
+    }
 }
@@ -0,0 +1,22 @@
+use pgrx::pg_sys::AsPgCStr;
+use pgrx::*;
+use tokenizers::{CustomTokenizerRegistry, CUSTOM_TOKENIZER_REGISTRY, CUSTOM_TOKENIZER_RENDEZVOUS};
+
+use crate::writer::user_dictionary_dir_path;
+
+/// Publish the custom tokenizer registry, so that libraries that are loaded after `pg_search`
+/// can register their own tokenizers with it. Tokenizers are only visible to the processes
+/// that registered them, so they must be registered from `shared_preload_libraries`, which
+/// every backend and the index writer inherit.
+///
+/// Also points the Lindera tokenizers at the directory that their user dictionaries are read
+/// from.
+pub fn init() {
+    tokenizers::lindera::set_user_dictionary_dir(user_dictionary_dir_path());
+
+    unsafe {
+        let registry = pg_sys::find_rendezvous_variable(CUSTOM_TOKENIZER_RENDEZVOUS.as_pg_cstr());
+        *registry = &CUSTOM_TOKENIZER_REGISTRY as *const CustomTokenizerRegistry
+            as *mut std::os::raw::c_void;
+    }
+}
@@ -1,7 +1,9 @@
 use pgrx::*;
 
 use crate::{
-    env::register_commit_callback, globals::WriterGlobal, index::SearchIndex,
+    env::{current_xid, register_commit_callback},
+    globals::WriterGlobal,
+    index::SearchIndex,
     writer::WriterDirectory,
 };
 
@@ -16,8 +18,7 @@ pub extern "C" fn ambulkdelete(
     let mut stats = unsafe { PgBox::from_pg(stats) };
     let index_rel: pg_sys::Relation = info.index;
     let index_relation = unsafe { PgRelation::from_pg(index_rel) };
-    let index_name = index_relation.name();
-    let directory = WriterDirectory::from_index_name(index_name);
+    let directory = WriterDirectory::from_index_oid(index_relation.oid().as_u32());
     let search_index = SearchIndex::from_cache(&directory)
         .unwrap_or_else(|err| panic!("error loading index from directory: {err}"));
 
@@ -34,7 +35,7 @@ pub extern "C" fn ambulkdelete(
         .expect("could not register commit callbacks for delete operation");
 
     if let Some(actual_callback) = callback {
-        match search_index.delete(&writer_client, |ctid| unsafe {
+        match search_index.delete(&writer_client, current_xid(), |ctid| unsafe {
             actual_callback(ctid, callback_state)
         }) {
             Ok((deleted, not_deleted)) => {
@@ -0,0 +1,62 @@
+use crate::{globals::WriterGlobal, index::SearchIndex};
+use pgrx::*;
+
+static mut PREV_OBJECT_ACCESS_HOOK: pg_sys::object_access_hook_type = None;
+
+/// Install a hook that removes the data of a bm25 index when it's dropped, however it's
+/// dropped: by DROP INDEX, by dropping its table, or by REINDEX CONCURRENTLY, which drops the
+/// old index once its rebuilt copy has taken its place.
+pub fn init() {
+    unsafe {
+        PREV_OBJECT_ACCESS_HOOK = pg_sys::object_access_hook;
+        pg_sys::object_access_hook = Some(object_access_hook);
+    }
+}
+
+#[pg_guard]
+unsafe extern "C" fn object_access_hook(
+    access: pg_sys::ObjectAccessType,
+    class_id: pg_sys::Oid,
+    object_id: pg_sys::Oid,
+    sub_id: i32,
+    arg: *mut std::os::raw::c_void,
+) {
+    if let Some(prev_hook) = PREV_OBJECT_ACCESS_HOOK {
+        prev_hook(access, class_id, object_id, sub_id, arg);
+    }
+
+    if access != pg_sys::ObjectAccessType::OAT_DROP
+        || class_id != pg_sys::RelationRelationId
+        || sub_id != 0
+        || !is_bm25_index(object_id)
+    {
+        return;
+    }
+
+    // The drop can still be rolled back, so the data is only removed once it commits.
+    let index_oid = object_id.as_u32();
+    register_xact_callback(PgXactCallbackEvent::Commit, move || {
+        // The transaction has already committed, so we can't raise an error here.
+        if let Err(err) = SearchIndex::drop_index(&WriterGlobal::client(), index_oid) {
+            warning!("could not remove the data of dropped bm25 index {index_oid}: {err}");
+        }
+    });
+}
+
+unsafe fn is_bm25_index(relation_oid: pg_sys::Oid) -> bool {
+    let bm25_oid = pg_sys::get_index_am_oid("bm25".as_pg_cstr(), true);
+    if bm25_oid == pg_sys::InvalidOid {
+        return false;
+    }
+
+    let relation = pg_sys::RelationIdGetRelation(relation_oid);
+    if relation.is_null() {
+        return false;
+    }
+    let is_bm25_index = (*(*relation).rd_rel).relkind
+        == pg_sys::RELKIND_INDEX as std::os::raw::c_char
+        && (*(*relation).rd_rel).relam == bm25_oid;
+    pg_sys::RelationClose(relation);
+
+    is_bm25_index
+}
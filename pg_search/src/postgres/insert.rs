@@ -1,6 +1,8 @@
 use super::utils::get_search_index;
 use crate::{
-    env::register_commit_callback, globals::WriterGlobal, postgres::utils::lookup_index_tupdesc,
+    env::{current_xid, register_commit_callback},
+    globals::WriterGlobal,
+    postgres::utils::{lookup_index_expressions, lookup_index_tupdesc},
 };
 use pgrx::*;
 
@@ -10,14 +12,14 @@ use pgrx::*;
 pub unsafe extern "C" fn aminsert(
     index_relation: pg_sys::Relation,
     values: *mut pg_sys::Datum,
-    _isnull: *mut bool,
+    isnull: *mut bool,
     heap_tid: pg_sys::ItemPointer,
     _heap_relation: pg_sys::Relation,
     _check_unique: pg_sys::IndexUniqueCheck,
     _index_unchanged: bool,
     _index_info: *mut pg_sys::IndexInfo,
 ) -> bool {
-    aminsert_internal(index_relation, values, heap_tid)
+    aminsert_internal(index_relation, values, isnull, heap_tid)
 }
 
 #[cfg(any(feature = "pg12", feature = "pg13"))]
@@ -25,27 +27,29 @@ pub unsafe extern "C" fn aminsert(
 pub unsafe extern "C" fn aminsert(
     index_relation: pg_sys::Relation,
     values: *mut pg_sys::Datum,
-    _isnull: *mut bool,
+    isnull: *mut bool,
     heap_tid: pg_sys::ItemPointer,
     _heap_relation: pg_sys::Relation,
     _check_unique: pg_sys::IndexUniqueCheck,
     _index_info: *mut pg_sys::IndexInfo,
 ) -> bool {
-    aminsert_internal(index_relation, values, heap_tid)
+    aminsert_internal(index_relation, values, isnull, heap_tid)
 }
 
 #[inline(always)]
 unsafe fn aminsert_internal(
     index_relation: pg_sys::Relation,
     values: *mut pg_sys::Datum,
+    isnull: *mut bool,
     ctid: pg_sys::ItemPointer,
 ) -> bool {
     let index_relation_ref: PgRelation = PgRelation::from_pg(index_relation);
     let tupdesc = lookup_index_tupdesc(&index_relation_ref);
+    let expressions = lookup_index_expressions(&index_relation_ref);
     let index_name = index_relation_ref.name();
-    let search_index = get_search_index(index_name);
+    let search_index = get_search_index(index_relation_ref.oid());
     let search_document = search_index
-        .row_to_search_document(*ctid, &tupdesc, values)
+        .row_to_search_document(*ctid, &tupdesc, values, isnull, &expressions)
         .unwrap_or_else(|err| {
             panic!("error creating index entries for index '{index_name}': {err:?}",)
         });
@@ -55,8 +59,81 @@ unsafe fn aminsert_internal(
         .expect("could not register commit callbacks for insert operation");
 
     search_index
-        .insert(&writer_client, search_document)
+        .insert(&writer_client, current_xid(), search_document)
         .unwrap_or_else(|err| panic!("error inserting document during insert callback: {err:?}"));
 
     true
 }
+
+/// Index heap rows again, as `aminsert` did when they were written. This is how a bm25 index
+/// that's missing some rows is repaired without being rebuilt. Each ctid is the root of a HOT
+/// chain, which is followed to the version of the row that's visible to the active snapshot,
+/// like an index scan does, and the row is indexed under the root. Returns the number of rows
+/// that were found.
+pub unsafe fn reinsert_heap_rows(
+    index_relation: &PgRelation,
+    heap_relation: &PgRelation,
+    ctids: &[u64],
+) -> usize {
+    let index_info = pg_sys::BuildIndexInfo(index_relation.as_ptr());
+    let estate = pg_sys::CreateExecutorState();
+    let econtext = pg_sys::MakePerTupleExprContext(estate);
+    let slot = pg_sys::table_slot_create(heap_relation.as_ptr(), std::ptr::null_mut());
+    (*econtext).ecxt_scantuple = slot;
+
+    let table_am = &*heap_relation.rd_tableam;
+    let index_fetch_begin = table_am
+        .index_fetch_begin
+        .expect("table access method can't fetch rows by ctid");
+    let index_fetch_tuple = table_am
+        .index_fetch_tuple
+        .expect("table access method can't fetch rows by ctid");
+    let index_fetch_end = table_am
+        .index_fetch_end
+        .expect("table access method can't fetch rows by ctid");
+    let fetch = index_fetch_begin(heap_relation.as_ptr());
+    let num_columns = (*index_info).ii_NumIndexAttrs as usize;
+    let mut values = vec![pg_sys::Datum::from(0usize); num_columns];
+    let mut isnull = vec![false; num_columns];
+    let mut found = 0;
+
+    for ctid in ctids {
+        let mut root_tid = pg_sys::ItemPointerData::default();
+        u64_to_item_pointer(*ctid, &mut root_tid);
+        // The fetch moves the tid along the chain, to the version that it finds.
+        let mut heap_tid = root_tid;
+        let mut call_again = false;
+        let mut all_dead = false;
+        if !index_fetch_tuple(
+            fetch,
+            &mut heap_tid,
+            pg_sys::GetActiveSnapshot(),
+            slot,
+            &mut call_again,
+            &mut all_dead,
+        ) {
+            continue;
+        }
+
+        pg_sys::FormIndexDatum(
+            index_info,
+            slot,
+            estate,
+            values.as_mut_ptr(),
+            isnull.as_mut_ptr(),
+        );
+        aminsert_internal(
+            index_relation.as_ptr(),
+            values.as_mut_ptr(),
+            isnull.as_mut_ptr(),
+            &mut root_tid,
+        );
+        pg_sys::MemoryContextReset((*econtext).ecxt_per_tuple_memory);
+        found += 1;
+    }
+
+    index_fetch_end(fetch);
+    pg_sys::ExecDropSingleTupleTableSlot(slot);
+    pg_sys::FreeExecutorState(estate);
+    found
+}
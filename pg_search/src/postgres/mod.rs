@@ -2,12 +2,16 @@ use pgrx::*;
 
 mod build;
 mod cost;
+pub mod custom_tokenizers;
 mod delete;
-mod insert;
+pub mod drop;
+pub mod insert;
 pub mod options;
+pub mod prepared;
 mod scan;
 mod vacuum;
 mod validate;
+pub mod wal;
 
 pub mod utils;
 
@@ -24,12 +28,14 @@ fn bm25_handler(_fcinfo: pg_sys::FunctionCallInfo) -> PgBox<pg_sys::IndexAmRouti
     amroutine.amsupport = 0;
     amroutine.amcanmulticol = true;
     amroutine.amsearcharray = true;
+    amroutine.amcanorderbyop = true;
 
     amroutine.amkeytype = pg_sys::InvalidOid;
 
     amroutine.amvalidate = Some(validate::amvalidate);
     amroutine.ambuild = Some(build::ambuild);
     amroutine.ambuildempty = Some(build::ambuildempty);
+    amroutine.ambuildphasename = Some(build::ambuildphasename);
     amroutine.aminsert = Some(insert::aminsert);
     amroutine.ambulkdelete = Some(delete::ambulkdelete);
     amroutine.amvacuumcleanup = Some(vacuum::amvacuumcleanup);
@@ -38,14 +44,7 @@ fn bm25_handler(_fcinfo: pg_sys::FunctionCallInfo) -> PgBox<pg_sys::IndexAmRouti
     amroutine.ambeginscan = Some(scan::ambeginscan);
     amroutine.amrescan = Some(scan::amrescan);
     amroutine.amgettuple = Some(scan::amgettuple);
-    // Disabling bitmap scans for the following reasons:
-    // 1. Bitmap scans are less optimal for our use case, where the AM API's capabilities are focused on supporting
-    //    what our index can reasonably support. Our extension leverages the efficiency of the index to the fullest,
-    //    without the need for intermediary bitmap scans.
-    // 2. Supporting bitmap scans would require transformation of queries into actual bitmaps, which introduces complexity
-    //    without significant performance gain. This complexity is unnecessary as our operator does not require bitmap scans
-    //    for optimal functioning.
-    amroutine.amgetbitmap = None;
+    amroutine.amgetbitmap = Some(scan::amgetbitmap);
     amroutine.amendscan = Some(scan::amendscan);
 
     amroutine.into_pg_boxed()
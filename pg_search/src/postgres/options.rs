@@ -5,6 +5,7 @@ use serde_json::json;
 use std::collections::HashMap;
 use std::ffi::CStr;
 
+use crate::index::MergePolicyConfig;
 use crate::schema::{SearchFieldConfig, SearchFieldName};
 
 /* ADDING OPTIONS
@@ -34,6 +35,11 @@ pub struct SearchIndexCreateOptions {
     boolean_fields_offset: i32,
     json_fields_offset: i32,
     key_field_offset: i32,
+    expression_fields_offset: i32,
+    partition_of_offset: i32,
+    merge_policy_offset: i32,
+    writer_memory_budget: i32,
+    writer_threads: i32,
 }
 
 #[pg_guard]
@@ -77,6 +83,29 @@ extern "C" fn validate_key_field(value: *const std::os::raw::c_char) {
     cstr_to_rust_str(value);
 }
 
+#[pg_guard]
+extern "C" fn validate_expression_fields(value: *const std::os::raw::c_char) {
+    let json_str = cstr_to_rust_str(value);
+    if json_str.is_empty() {
+        return;
+    }
+    SearchIndexCreateOptions::deserialize_expression_fields(json_str);
+}
+
+#[pg_guard]
+extern "C" fn validate_partition_of(value: *const std::os::raw::c_char) {
+    cstr_to_rust_str(value);
+}
+
+#[pg_guard]
+extern "C" fn validate_merge_policy(value: *const std::os::raw::c_char) {
+    let json_str = cstr_to_rust_str(value);
+    if json_str.is_empty() {
+        return;
+    }
+    SearchIndexCreateOptions::deserialize_merge_policy(json_str);
+}
+
 #[inline]
 fn cstr_to_rust_str(value: *const std::os::raw::c_char) -> String {
     if value.is_null() {
@@ -90,7 +119,7 @@ fn cstr_to_rust_str(value: *const std::os::raw::c_char) -> String {
 }
 
 // For now, we support changing the tokenizer between default, raw, and en_stem
-const NUM_REL_OPTS: usize = 5;
+const NUM_REL_OPTS: usize = 10;
 #[pg_guard]
 pub unsafe extern "C" fn amoptions(
     reloptions: pg_sys::Datum,
@@ -122,6 +151,31 @@ pub unsafe extern "C" fn amoptions(
             opttype: pg_sys::relopt_type_RELOPT_TYPE_STRING,
             offset: offset_of!(SearchIndexCreateOptions, key_field_offset) as i32,
         },
+        pg_sys::relopt_parse_elt {
+            optname: "expression_fields".as_pg_cstr(),
+            opttype: pg_sys::relopt_type_RELOPT_TYPE_STRING,
+            offset: offset_of!(SearchIndexCreateOptions, expression_fields_offset) as i32,
+        },
+        pg_sys::relopt_parse_elt {
+            optname: "partition_of".as_pg_cstr(),
+            opttype: pg_sys::relopt_type_RELOPT_TYPE_STRING,
+            offset: offset_of!(SearchIndexCreateOptions, partition_of_offset) as i32,
+        },
+        pg_sys::relopt_parse_elt {
+            optname: "merge_policy".as_pg_cstr(),
+            opttype: pg_sys::relopt_type_RELOPT_TYPE_STRING,
+            offset: offset_of!(SearchIndexCreateOptions, merge_policy_offset) as i32,
+        },
+        pg_sys::relopt_parse_elt {
+            optname: "writer_memory_budget".as_pg_cstr(),
+            opttype: pg_sys::relopt_type_RELOPT_TYPE_INT,
+            offset: offset_of!(SearchIndexCreateOptions, writer_memory_budget) as i32,
+        },
+        pg_sys::relopt_parse_elt {
+            optname: "writer_threads".as_pg_cstr(),
+            opttype: pg_sys::relopt_type_RELOPT_TYPE_INT,
+            offset: offset_of!(SearchIndexCreateOptions, writer_threads) as i32,
+        },
     ];
     build_relopts(reloptions, validate, options)
 }
@@ -198,10 +252,12 @@ impl SearchIndexCreateOptions {
         config_map
             .into_iter()
             .map(|(field_name, field_config)| {
-                (
-                    field_name.into(),
-                    serde_json::from_value(json!({variant.clone(): field_config})).unwrap(),
-                )
+                let config: SearchFieldConfig =
+                    serde_json::from_value(json!({variant.clone(): field_config})).unwrap();
+                config
+                    .validate()
+                    .unwrap_or_else(|err| panic!("invalid config for field {field_name}: {err}"));
+                (field_name.into(), config)
             })
             .collect()
     }
@@ -238,6 +294,65 @@ impl SearchIndexCreateOptions {
         Self::deserialize_config_fields("Json".into(), config)
     }
 
+    /// The names of the fields that hold the values of the index's expression columns,
+    /// which come after the indexed row, in the same order as the columns.
+    fn deserialize_expression_fields(serialized: String) -> Vec<SearchFieldName> {
+        let names: Vec<String> = json5::from_str(&serialized)
+            .unwrap_or_else(|err| panic!("failed to deserialize expression fields: {err:?}"));
+
+        names.into_iter().map(SearchFieldName::from).collect()
+    }
+
+    pub fn get_expression_fields(&self) -> Vec<SearchFieldName> {
+        let config = self.get_str(self.expression_fields_offset, "".to_string());
+        if config.is_empty() {
+            return Vec::new();
+        }
+        Self::deserialize_expression_fields(config)
+    }
+
+    /// The name of the index that this index is searched through, if it's the index of a
+    /// partition of a partitioned table.
+    pub fn get_partition_of(&self) -> Option<String> {
+        let partition_of = self.get_str(self.partition_of_offset, "".to_string());
+        if partition_of.is_empty() {
+            None
+        } else {
+            Some(partition_of)
+        }
+    }
+
+    fn deserialize_merge_policy(serialized: String) -> MergePolicyConfig {
+        let config: MergePolicyConfig = json5::from_str(&serialized)
+            .unwrap_or_else(|err| panic!("failed to deserialize merge policy: {err:?}"));
+        config
+            .validate()
+            .unwrap_or_else(|err| panic!("invalid merge policy: {err}"));
+        config
+    }
+
+    /// How the index's segments are merged, which is Tantivy's default policy if the
+    /// `merge_policy` option isn't given.
+    pub fn get_merge_policy(&self) -> MergePolicyConfig {
+        let config = self.get_str(self.merge_policy_offset, "".to_string());
+        if config.is_empty() {
+            return MergePolicyConfig::default();
+        }
+        Self::deserialize_merge_policy(config)
+    }
+
+    /// The memory budget of the index's writer in megabytes, if it overrides the
+    /// `paradedb.pg_search_writer_memory_budget` setting.
+    pub fn get_writer_memory_budget_mb(&self) -> Option<usize> {
+        (self.writer_memory_budget > 0).then_some(self.writer_memory_budget as usize)
+    }
+
+    /// The number of indexing threads of the index's writer, if it overrides the
+    /// `paradedb.pg_search_writer_threads` setting.
+    pub fn get_writer_threads(&self) -> Option<usize> {
+        (self.writer_threads > 0).then_some(self.writer_threads as usize)
+    }
+
     pub fn get_key_field(&self) -> Option<SearchFieldName> {
         let key_field = self.get_str(self.key_field_offset, "".to_string());
         if key_field.is_empty() {
@@ -319,4 +434,63 @@ pub unsafe fn init() {
             pg_sys::AccessExclusiveLock as pg_sys::LOCKMODE
         },
     );
+    pg_sys::add_string_reloption(
+        RELOPT_KIND_PDB,
+        "expression_fields".as_pg_cstr(),
+        "JSON array naming the fields that index the expression columns, in order".as_pg_cstr(),
+        std::ptr::null(),
+        Some(validate_expression_fields),
+        #[cfg(any(feature = "pg13", feature = "pg14", feature = "pg15", feature = "pg16"))]
+        {
+            pg_sys::AccessExclusiveLock as pg_sys::LOCKMODE
+        },
+    );
+    pg_sys::add_string_reloption(
+        RELOPT_KIND_PDB,
+        "partition_of".as_pg_cstr(),
+        "Name of the index of the partitioned table that this partition's index belongs to"
+            .as_pg_cstr(),
+        std::ptr::null(),
+        Some(validate_partition_of),
+        #[cfg(any(feature = "pg13", feature = "pg14", feature = "pg15", feature = "pg16"))]
+        {
+            pg_sys::AccessExclusiveLock as pg_sys::LOCKMODE
+        },
+    );
+    pg_sys::add_string_reloption(
+        RELOPT_KIND_PDB,
+        "merge_policy".as_pg_cstr(),
+        "JSON object configuring how the index's segments are merged".as_pg_cstr(),
+        std::ptr::null(),
+        Some(validate_merge_policy),
+        #[cfg(any(feature = "pg13", feature = "pg14", feature = "pg15", feature = "pg16"))]
+        {
+            pg_sys::AccessExclusiveLock as pg_sys::LOCKMODE
+        },
+    );
+    // Zero leaves the writer settings to their server-wide defaults.
+    pg_sys::add_int_reloption(
+        RELOPT_KIND_PDB,
+        "writer_memory_budget".as_pg_cstr(),
+        "Memory budget of the index's writer, in megabytes".as_pg_cstr(),
+        0,
+        0,
+        32_000,
+        #[cfg(any(feature = "pg13", feature = "pg14", feature = "pg15", feature = "pg16"))]
+        {
+            pg_sys::AccessExclusiveLock as pg_sys::LOCKMODE
+        },
+    );
+    pg_sys::add_int_reloption(
+        RELOPT_KIND_PDB,
+        "writer_threads".as_pg_cstr(),
+        "Number of threads that the index's writer indexes documents with".as_pg_cstr(),
+        0,
+        0,
+        8,
+        #[cfg(any(feature = "pg13", feature = "pg14", feature = "pg15", feature = "pg16"))]
+        {
+            pg_sys::AccessExclusiveLock as pg_sys::LOCKMODE
+        },
+    );
 }
@@ -0,0 +1,134 @@
+use crate::{
+    env::postgres_data_dir_path,
+    globals::WriterGlobal,
+    writer::{WriterClient, WriterDirectory, WriterRequest},
+};
+use pgrx::*;
+use std::ffi::CStr;
+
+static mut PREPARED_TRANSACTION_HOOK: PreparedTransactionHook = PreparedTransactionHook;
+
+/// Install a hook that resolves the bm25 documents of a prepared transaction. They're committed
+/// to the index and journaled when the transaction is prepared, so COMMIT PREPARED only has to
+/// clear the journal, and ROLLBACK PREPARED removes the journaled documents from the index.
+pub fn init() {
+    unsafe { register_hook(&mut PREPARED_TRANSACTION_HOOK) }
+}
+
+struct PreparedTransactionHook;
+
+impl hooks::PgHooks for PreparedTransactionHook {
+    fn process_utility_hook(
+        &mut self,
+        pstmt: PgBox<pg_sys::PlannedStmt>,
+        query_string: &CStr,
+        read_only_tree: Option<bool>,
+        context: pg_sys::ProcessUtilityContext,
+        params: PgBox<pg_sys::ParamListInfoData>,
+        query_env: PgBox<pg_sys::QueryEnvironment>,
+        dest: PgBox<pg_sys::DestReceiver>,
+        completion_tag: *mut pg_sys::QueryCompletion,
+        prev_hook: fn(
+            pstmt: PgBox<pg_sys::PlannedStmt>,
+            query_string: &CStr,
+            read_only_tree: Option<bool>,
+            context: pg_sys::ProcessUtilityContext,
+            params: PgBox<pg_sys::ParamListInfoData>,
+            query_env: PgBox<pg_sys::QueryEnvironment>,
+            dest: PgBox<pg_sys::DestReceiver>,
+            completion_tag: *mut pg_sys::QueryCompletion,
+        ) -> HookResult<()>,
+    ) -> HookResult<()> {
+        // The transaction's xid has to be looked up before it's resolved, when it's gone.
+        let resolved = unsafe { prepared_transaction(&pstmt) };
+
+        prev_hook(
+            pstmt,
+            query_string,
+            read_only_tree,
+            context,
+            params,
+            query_env,
+            dest,
+            completion_tag,
+        );
+
+        if let Some((xid, committed)) = resolved {
+            resolve_prepared_transaction(xid, committed);
+        }
+
+        HookResult::new(())
+    }
+}
+
+/// The xid of the prepared transaction resolved by a COMMIT PREPARED or ROLLBACK PREPARED
+/// statement, and whether it's committed.
+unsafe fn prepared_transaction(pstmt: &PgBox<pg_sys::PlannedStmt>) -> Option<(u32, bool)> {
+    let utility_stmt = pstmt.utilityStmt;
+    if utility_stmt.is_null() || (*utility_stmt).type_ != pg_sys::NodeTag::T_TransactionStmt {
+        return None;
+    }
+
+    let transaction_stmt = utility_stmt as *mut pg_sys::TransactionStmt;
+    let committed = match (*transaction_stmt).kind {
+        pg_sys::TransactionStmtKind_TRANS_STMT_COMMIT_PREPARED => true,
+        pg_sys::TransactionStmtKind_TRANS_STMT_ROLLBACK_PREPARED => false,
+        _ => return None,
+    };
+
+    let gid = CStr::from_ptr((*transaction_stmt).gid).to_string_lossy();
+    // If the gid doesn't exist, the statement itself raises the error.
+    let xid = Spi::get_one_with_args::<i64>(
+        "SELECT transaction::text::bigint FROM pg_prepared_xacts WHERE gid = $1",
+        vec![(PgBuiltInOids::TEXTOID.oid(), gid.as_ref().into_datum())],
+    )
+    .unwrap_or_else(|err| panic!("could not look up prepared transaction {gid}: {err}"))?;
+
+    Some((xid as u32, committed))
+}
+
+/// Clear or roll back the journaled documents of a prepared transaction in every bm25 index.
+/// The transaction is already resolved, so failures are only reported. Anything left over is
+/// resolved when the writer next starts.
+fn resolve_prepared_transaction(xid: u32, committed: bool) {
+    let directories = match WriterDirectory::all(&postgres_data_dir_path()) {
+        Ok(directories) => directories,
+        Err(err) => {
+            warning!("could not list bm25 indexes to resolve prepared transaction {xid}: {err}");
+            return;
+        }
+    };
+
+    let writer = WriterGlobal::client();
+    for directory in directories {
+        match directory.journal_entry(xid) {
+            Ok(None) => continue,
+            Ok(Some(_)) => {}
+            Err(err) => {
+                warning!("could not read journal of bm25 index {directory:?}: {err}");
+                continue;
+            }
+        }
+
+        let request = if committed {
+            WriterRequest::ClearJournal {
+                directory: directory.clone(),
+                xid,
+            }
+        } else {
+            WriterRequest::RollbackPrepared {
+                directory: directory.clone(),
+                xid,
+            }
+        };
+        let result = match writer.lock() {
+            Err(err) => Err(err.to_string()),
+            Ok(mut client) => client.request(request).map_err(|err| err.to_string()),
+        };
+        if let Err(err) = result {
+            warning!(
+                "could not resolve prepared transaction {xid} in bm25 index {directory:?}: {err}"
+            );
+        }
+    }
+}
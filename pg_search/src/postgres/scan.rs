@@ -1,9 +1,9 @@
-use crate::env::needs_commit;
-use crate::index::state::SearchStateManager;
+use crate::index::state::{SearchResults, SearchStateManager};
+use crate::index::SearchIndex;
+use crate::postgres::utils::search_states;
+use crate::query::SearchQueryInput;
 use crate::schema::SearchConfig;
-use crate::{globals::WriterGlobal, postgres::utils::get_search_index};
 use pgrx::*;
-use tantivy::{DocAddress, Score};
 
 #[pg_guard]
 pub extern "C" fn ambeginscan(
@@ -17,17 +17,24 @@ pub extern "C" fn ambeginscan(
     scandesc.into_pg()
 }
 
+/// The results of a scan, in descending score order, or `None` if its keys can't match
+/// anything. Results are collected in batches as they're reached, so that an ordered scan
+/// under a LIMIT only collects the top documents it returns.
+type SearchResultIter = Option<SearchResults<'static>>;
+
 // An annotation to guard the function for PostgreSQL's threading model.
 #[pg_guard]
 pub extern "C" fn amrescan(
     scan: pg_sys::IndexScanDesc,
     keys: pg_sys::ScanKey,
     nkeys: ::std::os::raw::c_int,
-    _orderbys: pg_sys::ScanKey,
-    _norderbys: ::std::os::raw::c_int,
+    orderbys: pg_sys::ScanKey,
+    norderbys: ::std::os::raw::c_int,
 ) {
-    // Ensure there's at least one key provided for the search.
-    if nkeys == 0 {
+    // Ensure there's at least one key provided for the search. A scan ordered by
+    // the `<=>` operator can get its search config from the ORDER BY key alone, and
+    // then returns every row of the index.
+    if nkeys == 0 && norderbys == 0 {
         panic!("no ScanKeys provided");
     }
 
@@ -35,39 +42,183 @@ pub extern "C" fn amrescan(
     // pointed to by the raw pointer in a safe way.
     let mut scan: PgBox<pg_sys::IndexScanDescData> = unsafe { PgBox::from_pg(scan) };
 
-    // Convert the raw keys into a slice for easier access.
-    let nkeys = nkeys as usize;
-    let keys = unsafe { std::slice::from_raw_parts(keys as *const pg_sys::ScanKeyData, nkeys) };
-
-    // Convert the first scan key argument into a byte array. This is assumed to be the `::jsonb` search config.
-    let config_jsonb = unsafe {
-        JsonB::from_datum(keys[0].sk_argument, false)
-            .expect("failed to convert query to tuple of strings")
+    // Convert the raw keys into slices for easier access.
+    let keys =
+        unsafe { std::slice::from_raw_parts(keys as *const pg_sys::ScanKeyData, nkeys as usize) };
+    let orderbys = unsafe {
+        std::slice::from_raw_parts(orderbys as *const pg_sys::ScanKeyData, norderbys as usize)
     };
 
-    let search_config =
-        SearchConfig::from_jsonb(config_jsonb).expect("could not parse search config");
-    let index_name = &search_config.index_name;
-
-    // Create the index and scan state
-    let search_index = get_search_index(index_name);
-    let writer_client = WriterGlobal::client();
-    let state = search_index
-        .search_state(&writer_client, &search_config, needs_commit())
-        .unwrap();
+    // The scan keys are NULL or empty arrays, so there's no need to search at all.
+    let Some(search_config) = search_config_from_keys(keys, orderbys) else {
+        let top_docs: SearchResultIter = None;
+        scan.opaque = PgMemoryContexts::CurrentMemoryContext.leak_and_drop_on_delete(top_docs)
+            as void_mut_ptr;
+        scan.into_pg();
+        return;
+    };
+    // Create the scan state. A search through the index on a partitioned table scans the
+    // index on each partition, and each scan reads the state of its own partition.
+    let mut states = search_states(&search_config);
+    let state = if states.len() == 1 {
+        states.remove(0)
+    } else {
+        let index_relation = unsafe { PgRelation::from_pg(scan.indexRelation) };
+        states
+            .into_iter()
+            .find(|state| state.directory.index_oid == index_relation.oid().as_u32())
+            .unwrap_or_else(|| {
+                panic!(
+                    "index '{}' is not a partition of index '{}'",
+                    index_relation.name(),
+                    search_config.index_name
+                )
+            })
+    };
 
-    let top_docs = state.search(search_index.executor);
+    let top_docs: SearchResultIter = Some(state.search_lazy(SearchIndex::executor()));
 
     SearchStateManager::set_state(state.clone()).expect("could not store search state in manager");
 
+    // The index returns exact scores in order, so Postgres doesn't need to recompute them.
+    scan.xs_recheckorderby = false;
+
     // Save the iterator onto the current memory context.
-    scan.opaque = PgMemoryContexts::CurrentMemoryContext
-        .leak_and_drop_on_delete(top_docs.into_iter()) as void_mut_ptr;
+    scan.opaque =
+        PgMemoryContexts::CurrentMemoryContext.leak_and_drop_on_delete(top_docs) as void_mut_ptr;
 
     // Return scan state back management to Postgres.
     scan.into_pg();
 }
 
+/// Combines the scan keys of a scan into the config of a single search. Every `@@@` key must
+/// match, so their queries become the `must` clauses of a boolean query. A key for
+/// `@@@ ANY(array)` matches if any of the configs in its array does.
+///
+/// When the scan is ordered by `<=>`, the index returns results in the order of the ORDER BY
+/// query's score. The `@@@` queries that differ from it then only filter results, and don't
+/// contribute to the score. If the ORDER BY query is also one of the `@@@` queries, it must
+/// match like the others, and otherwise it only scores the results. A scan that's only
+/// ordered by `<=>`, without any `@@@` key, returns the rows that don't match too.
+///
+/// Returns `None` if the keys can't match anything, because one of them is NULL or they only
+/// hold empty arrays.
+fn search_config_from_keys(
+    keys: &[pg_sys::ScanKeyData],
+    orderbys: &[pg_sys::ScanKeyData],
+) -> Option<SearchConfig> {
+    let mut configs: Vec<SearchConfig> = vec![];
+    let mut queries: Vec<SearchQueryInput> = vec![];
+
+    for key in keys {
+        if key.sk_flags & pg_sys::SK_ISNULL as i32 != 0 {
+            // The @@@ operator is strict, so a NULL search config matches nothing.
+            return None;
+        } else if key.sk_flags & pg_sys::SK_SEARCHARRAY as i32 != 0 {
+            let array = unsafe {
+                Array::<JsonB>::from_datum(key.sk_argument, false)
+                    .expect("failed to convert search config array")
+            };
+            let array_configs: Vec<_> = array
+                .iter()
+                .flatten()
+                .map(|config_jsonb| {
+                    SearchConfig::from_jsonb(config_jsonb).expect("could not parse search config")
+                })
+                .collect();
+            queries.push(SearchQueryInput::Boolean {
+                must: vec![],
+                should: array_configs
+                    .iter()
+                    .map(|config| config.query.clone())
+                    .collect(),
+                must_not: vec![],
+            });
+            configs.extend(array_configs);
+        } else {
+            let config = search_config_from_datum(key.sk_argument);
+            queries.push(config.query.clone());
+            configs.push(config);
+        }
+    }
+
+    let order_config = orderbys.first().map(|orderby| {
+        if orderby.sk_flags & pg_sys::SK_ISNULL as i32 != 0 {
+            panic!("the search config of the <=> operator must not be NULL");
+        }
+        search_config_from_datum(orderby.sk_argument)
+    });
+
+    // The ORDER BY config, or else the first @@@ config, decides the options of the search,
+    // like its limit or alias.
+    let mut search_config = order_config.clone().or_else(|| configs.first().cloned())?;
+    if let Some(config) = configs
+        .iter()
+        .find(|config| config.index_name != search_config.index_name)
+    {
+        panic!(
+            "all search configs of a scan must use the same index, found '{}' and '{}'",
+            search_config.index_name, config.index_name
+        );
+    }
+
+    search_config.query = match order_config {
+        // Without a `@@@` key the scan returns every row, so the rows that don't match the
+        // ORDER BY query come last with a score of zero, like the NULL that `<=>` gives them.
+        Some(order_config) if queries.is_empty() => SearchQueryInput::Boolean {
+            must: vec![SearchQueryInput::ConstScore {
+                query: Box::new(SearchQueryInput::All),
+                score: 0.0,
+            }],
+            should: vec![order_config.query],
+            must_not: vec![],
+        },
+        Some(order_config) => {
+            let is_qual = queries.contains(&order_config.query);
+            let mut must: Vec<_> = queries
+                .into_iter()
+                .filter(|query| *query != order_config.query)
+                .map(|query| SearchQueryInput::ConstScore {
+                    query: Box::new(query),
+                    score: 0.0,
+                })
+                .collect();
+            if must.is_empty() {
+                order_config.query
+            } else if is_qual {
+                must.push(order_config.query);
+                SearchQueryInput::Boolean {
+                    must,
+                    should: vec![],
+                    must_not: vec![],
+                }
+            } else {
+                SearchQueryInput::Boolean {
+                    must,
+                    should: vec![order_config.query],
+                    must_not: vec![],
+                }
+            }
+        }
+        None if queries.len() == 1 => queries.remove(0),
+        None => SearchQueryInput::Boolean {
+            must: queries,
+            should: vec![],
+            must_not: vec![],
+        },
+    };
+
+    Some(search_config)
+}
+
+fn search_config_from_datum(datum: pg_sys::Datum) -> SearchConfig {
+    // The scan key argument is assumed to be the `::jsonb` search config.
+    let config_jsonb = unsafe {
+        JsonB::from_datum(datum, false).expect("failed to convert query to tuple of strings")
+    };
+    SearchConfig::from_jsonb(config_jsonb).expect("could not parse search config")
+}
+
 #[pg_guard]
 pub extern "C" fn amendscan(_scan: pg_sys::IndexScanDesc) {}
 
@@ -78,13 +229,12 @@ pub extern "C" fn amgettuple(
 ) -> bool {
     let mut scan: PgBox<pg_sys::IndexScanDescData> = unsafe { PgBox::from_pg(scan) };
     let iter =
-        unsafe { (scan.opaque as *mut std::vec::IntoIter<(Score, DocAddress, i64, u64)>).as_mut() }
-            .expect("no scandesc state");
+        unsafe { (scan.opaque as *mut SearchResultIter).as_mut() }.expect("no scandesc state");
 
     scan.xs_recheck = false;
 
-    match iter.next() {
-        Some((_, _, _, ctid)) => {
+    match iter.as_mut().and_then(|iter| iter.next()) {
+        Some((score, _, _, ctid)) => {
             #[cfg(any(
                 feature = "pg12",
                 feature = "pg13",
@@ -95,8 +245,41 @@ pub extern "C" fn amgettuple(
             let tid = &mut scan.xs_heaptid;
             u64_to_item_pointer(ctid, tid);
 
+            // The distance of the `<=>` operator, which orders the scan, is the negated score.
+            if scan.numberOfOrderBys > 0 {
+                unsafe {
+                    *scan.xs_orderbyvals = (-score).into_datum().expect("could not convert score");
+                    *scan.xs_orderbynulls = false;
+                }
+            }
+
             true
         }
         None => false,
     }
 }
+
+#[pg_guard]
+pub extern "C" fn amgetbitmap(scan: pg_sys::IndexScanDesc, tbm: *mut pg_sys::TIDBitmap) -> i64 {
+    let scan: PgBox<pg_sys::IndexScanDescData> = unsafe { PgBox::from_pg(scan) };
+    let iter =
+        unsafe { (scan.opaque as *mut SearchResultIter).as_mut() }.expect("no scandesc state");
+
+    let Some(iter) = iter else {
+        return 0;
+    };
+    // A bitmap scan reads every result, so there's no use collecting them in batches.
+    iter.collect_rest();
+
+    let mut ntids = 0;
+    for (_, _, _, ctid) in iter {
+        let mut tid = pg_sys::ItemPointerData::default();
+        u64_to_item_pointer(ctid, &mut tid);
+
+        // Matches come straight from the index, so the heap tuples don't need to be rechecked.
+        unsafe { pg_sys::tbm_add_tuples(tbm, &mut tid, 1, false) };
+        ntids += 1;
+    }
+
+    ntids
+}
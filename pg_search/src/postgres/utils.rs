@@ -1,18 +1,171 @@
+use crate::env::{current_xid, needs_commit};
+use crate::globals::WriterGlobal;
+use crate::index::state::SearchState;
 use crate::index::SearchIndex;
-use crate::schema::{SearchDocument, SearchIndexSchema};
+use crate::postgres::options::SearchIndexCreateOptions;
+use crate::schema::{
+    SearchConfig, SearchDocument, SearchField, SearchFieldName, SearchIndexSchema,
+};
 use crate::writer::{IndexError, WriterDirectory};
 use pgrx::{
-    pg_sys, varsize, Array, FromDatum, JsonB, JsonString, PgBuiltInOids, PgOid, PgRelation,
-    PgTupleDesc,
+    pg_sys, varsize, Array, FromDatum, IntoDatum, JsonB, JsonString, PgBox, PgBuiltInOids, PgOid,
+    PgRelation, PgTupleDesc, Spi,
 };
 use serde_json::Map;
+use std::collections::HashSet;
+use tantivy::{DocAddress, Score};
 
-pub fn get_search_index(index_name: &str) -> &'static mut SearchIndex {
-    let directory = WriterDirectory::from_index_name(index_name);
+pub fn get_search_index(index_oid: pg_sys::Oid) -> &'static mut SearchIndex {
+    let directory = WriterDirectory::from_index_oid(index_oid.as_u32());
     SearchIndex::from_cache(&directory)
         .unwrap_or_else(|err| panic!("error loading index from directory: {err}"))
 }
 
+/// The oid of the bm25 index with the given name. Searches name their index, but its data is
+/// stored under its oid, so the name is resolved on every search. This is what lets a renamed
+/// index, or the index rebuilt by REINDEX CONCURRENTLY, be found under its current name.
+///
+/// The name may be qualified with the index's schema, and is otherwise resolved through the
+/// search_path like any other relation name. Searches often name an index outside of the
+/// search_path, so an unqualified name that isn't found there is looked up in every schema,
+/// and must then belong to a single bm25 index.
+pub fn lookup_index_oid(index_name: &str) -> pg_sys::Oid {
+    let index_oids = Spi::get_one_with_args::<Vec<pg_sys::Oid>>(
+        "SELECT ARRAY(
+            SELECT pg_class.oid FROM pg_class
+            JOIN pg_am ON pg_am.oid = pg_class.relam
+            WHERE relkind = 'i' AND amname = 'bm25' AND CASE
+                WHEN EXISTS (
+                    SELECT FROM pg_class resolved
+                    JOIN pg_am resolved_am ON resolved_am.oid = resolved.relam
+                    WHERE resolved.oid = to_regclass($1) AND resolved_am.amname = 'bm25'
+                ) THEN pg_class.oid = to_regclass($1)
+                ELSE relname = $1
+            END
+        )",
+        vec![(PgBuiltInOids::TEXTOID.oid(), index_name.into_datum())],
+    )
+    .unwrap_or_else(|err| panic!("could not look up index {index_name}: {err}"))
+    .unwrap_or_default();
+
+    match index_oids.as_slice() {
+        [index_oid] => *index_oid,
+        [] => panic!("bm25 index {index_name} does not exist"),
+        _ => panic!(
+            "bm25 index name {index_name} is ambiguous, qualify it with the schema of the index"
+        ),
+    }
+}
+
+/// The oids of the bm25 indexes on the partitions of a partitioned table, if the named index
+/// is the index of a partitioned table. Postgres can't create an index with a whole-row
+/// expression on a partitioned table, so each partition has its own bm25 index instead, whose
+/// `partition_of` option names the index that they're searched through.
+pub fn lookup_partition_index_oids(index_name: &str) -> Option<Vec<pg_sys::Oid>> {
+    let partition_index_oids = Spi::get_one_with_args::<Vec<pg_sys::Oid>>(
+        "SELECT ARRAY(
+            SELECT oid FROM pg_class
+            WHERE relkind = 'i' AND reloptions @> ARRAY['partition_of=' || $1]
+            ORDER BY relname
+        )",
+        vec![(PgBuiltInOids::TEXTOID.oid(), index_name.into_datum())],
+    )
+    .unwrap_or_else(|err| panic!("could not look up partitions of index {index_name}: {err}"))
+    .unwrap_or_default();
+
+    if partition_index_oids.is_empty() {
+        None
+    } else {
+        Some(partition_index_oids)
+    }
+}
+
+/// The indexes that a search through the named index reads: the indexes on the partitions
+/// of a partitioned table, or else the index itself.
+pub fn get_search_indexes(index_name: &str) -> Vec<&'static mut SearchIndex> {
+    match lookup_partition_index_oids(index_name) {
+        Some(partition_index_oids) => partition_index_oids
+            .into_iter()
+            .map(get_search_index)
+            .collect(),
+        None => vec![get_search_index(lookup_index_oid(index_name))],
+    }
+}
+
+/// The transaction to commit the changes of before searching, if it has made any.
+fn commit_xid() -> Option<u32> {
+    needs_commit().then(current_xid)
+}
+
+/// The state of each index that a search reads, ready to search. The states of the partitions
+/// of a partitioned index score documents with statistics summed over all the partitions, and
+/// return only their results that are within the search's offset and limit once the results
+/// of all the partitions are merged by score.
+pub fn search_states(config: &SearchConfig) -> Vec<SearchState> {
+    let writer_client = WriterGlobal::client();
+    let Some(partition_index_oids) = lookup_partition_index_oids(&config.index_name) else {
+        let search_index = get_search_index(lookup_index_oid(&config.index_name));
+        let state = search_index
+            .search_state(&writer_client, config, commit_xid())
+            .unwrap();
+        return vec![state];
+    };
+
+    let partition_config = SearchConfig {
+        limit_rows: config
+            .limit_rows
+            .map(|limit| limit + config.offset_rows.unwrap_or(0)),
+        offset_rows: None,
+        ..config.clone()
+    };
+    let states: Vec<_> = partition_index_oids
+        .into_iter()
+        .map(|partition_index_oid| {
+            get_search_index(partition_index_oid)
+                .search_state(&writer_client, &partition_config, commit_xid())
+                .unwrap()
+        })
+        .collect();
+
+    let states: Vec<_> = states
+        .iter()
+        .map(|state| state.clone().with_partition_statistics(&states))
+        .collect();
+
+    if config.limit_rows.is_none() && config.offset_rows.is_none() {
+        return states;
+    }
+
+    // Each partition's search is limited by the limit and offset combined, so the results that
+    // are within the offset and limit overall are among them. Ties are broken by key, which the
+    // results only have when they're sorted stably, and otherwise by partition.
+    let mut results: Vec<(Score, Option<i64>, usize, DocAddress)> = vec![];
+    for (partition, state) in states.iter().enumerate() {
+        results.extend(
+            state
+                .top_docs(SearchIndex::executor())
+                .into_iter()
+                .map(|(score, doc_address, key)| (score, key, partition, doc_address)),
+        );
+    }
+    results.sort_by(|a, b| b.0.total_cmp(&a.0).then(a.1.cmp(&b.1)).then(a.2.cmp(&b.2)));
+
+    let mut windows = vec![HashSet::new(); states.len()];
+    for (_, _, partition, doc_address) in results
+        .into_iter()
+        .skip(config.offset_rows.unwrap_or(0))
+        .take(config.limit_rows.unwrap_or(usize::MAX))
+    {
+        windows[partition].insert(doc_address);
+    }
+
+    states
+        .into_iter()
+        .zip(windows)
+        .map(|(state, window)| state.with_partition_window(window))
+        .collect()
+}
+
 pub fn lookup_index_tupdesc(indexrel: &PgRelation) -> PgTupleDesc<'static> {
     let tupdesc = indexrel.tuple_desc();
 
@@ -31,12 +184,55 @@ pub fn lookup_index_tupdesc(indexrel: &PgRelation) -> PgTupleDesc<'static> {
     unsafe { PgTupleDesc::from_pg_is_copy(pg_sys::lookup_rowtype_tupdesc_copy(typid, typmod)) }
 }
 
+/// An expression column of a bm25 index, like `lower(description)`, along with the name of
+/// the field that its values are indexed as.
+pub struct IndexExpression {
+    pub name: SearchFieldName,
+    pub type_oid: PgOid,
+}
+
+/// Looks up the expression columns of a bm25 index. The first column of the index is always
+/// the indexed row, so the expression columns are the ones that follow it, and they're named
+/// by the `expression_fields` option in the same order.
+pub fn lookup_index_expressions(indexrel: &PgRelation) -> Vec<IndexExpression> {
+    if indexrel.rd_options.is_null() {
+        return vec![];
+    }
+
+    let rdopts: PgBox<SearchIndexCreateOptions> =
+        unsafe { PgBox::from_pg(indexrel.rd_options as *mut SearchIndexCreateOptions) };
+    let expression_fields = rdopts.get_expression_fields();
+    let tupdesc = indexrel.tuple_desc();
+
+    if expression_fields.len() != tupdesc.len() - 1 {
+        panic!(
+            "bm25 index has {} expression columns, but {} expression fields",
+            tupdesc.len() - 1,
+            expression_fields.len()
+        );
+    }
+
+    expression_fields
+        .into_iter()
+        .zip(tupdesc.iter().skip(1))
+        .map(|(name, attribute)| IndexExpression {
+            name,
+            type_oid: attribute.type_oid(),
+        })
+        .collect()
+}
+
 pub unsafe fn row_to_search_document(
     tupdesc: &PgTupleDesc,
     values: *mut pg_sys::Datum,
+    isnull: *mut bool,
+    expressions: &[IndexExpression],
     schema: &SearchIndexSchema,
 ) -> Result<SearchDocument, IndexError> {
-    let row = std::slice::from_raw_parts(values, 1)[0];
+    let values = std::slice::from_raw_parts(values, expressions.len() + 1);
+    let isnull = std::slice::from_raw_parts(isnull, expressions.len() + 1);
+
+    let row = values[0];
     let td =
         pg_sys::pg_detoast_datum(row.cast_mut_ptr::<pg_sys::varlena>()) as pg_sys::HeapTupleHeader;
 
@@ -83,84 +279,115 @@ pub unsafe fn row_to_search_document(
             continue;
         };
 
-        let array_type = unsafe { pg_sys::get_element_type(attribute_type_oid.value()) };
-        let (base_oid, is_array) = if array_type != pg_sys::InvalidOid {
-            (PgOid::from(array_type), true)
+        let datum = datums[attno - dropped];
+        insert_datum(&mut document, search_field, attribute_type_oid, datum)?;
+    }
+
+    // The expression columns follow the row, and are indexed under their own field names.
+    for (i, expression) in expressions.iter().enumerate() {
+        if isnull[i + 1] {
+            continue;
+        }
+
+        let search_field = if let Some(index_field) = schema.get_search_field(&expression.name) {
+            index_field
         } else {
-            (attribute_type_oid, false)
+            continue;
         };
 
-        let datum = datums[attno - dropped];
+        insert_datum(
+            &mut document,
+            search_field,
+            expression.type_oid,
+            values[i + 1],
+        )?;
+    }
 
-        match &base_oid {
-            PgOid::BuiltIn(builtin) => match builtin {
-                PgBuiltInOids::BOOLOID => {
-                    let value = bool::from_datum(datum, false).ok_or(IndexError::DatumDeref)?;
-                    document.insert(search_field.id, value.into());
-                }
-                PgBuiltInOids::INT2OID => {
-                    let value = i16::from_datum(datum, false).ok_or(IndexError::DatumDeref)?;
-                    document.insert(search_field.id, (value as i64).into());
-                }
-                PgBuiltInOids::INT4OID => {
-                    let value = i32::from_datum(datum, false).ok_or(IndexError::DatumDeref)?;
-                    document.insert(search_field.id, (value as i64).into());
-                }
-                PgBuiltInOids::INT8OID => {
-                    let value = i64::from_datum(datum, false).ok_or(IndexError::DatumDeref)?;
-                    document.insert(search_field.id, value.into());
-                }
-                PgBuiltInOids::OIDOID => {
-                    let value = u32::from_datum(datum, false).ok_or(IndexError::DatumDeref)?;
-                    document.insert(search_field.id, (value as u64).into());
-                }
-                PgBuiltInOids::FLOAT4OID => {
-                    let value = f32::from_datum(datum, false).ok_or(IndexError::DatumDeref)?;
-                    document.insert(search_field.id, (value as f64).into());
-                }
-                PgBuiltInOids::FLOAT8OID => {
-                    let value = f64::from_datum(datum, false).ok_or(IndexError::DatumDeref)?;
-                    document.insert(search_field.id, value.into());
-                }
-                PgBuiltInOids::TEXTOID | PgBuiltInOids::VARCHAROID => {
-                    if is_array {
-                        let array: Array<pg_sys::Datum> =
-                            Array::from_datum(datum, false).ok_or(IndexError::DatumDeref)?;
-                        for element_datum in array.iter().flatten() {
-                            let value = String::from_datum(element_datum, false)
-                                .ok_or(IndexError::DatumDeref)?;
-                            document.insert(search_field.id, value.into())
-                        }
-                    } else {
-                        let value =
-                            String::from_datum(datum, false).ok_or(IndexError::DatumDeref)?;
+    Ok(document)
+}
+
+/// Converts a datum of the given type into a value of the search field, and adds it to the
+/// document. Arrays of text are added as multiple values of the field.
+unsafe fn insert_datum(
+    document: &mut SearchDocument,
+    search_field: &SearchField,
+    type_oid: PgOid,
+    datum: pg_sys::Datum,
+) -> Result<(), IndexError> {
+    let array_type = pg_sys::get_element_type(type_oid.value());
+    let (base_oid, is_array) = if array_type != pg_sys::InvalidOid {
+        (PgOid::from(array_type), true)
+    } else {
+        (type_oid, false)
+    };
+
+    match &base_oid {
+        PgOid::BuiltIn(builtin) => match builtin {
+            PgBuiltInOids::BOOLOID => {
+                let value = bool::from_datum(datum, false).ok_or(IndexError::DatumDeref)?;
+                document.insert(search_field.id, value.into());
+            }
+            PgBuiltInOids::INT2OID => {
+                let value = i16::from_datum(datum, false).ok_or(IndexError::DatumDeref)?;
+                document.insert(search_field.id, (value as i64).into());
+            }
+            PgBuiltInOids::INT4OID => {
+                let value = i32::from_datum(datum, false).ok_or(IndexError::DatumDeref)?;
+                document.insert(search_field.id, (value as i64).into());
+            }
+            PgBuiltInOids::INT8OID => {
+                let value = i64::from_datum(datum, false).ok_or(IndexError::DatumDeref)?;
+                document.insert(search_field.id, value.into());
+            }
+            PgBuiltInOids::OIDOID => {
+                let value = u32::from_datum(datum, false).ok_or(IndexError::DatumDeref)?;
+                document.insert(search_field.id, (value as u64).into());
+            }
+            PgBuiltInOids::FLOAT4OID => {
+                let value = f32::from_datum(datum, false).ok_or(IndexError::DatumDeref)?;
+                document.insert(search_field.id, (value as f64).into());
+            }
+            PgBuiltInOids::FLOAT8OID => {
+                let value = f64::from_datum(datum, false).ok_or(IndexError::DatumDeref)?;
+                document.insert(search_field.id, value.into());
+            }
+            PgBuiltInOids::TEXTOID | PgBuiltInOids::VARCHAROID => {
+                if is_array {
+                    let array: Array<pg_sys::Datum> =
+                        Array::from_datum(datum, false).ok_or(IndexError::DatumDeref)?;
+                    for element_datum in array.iter().flatten() {
+                        let value = String::from_datum(element_datum, false)
+                            .ok_or(IndexError::DatumDeref)?;
                         document.insert(search_field.id, value.into())
                     }
+                } else {
+                    let value = String::from_datum(datum, false).ok_or(IndexError::DatumDeref)?;
+                    document.insert(search_field.id, value.into())
                 }
-                PgBuiltInOids::JSONOID => {
-                    let JsonString(value) =
-                        JsonString::from_datum(datum, false).ok_or(IndexError::DatumDeref)?;
-                    document.insert(
-                        search_field.id,
-                        serde_json::from_str::<Map<String, serde_json::Value>>(&value)?.into(),
-                    );
-                }
-                PgBuiltInOids::JSONBOID => {
-                    let JsonB(serde_value) =
-                        JsonB::from_datum(datum, false).ok_or(IndexError::DatumDeref)?;
-                    let value = serde_json::to_vec(&serde_value)?;
-                    document.insert(
-                        search_field.id,
-                        serde_json::from_slice::<Map<String, serde_json::Value>>(&value)?.into(),
-                    );
-                }
-                unsupported => Err(IndexError::UnsupportedValue(
-                    search_field.name.0.to_string(),
-                    format!("{unsupported:?}"),
-                ))?,
-            },
-            _ => Err(IndexError::InvalidOid(search_field.name.0.to_string()))?,
-        }
+            }
+            PgBuiltInOids::JSONOID => {
+                let JsonString(value) =
+                    JsonString::from_datum(datum, false).ok_or(IndexError::DatumDeref)?;
+                document.insert(
+                    search_field.id,
+                    serde_json::from_str::<Map<String, serde_json::Value>>(&value)?.into(),
+                );
+            }
+            PgBuiltInOids::JSONBOID => {
+                let JsonB(serde_value) =
+                    JsonB::from_datum(datum, false).ok_or(IndexError::DatumDeref)?;
+                let value = serde_json::to_vec(&serde_value)?;
+                document.insert(
+                    search_field.id,
+                    serde_json::from_slice::<Map<String, serde_json::Value>>(&value)?.into(),
+                );
+            }
+            unsupported => Err(IndexError::UnsupportedValue(
+                search_field.name.0.to_string(),
+                format!("{unsupported:?}"),
+            ))?,
+        },
+        _ => Err(IndexError::InvalidOid(search_field.name.0.to_string()))?,
     }
-    Ok(document)
+    Ok(())
 }
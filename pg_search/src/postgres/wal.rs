@@ -0,0 +1,471 @@
+//! Write-ahead logging of bm25 index changes, so that they reach streaming replicas.
+//!
+//! Tantivy's files aren't written through Postgres' buffer manager, so nothing about them is
+//! in the write-ahead log. Instead, the requests that a backend sends to the writer process are
+//! logged as records of a custom resource manager, and a standby replays them on a writer of
+//! its own as it replays the rest of the log. Custom resource managers need Postgres 15 or
+//! newer, and on older versions nothing is logged.
+//!
+//! A primary that recovers from a crash replays the records too, from its last checkpoint on.
+//! Replay brings an index to where the writer process had it, and no further: the documents
+//! of a logged commit are committed and journaled under their transaction again, and those
+//! that weren't committed are discarded when recovery ends. An index that was created after
+//! the checkpoint is created anew, along with its journal, from records that all follow.
+//! Either way, the transactions that were in flight are then resolved from the journal by the
+//! writer process when it starts, as they are without any log.
+
+use crate::{
+    index::SearchIndexSettings,
+    schema::{SearchFieldConfig, SearchFieldName},
+    writer::{ClientError, WriterClient, WriterDirectory, WriterRequest},
+};
+use std::path::Path;
+
+/// A writer client that logs each request it sends to the write-ahead log.
+pub struct WalClient<W> {
+    client: W,
+}
+
+impl<W> WalClient<W> {
+    pub fn new(client: W) -> Self {
+        Self { client }
+    }
+}
+
+impl<W: WriterClient<WriterRequest>> WriterClient<WriterRequest> for WalClient<W> {
+    fn request(&mut self, request: WriterRequest) -> Result<(), ClientError> {
+        // Only requests that the writer has carried out are logged.
+        let record = bincode::serialize(&request)?;
+        self.client.request(request)?;
+        log_record(XLOG_BM25_REQUEST, &record);
+        Ok(())
+    }
+
+    fn transfer<P: AsRef<Path>>(
+        &mut self,
+        pipe_path: P,
+        request: WriterRequest,
+    ) -> Result<(), ClientError> {
+        let record = bincode::serialize(&request)?;
+        self.client.transfer(pipe_path, request)?;
+        log_record(XLOG_BM25_REQUEST, &record);
+        Ok(())
+    }
+}
+
+/// Log a request that was sent to the writer without a client, like the inserts that the
+/// participants of a parallel build write to their pipes.
+pub fn log_request(request: &WriterRequest) -> Result<(), ClientError> {
+    log_record(XLOG_BM25_REQUEST, &bincode::serialize(request)?);
+    Ok(())
+}
+
+/// Log the creation of an index, which replaces any index in its directory.
+pub fn log_create(
+    directory: &WriterDirectory,
+    fields: &[(SearchFieldName, SearchFieldConfig)],
+    settings: &SearchIndexSettings,
+) -> Result<(), ClientError> {
+    let record = serde_json::to_vec(&(directory, fields, settings))?;
+    log_record(XLOG_BM25_CREATE, &record);
+    Ok(())
+}
+
+/// Log that the writer process has started, and is about to reconcile the indexes with the
+/// transactions that were in flight when Postgres last stopped. A standby drops the documents
+/// of those transactions too.
+pub fn log_recover() {
+    log_record(XLOG_BM25_RECOVER, &[]);
+}
+
+// The record types, in the bits of a record's info byte that are left to its resource manager.
+const XLOG_BM25_REQUEST: u8 = 0x00;
+const XLOG_BM25_CREATE: u8 = 0x10;
+const XLOG_BM25_RECOVER: u8 = 0x20;
+
+#[cfg(any(feature = "pg15", feature = "pg16"))]
+pub use rmgr::init;
+#[cfg(any(feature = "pg15", feature = "pg16"))]
+use rmgr::log_record;
+
+/// Custom resource managers aren't available before Postgres 15.
+#[cfg(not(any(feature = "pg15", feature = "pg16")))]
+pub fn init() {}
+
+#[cfg(not(any(feature = "pg15", feature = "pg16")))]
+fn log_record(_info: u8, _data: &[u8]) {}
+
+#[cfg(any(feature = "pg15", feature = "pg16"))]
+mod rmgr {
+    use super::{XLOG_BM25_CREATE, XLOG_BM25_RECOVER, XLOG_BM25_REQUEST};
+    use crate::{
+        env::{postgres_data_dir_path, transaction_status},
+        index::{SearchIndex, SearchIndexSettings},
+        schema::{SearchFieldConfig, SearchFieldName},
+        writer::{self, Handler, Writer, WriterDirectory, WriterRequest},
+    };
+    use once_cell::sync::Lazy;
+    use pgrx::*;
+    use std::{
+        ffi::{c_char, CStr},
+        path::Path,
+        sync::Mutex,
+    };
+
+    /// The id of the resource manager, which is written into every record. Postgres reserves
+    /// `RM_EXPERIMENTAL_ID` for extensions whose id isn't registered with the project yet, so
+    /// it can't be used alongside another extension that uses it too.
+    const RM_BM25_ID: u8 = pg_sys::RM_EXPERIMENTAL_ID as u8;
+    const XLR_RMGR_INFO_MASK: u8 = 0xF0;
+
+    static mut BM25_RMGR: pg_sys::RmgrData = pg_sys::RmgrData {
+        rm_name: b"pg_search\0".as_ptr() as *const c_char,
+        rm_redo: Some(bm25_redo),
+        rm_desc: Some(bm25_desc),
+        rm_identify: Some(bm25_identify),
+        rm_startup: Some(bm25_startup),
+        rm_cleanup: Some(bm25_cleanup),
+        rm_mask: None,
+        rm_decode: None,
+    };
+
+    /// The writer that replays logged requests, in the startup process of a standby or of a
+    /// server that's recovering from a crash.
+    static REPLAY_WRITER: Lazy<Mutex<Option<Writer>>> = Lazy::new(|| Mutex::new(None));
+
+    /// Register the resource manager. Postgres only allows it while it's loading the libraries
+    /// in `shared_preload_libraries`, which is where pg_search has to be loaded from anyway.
+    pub fn init() {
+        unsafe {
+            if pg_sys::process_shared_preload_libraries_in_progress {
+                pg_sys::RegisterCustomRmgr(RM_BM25_ID, std::ptr::addr_of!(BM25_RMGR));
+            }
+        }
+    }
+
+    pub(super) fn log_record(info: u8, data: &[u8]) {
+        unsafe {
+            // The records are only needed by a standby. A primary replays them after a crash
+            // too, but it recovers its indexes with their journals without them.
+            if pg_sys::wal_level < pg_sys::WalLevel_WAL_LEVEL_REPLICA as i32 {
+                return;
+            }
+
+            pg_sys::XLogBeginInsert();
+            pg_sys::XLogRegisterData(data.as_ptr() as *mut c_char, data.len() as u32);
+            pg_sys::XLogInsert(RM_BM25_ID, info);
+        }
+    }
+
+    #[pg_guard]
+    unsafe extern "C" fn bm25_startup() {
+        *REPLAY_WRITER.lock().expect("could not lock replay writer") = Some(Writer::new());
+    }
+
+    /// Recovery is over, so the replay writer's indexes are released for the writer process.
+    #[pg_guard]
+    unsafe extern "C" fn bm25_cleanup() {
+        *REPLAY_WRITER.lock().expect("could not lock replay writer") = None;
+    }
+
+    #[pg_guard]
+    unsafe extern "C" fn bm25_redo(record: *mut pg_sys::XLogReaderState) {
+        let decoded = (*record).record;
+        let info = (*decoded).header.xl_info & XLR_RMGR_INFO_MASK;
+        let data = record_data(decoded);
+
+        let mut replay_writer = REPLAY_WRITER.lock().expect("could not lock replay writer");
+        let writer = replay_writer.get_or_insert_with(Writer::new);
+        let data_dir = postgres_data_dir_path();
+
+        match info {
+            XLOG_BM25_REQUEST => match bincode::deserialize::<WriterRequest>(data) {
+                Ok(request) => replay_request(writer, request, &data_dir),
+                Err(err) => warning!("could not decode logged bm25 request: {err}"),
+            },
+            XLOG_BM25_CREATE => {
+                match serde_json::from_slice::<(
+                    WriterDirectory,
+                    Vec<(SearchFieldName, SearchFieldConfig)>,
+                    SearchIndexSettings,
+                )>(data)
+                {
+                    Ok((mut directory, fields, settings)) => {
+                        directory.postgres_data_dir_path = data_dir;
+                        replay_create(writer, directory, fields, settings)
+                    }
+                    Err(err) => warning!("could not decode logged bm25 index creation: {err}"),
+                }
+            }
+            XLOG_BM25_RECOVER => {
+                // The documents that the primary's writer hadn't committed were lost with it.
+                *writer = Writer::new();
+                match WriterDirectory::all(&data_dir) {
+                    Ok(directories) => {
+                        for directory in directories {
+                            writer::recover(&directory, transaction_status);
+                        }
+                    }
+                    Err(err) => warning!("could not list bm25 indexes to recover: {err}"),
+                }
+            }
+            _ => panic!("unknown pg_search record type: {info}"),
+        }
+    }
+
+    /// Replay a request on the replay writer. A request may have been carried out on the index
+    /// already, if the index was copied by a base backup or committed before a crash, after
+    /// the point that replay starts from. So an insert first deletes any copy of its document,
+    /// and the other requests are safe to repeat.
+    fn replay_request(writer: &mut Writer, mut request: WriterRequest, data_dir: &Path) {
+        let directory = relocate(&mut request, data_dir);
+        if let WriterRequest::Insert { document, .. } = &request {
+            if let Some(ctid) = document
+                .doc
+                .get_first(document.ctid.0)
+                .and_then(|value| value.as_u64())
+            {
+                let delete = WriterRequest::Delete {
+                    directory: directory.clone(),
+                    xid: 0,
+                    field: document.ctid.0,
+                    ctids: vec![ctid],
+                };
+                if let Err(err) = writer.handle(delete) {
+                    return invalidate(&directory, &err.to_string());
+                }
+            }
+        }
+
+        if let Err(err) = writer.handle(request) {
+            invalidate(&directory, &err.to_string());
+        }
+    }
+
+    fn replay_create(
+        writer: &mut Writer,
+        directory: WriterDirectory,
+        fields: Vec<(SearchFieldName, SearchFieldConfig)>,
+        settings: SearchIndexSettings,
+    ) {
+        // Any writer still open on the directory would hold on to the index it replaces.
+        let abort = WriterRequest::Abort {
+            directory: directory.clone(),
+            xid: 0,
+        };
+        if let Err(err) = writer.handle(abort) {
+            return invalidate(&directory, &err.to_string());
+        }
+        if let Err(err) = SearchIndex::new(directory.clone(), fields, settings) {
+            invalidate(&directory, &err.to_string());
+        }
+    }
+
+    /// A logged request names the primary's data directory, which a standby may keep elsewhere.
+    fn relocate(request: &mut WriterRequest, data_dir: &Path) -> WriterDirectory {
+        let directory = match request {
+            WriterRequest::Insert { directory, .. }
+            | WriterRequest::Delete { directory, .. }
+            | WriterRequest::DropIndex { directory }
+            | WriterRequest::Abort { directory, .. }
+            | WriterRequest::Commit { directory, .. }
+            | WriterRequest::ClearJournal { directory, .. }
+            | WriterRequest::RollbackPrepared { directory, .. }
+            | WriterRequest::Vacuum { directory }
+            | WriterRequest::Optimize { directory, .. }
+            | WriterRequest::PrepareCommit { directory } => directory,
+        };
+        directory.postgres_data_dir_path = data_dir.to_path_buf();
+        directory.clone()
+    }
+
+    /// Replay can't stop for an index that it couldn't update, so the index is marked invalid,
+    /// and searching it raises an error until it's rebuilt.
+    fn invalidate(directory: &WriterDirectory, reason: &str) {
+        warning!("could not replay change to bm25 index {directory:?}: {reason}");
+        if let Err(err) = directory.mark_invalid(reason) {
+            warning!("could not mark bm25 index {directory:?} invalid: {err}");
+        }
+    }
+
+    unsafe fn record_data<'a>(decoded: *mut pg_sys::DecodedXLogRecord) -> &'a [u8] {
+        if (*decoded).main_data_len == 0 {
+            return &[];
+        }
+        std::slice::from_raw_parts(
+            (*decoded).main_data as *const u8,
+            (*decoded).main_data_len as usize,
+        )
+    }
+
+    #[pg_guard]
+    unsafe extern "C" fn bm25_desc(buf: pg_sys::StringInfo, record: *mut pg_sys::XLogReaderState) {
+        let decoded = (*record).record;
+        let info = (*decoded).header.xl_info & XLR_RMGR_INFO_MASK;
+        let description = match info {
+            XLOG_BM25_REQUEST => describe_request_record(record_data(decoded)),
+            XLOG_BM25_CREATE => format!("{} bytes of index settings", (*decoded).main_data_len),
+            _ => String::new(),
+        };
+        let description = std::ffi::CString::new(description).unwrap_or_default();
+        pg_sys::appendStringInfoString(buf, description.as_ptr());
+    }
+
+    fn describe_request_record(data: &[u8]) -> String {
+        match bincode::deserialize::<WriterRequest>(data) {
+            Ok(request) => describe_request(&request),
+            Err(_) => "undecodable request".into(),
+        }
+    }
+
+    fn describe_request(request: &WriterRequest) -> String {
+        match request {
+            WriterRequest::Insert { directory, .. } => {
+                format!("insert into index {}", directory.index_oid)
+            }
+            WriterRequest::Delete {
+                directory, ctids, ..
+            } => format!("delete {} from index {}", ctids.len(), directory.index_oid),
+            WriterRequest::DropIndex { directory } => format!("drop index {}", directory.index_oid),
+            WriterRequest::Abort { directory, xid } => {
+                format!("abort index {}, xid {xid}", directory.index_oid)
+            }
+            WriterRequest::Commit { directory, xid } => {
+                format!("commit index {}, xid {xid}", directory.index_oid)
+            }
+            WriterRequest::ClearJournal { directory, xid } => {
+                format!("clear journal of index {}, xid {xid}", directory.index_oid)
+            }
+            WriterRequest::RollbackPrepared { directory, xid } => {
+                format!("rollback prepared index {}, xid {xid}", directory.index_oid)
+            }
+            WriterRequest::Vacuum { directory } => format!("vacuum index {}", directory.index_oid),
+            WriterRequest::Optimize {
+                directory,
+                max_segments,
+            } => format!(
+                "optimize index {} to {max_segments} segments",
+                directory.index_oid
+            ),
+            WriterRequest::PrepareCommit { directory } => {
+                format!("prepare commit index {}", directory.index_oid)
+            }
+        }
+    }
+
+    #[pg_guard]
+    unsafe extern "C" fn bm25_identify(info: u8) -> *const c_char {
+        let name: &'static CStr = match info & XLR_RMGR_INFO_MASK {
+            XLOG_BM25_REQUEST => CStr::from_bytes_with_nul_unchecked(b"REQUEST\0"),
+            XLOG_BM25_CREATE => CStr::from_bytes_with_nul_unchecked(b"CREATE\0"),
+            XLOG_BM25_RECOVER => CStr::from_bytes_with_nul_unchecked(b"RECOVER\0"),
+            _ => return std::ptr::null(),
+        };
+        name.as_ptr()
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::{describe_request, describe_request_record, relocate, replay_request};
+        use crate::{
+            fixtures::*,
+            index::SearchIndex,
+            writer::{self, TransactionStatus, Writer, WriterClient, WriterRequest},
+        };
+        use anyhow::Result;
+        use rstest::*;
+        use std::path::{Path, PathBuf};
+
+        #[rstest]
+        fn test_relocate(mock_dir: MockWriterDirectory) {
+            let mut request = WriterRequest::Commit {
+                directory: mock_dir.writer_dir.clone(),
+                xid: 7,
+            };
+
+            let directory = relocate(&mut request, Path::new("/standby"));
+
+            assert_eq!(directory.postgres_data_dir_path, PathBuf::from("/standby"));
+            assert_eq!(request, WriterRequest::Commit { directory, xid: 7 });
+        }
+
+        #[rstest]
+        #[case::commit(WriterRequest::Commit { directory: mock_dir().writer_dir, xid: 7 }, "commit index 0, xid 7")]
+        #[case::abort(WriterRequest::Abort { directory: mock_dir().writer_dir, xid: 7 }, "abort index 0, xid 7")]
+        #[case::delete(WriterRequest::Delete { directory: mock_dir().writer_dir, xid: 0, field: tantivy::schema::Field::from_field_id(1), ctids: vec![1, 2] }, "delete 2 from index 0")]
+        #[case::optimize(WriterRequest::Optimize { directory: mock_dir().writer_dir, max_segments: 1 }, "optimize index 0 to 1 segments")]
+        fn test_describe_request(#[case] request: WriterRequest, #[case] expected: &str) {
+            let logged = bincode::serialize(&request).unwrap();
+
+            assert_eq!(
+                bincode::deserialize::<WriterRequest>(&logged).unwrap(),
+                request
+            );
+            assert_eq!(describe_request(&request), expected);
+            assert_eq!(describe_request_record(&logged), expected);
+            assert_eq!(describe_request_record(&logged[1..]), "undecodable request");
+        }
+
+        /// A primary that crashed replays the records logged since its checkpoint, over an
+        /// index that may hold their changes already, and then recovers from the journal.
+        #[rstest]
+        #[case::committed(true, TransactionStatus::Committed, 1)]
+        #[case::aborted_after_commit(true, TransactionStatus::Aborted, 0)]
+        #[case::aborted_before_commit(false, TransactionStatus::Aborted, 0)]
+        fn test_replay_insert_and_commit(
+            default_index: MockSearchIndex,
+            #[case] committed: bool,
+            #[case] status: TransactionStatus,
+            #[case] expected_docs: u64,
+        ) -> Result<()> {
+            let client = TestClient::new_arc();
+            let mut index = default_index.index;
+            let directory = index.directory.clone();
+            let document = simple_doc(simple_schema(default_fields()));
+
+            let mut logged = vec![WriterRequest::Insert {
+                directory: directory.clone(),
+                xid: 7,
+                document: document.clone(),
+            }];
+            index.insert(&client, 7, document)?;
+            if committed {
+                let commit = WriterRequest::Commit {
+                    directory: directory.clone(),
+                    xid: 7,
+                };
+                client.lock().unwrap().request(commit.clone())?;
+                logged.push(commit);
+            }
+
+            // The primary's writer, and anything that it hadn't committed, is lost in the crash.
+            drop(client);
+            let mut replay_writer = Writer::new();
+            for request in &logged {
+                let request = bincode::deserialize(&bincode::serialize(request)?)?;
+                replay_request(
+                    &mut replay_writer,
+                    request,
+                    &directory.postgres_data_dir_path,
+                );
+            }
+            if committed {
+                assert_eq!(
+                    directory.journal_entry(7)?.map(|entry| entry.ctids),
+                    Some(vec![0])
+                );
+            }
+
+            // Recovery ends with the replay writer dropped, before the writer process starts.
+            drop(replay_writer);
+            writer::recover(&directory, |_| status);
+
+            let recovered: SearchIndex = directory.load_index()?;
+            assert_eq!(recovered.searcher().num_docs(), expected_docs);
+            assert!(directory.journal_entries()?.is_empty());
+            assert_eq!(directory.invalid_reason(), None);
+
+            Ok(())
+        }
+    }
+}
@@ -133,6 +133,23 @@ mod tests {
         assert_ne!(expected.is_fast(), text_options.is_fast());
     }
 
+    #[rstest]
+    fn test_search_text_options_with_filters() {
+        let json = r#"{
+            "tokenizer": {"type": "en_stem"},
+            "filters": [{"type": "phonetic", "encoder": "soundex", "keep_original": true}]
+        }"#;
+        let config: serde_json::Value = serde_json::from_str(json).unwrap();
+        let search_text_option: SearchFieldConfig =
+            serde_json::from_value(serde_json::json!({"Text": config})).unwrap();
+        let text_options: TextOptions = search_text_option.into();
+
+        assert_eq!(
+            text_options.get_indexing_options().unwrap().tokenizer(),
+            "en_stem_filters:phonetic_encoder:soundex_keeporiginal:true"
+        );
+    }
+
     #[rstest]
     fn test_search_numeric_options() {
         let json = r#"{
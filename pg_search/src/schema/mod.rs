@@ -13,7 +13,7 @@ use tantivy::schema::{
     TextOptions, FAST, INDEXED, STORED,
 };
 use thiserror::Error;
-use tokenizers::{SearchNormalizer, SearchTokenizer};
+use tokenizers::{SearchNormalizer, SearchTokenFilter, SearchTokenizer};
 
 use crate::query::AsFieldType;
 
@@ -81,6 +81,8 @@ pub enum SearchFieldConfig {
         record: IndexRecordOption,
         #[serde(default)]
         normalizer: SearchNormalizer,
+        #[serde(default)]
+        filters: Vec<SearchTokenFilter>,
     },
     Json {
         #[serde(default = "default_as_true")]
@@ -98,6 +100,8 @@ pub enum SearchFieldConfig {
         record: IndexRecordOption,
         #[serde(default)]
         normalizer: SearchNormalizer,
+        #[serde(default)]
+        filters: Vec<SearchTokenFilter>,
     },
     Numeric {
         #[serde(default = "default_as_true")]
@@ -139,6 +143,23 @@ impl SearchFieldConfig {
     pub fn default_json() -> Self {
         Self::from_json(json!({"Json": {}}))
     }
+
+    /// Checks the options of the field that are only known to be valid once its tokenizer is
+    /// built, like the sizes of an `edge_ngram` filter or the name of a custom tokenizer.
+    pub fn validate(&self) -> Result<(), String> {
+        match self {
+            SearchFieldConfig::Text {
+                tokenizer, filters, ..
+            }
+            | SearchFieldConfig::Json {
+                tokenizer, filters, ..
+            } => {
+                tokenizer.validate()?;
+                filters.iter().try_for_each(SearchTokenFilter::validate)
+            }
+            _ => Ok(()),
+        }
+    }
 }
 
 impl From<SearchFieldConfig> for TextOptions {
@@ -153,6 +174,7 @@ impl From<SearchFieldConfig> for TextOptions {
                 tokenizer,
                 record,
                 normalizer,
+                filters,
             } => {
                 if stored {
                     text_options = text_options.set_stored();
@@ -164,7 +186,7 @@ impl From<SearchFieldConfig> for TextOptions {
                     let text_field_indexing = TextFieldIndexing::default()
                         .set_index_option(record)
                         .set_fieldnorms(fieldnorms)
-                        .set_tokenizer(&tokenizer.name());
+                        .set_tokenizer(&tokenizer.name_with_filters(&filters));
 
                     text_options = text_options.set_indexing_options(text_field_indexing);
                 }
@@ -218,6 +240,7 @@ impl From<SearchFieldConfig> for JsonObjectOptions {
                 tokenizer,
                 record,
                 normalizer,
+                filters,
             } => {
                 if stored {
                     json_options = json_options.set_stored();
@@ -231,7 +254,7 @@ impl From<SearchFieldConfig> for JsonObjectOptions {
                 if indexed {
                     let text_field_indexing = TextFieldIndexing::default()
                         .set_index_option(record)
-                        .set_tokenizer(&tokenizer.name());
+                        .set_tokenizer(&tokenizer.name_with_filters(&filters));
 
                     json_options = json_options.set_indexing_options(text_field_indexing);
                 }
@@ -1,14 +1,21 @@
 use crate::globals::WRITER_GLOBAL;
 
-use super::{transfer::WriterTransferProducer, ServerRequest, WriterClient};
+use super::{transfer::WriterTransferProducer, ServerRequest, ServerResponse, WriterClient};
 use serde::Serialize;
-use std::{marker::PhantomData, net::SocketAddr, panic, path::Path};
+use std::{
+    marker::PhantomData,
+    os::unix::net::UnixStream,
+    panic,
+    path::{Path, PathBuf},
+};
 use thiserror::Error;
 
 pub struct Client<T: Serialize> {
-    addr: std::net::SocketAddr,
-    http: reqwest::blocking::Client,
+    socket_path: PathBuf,
     producer: Option<WriterTransferProducer<T>>,
+    /// The connection of an open transfer, which the server responds on again once it has
+    /// handled every request of the transfer.
+    transfer_stream: Option<UnixStream>,
     marker: PhantomData<T>,
 }
 
@@ -21,23 +28,15 @@ pub struct Client<T: Serialize> {
 /// This is useful for transfering large volumes of data, where "request"
 /// has too much overhead to be called over and over.
 
-/// A transfer requires exclusive access to the background server, so
-/// during a transfer, other connections will block and wait for the
-/// background server to become available again.
+/// The background server handles requests for different indexes concurrently,
+/// but requests for the same index in order, so during a transfer, requests for
+/// its indexes from other connections wait behind the requests of the transfer.
 impl<T: Serialize> Client<T> {
-    pub fn new(addr: SocketAddr) -> Self {
-        // Some server processes, like creating a index, can take a long time.
-        // Because the server is blocking/single-threaded, clients should wait
-        // as long as they need to for their turn to use the server.
-        let http = reqwest::blocking::ClientBuilder::new()
-            .timeout(None)
-            .build()
-            .expect("error building http client");
-
+    pub fn new<P: AsRef<Path>>(socket_path: P) -> Self {
         Self {
-            addr,
-            http,
+            socket_path: socket_path.as_ref().to_path_buf(),
             producer: None,
+            transfer_stream: None,
             marker: PhantomData,
         }
     }
@@ -45,33 +44,41 @@ impl<T: Serialize> Client<T> {
     pub fn from_global() -> Self {
         let lock = panic::catch_unwind(|| WRITER_GLOBAL.share());
 
-        let addr = match lock {
-            Ok(lock) => lock.addr(),
+        let socket_path = match lock {
+            Ok(lock) => lock.socket_path(),
             Err(_) => {
                 panic!("Could not get lock on writer. Have you added the extension to the shared preload library list?");
             }
         };
 
-        Self::new(addr)
-    }
-
-    fn url(&self) -> String {
-        format!("http://{}", self.addr)
+        Self::new(socket_path)
     }
 
     fn send_request(&mut self, request: ServerRequest<T>) -> Result<(), ClientError> {
         // If there is an open pending transfer, stop it so that we can continue
         // with more requests.
-        self.stop_transfer();
-        let bytes = bincode::serialize(&request).unwrap();
-        let response = self.http.post(self.url()).body::<Vec<u8>>(bytes).send()?;
-
-        match response.status() {
-            reqwest::StatusCode::OK => Ok(()),
-            _ => {
-                let err = response.text().map_err(ClientError::ResponseParse)?;
-                Err(ClientError::ServerError(err))
-            }
+        self.stop_transfer()?;
+        self.connect(request)?;
+        Ok(())
+    }
+
+    /// Send a request on a new connection, and return the connection once the server
+    /// has responded.
+    fn connect(&self, request: ServerRequest<T>) -> Result<UnixStream, ClientError> {
+        // Some server processes, like committing a large index, can take a long time.
+        // Requests for the same index are handled one at a time, so clients should wait
+        // as long as they need to for their turn, and there's no timeout.
+        let mut stream = UnixStream::connect(&self.socket_path)
+            .map_err(|err| ClientError::Connect(self.socket_path.clone(), err))?;
+        bincode::serialize_into(&mut stream, &request)?;
+        Self::read_response(&mut stream)?;
+        Ok(stream)
+    }
+
+    fn read_response(stream: &mut UnixStream) -> Result<(), ClientError> {
+        match bincode::deserialize_from(stream)? {
+            ServerResponse::Ok => Ok(()),
+            ServerResponse::Error(err) => Err(ClientError::ServerError(err)),
         }
     }
 
@@ -82,9 +89,11 @@ impl<T: Serialize> Client<T> {
     ) -> Result<(), ClientError> {
         if self.producer.is_none() {
             // Send a request to open a transfer to the server.
-            self.send_request(ServerRequest::Transfer(
+            self.stop_transfer()?;
+            let stream = self.connect(ServerRequest::Transfer(
                 pipe_path.as_ref().display().to_string(),
             ))?;
+            self.transfer_stream.replace(stream);
             // Store a new transfer producer in the client state.
             self.producer
                 .replace(WriterTransferProducer::new(pipe_path)?);
@@ -95,15 +104,48 @@ impl<T: Serialize> Client<T> {
         Ok(())
     }
 
+    /// Start a data pipe transfer from several producers at once, like the participants of a
+    /// parallel index build. Each producer writes to its own pipe path with a
+    /// `WriterTransferProducer`, and the server handles their requests as they arrive, until
+    /// every producer is done. The next request waits for the transfer to be done.
+    pub fn parallel_transfer<P: AsRef<Path>>(
+        &mut self,
+        pipe_paths: &[P],
+    ) -> Result<(), ClientError> {
+        self.stop_transfer()?;
+        let stream = self.connect(ServerRequest::ParallelTransfer(
+            pipe_paths
+                .iter()
+                .map(|pipe_path| pipe_path.as_ref().display().to_string())
+                .collect(),
+        ))?;
+        self.transfer_stream.replace(stream);
+        Ok(())
+    }
+
     /// Stop a data pipe transfer. Must be called when the transfer is done, or
     /// the client + server will both hang forever.
     ///
     /// With insert transactions, it's tricky to know when the transfer is
     /// completely done. Best practice is to call this both during the end of
     /// transaction callback, as well as before every send_request.
-    fn stop_transfer(&mut self) {
-        // Dropping the producer closes the named pipe file.
-        self.producer.take();
+    ///
+    /// Waits for the server to handle every request of the transfer, so that requests sent
+    /// after it, like a commit, are handled after them.
+    fn stop_transfer(&mut self) -> Result<(), ClientError> {
+        // The producer buffers its requests, so finishing it writes out the last of them,
+        // and fails the transfer if they can't be written. Either way, the producer closes
+        // the named pipe file, and the server responds once it has read everything.
+        let finished = self
+            .producer
+            .take()
+            .map_or(Ok(()), |producer| producer.finish());
+        let handled = match self.transfer_stream.take() {
+            Some(mut stream) => Self::read_response(&mut stream),
+            None => Ok(()),
+        };
+        finished?;
+        handled
     }
 
     /// Should only be called by shutdown background worker.
@@ -125,8 +167,8 @@ impl<T: Serialize> WriterClient<T> for Client<T> {
 
 #[derive(Error, Debug)]
 pub enum ClientError {
-    #[error("could not parse response from writer server: {0}")]
-    ResponseParse(reqwest::Error),
+    #[error("could not connect to writer server at {0:?}: {1}")]
+    Connect(PathBuf, std::io::Error),
 
     #[error("writer server responded with an error: {0}")]
     ServerError(String),
@@ -135,7 +177,7 @@ pub enum ClientError {
     IOError(#[from] std::io::Error),
 
     #[error(transparent)]
-    ReqwestError(#[from] reqwest::Error),
+    Bincode(#[from] bincode::Error),
 
     #[error(transparent)]
     SerdeError(#[from] serde_json::Error),
@@ -144,36 +186,183 @@ pub enum ClientError {
 #[cfg(test)]
 mod tests {
     use crate::fixtures::*;
-    use crate::writer::{Client, Server, WriterClient, WriterRequest};
+    use crate::writer::{
+        Client, Server, WriterClient, WriterDirectory, WriterRequest, WriterTransferPipeFilePath,
+    };
     use rstest::*;
+    use std::sync::{mpsc, Arc, Mutex};
     use std::thread;
 
     #[rstest]
     #[case::insert_request(WriterRequest::Insert {
         directory: mock_dir().writer_dir,
+        xid: 1,
         document: simple_doc(simple_schema(default_fields())),
     })]
-    #[case::commit_request(WriterRequest::Commit { directory: mock_dir().writer_dir })]
-    #[case::abort_request(WriterRequest::Abort {directory: mock_dir().writer_dir})]
+    #[case::commit_request(WriterRequest::Commit { directory: mock_dir().writer_dir, xid: 1 })]
+    #[case::clear_journal_request(WriterRequest::ClearJournal { directory: mock_dir().writer_dir, xid: 1 })]
+    #[case::rollback_prepared_request(WriterRequest::RollbackPrepared { directory: mock_dir().writer_dir, xid: 1 })]
+    #[case::abort_request(WriterRequest::Abort { directory: mock_dir().writer_dir, xid: 1 })]
     #[case::vacuum_request(WriterRequest::Vacuum { directory: mock_dir().writer_dir })]
+    #[case::optimize_request(WriterRequest::Optimize { directory: mock_dir().writer_dir, max_segments: 1 })]
     #[case::drop_index_request(WriterRequest::DropIndex { directory: mock_dir().writer_dir })]
     /// Test request serialization and transfer between client and server.
     fn test_client_request(#[case] request: WriterRequest) {
         // Create a handler that will test that the received request is the same as sent.
         let request_clone = request.clone();
-        let handler = TestHandler::new(move |req: WriterRequest| assert_eq!(&req, &request_clone));
-        let mut server = Server::new(handler).unwrap();
-        let addr = server.addr();
+        let new_handler = move || {
+            let request_clone = request_clone.clone();
+            TestHandler::new(move |req: WriterRequest| assert_eq!(&req, &request_clone))
+        };
+        let socket_dir = tempfile::tempdir().unwrap();
+        let mut server = Server::new(socket_dir.path().join("writer.sock"), new_handler).unwrap();
+        let socket_path = server.socket_path().to_path_buf();
 
         // Start the server in a new thread, as it blocks once started.
         thread::spawn(move || {
             server.start().unwrap();
         });
 
-        let mut client: Client<WriterRequest> = Client::new(addr);
+        let mut client: Client<WriterRequest> = Client::new(socket_path);
         client.request(request.clone()).unwrap();
 
         // The server must be stopped, or this test will not finish.
         client.stop_server().unwrap();
     }
+
+    #[rstest]
+    /// Test that a request for one index is handled while another index is busy.
+    fn test_concurrent_indexes(mock_dir: MockWriterDirectory) {
+        let busy_dir = WriterDirectory {
+            index_oid: 1,
+            ..mock_dir.writer_dir.clone()
+        };
+        let free_dir = WriterDirectory {
+            index_oid: 2,
+            ..mock_dir.writer_dir.clone()
+        };
+
+        // The handler for the busy index blocks until the test releases it.
+        let (release, released) = mpsc::channel::<()>();
+        let released = Arc::new(Mutex::new(released));
+        let blocking_dir = busy_dir.clone();
+        let new_handler = move || {
+            let released = released.clone();
+            let blocking_dir = blocking_dir.clone();
+            TestHandler::new(move |req: WriterRequest| {
+                if req
+                    == (WriterRequest::Commit {
+                        directory: blocking_dir.clone(),
+                        xid: 1,
+                    })
+                {
+                    released.lock().unwrap().recv().unwrap();
+                }
+            })
+        };
+        let socket_dir = tempfile::tempdir().unwrap();
+        let mut server = Server::new(socket_dir.path().join("writer.sock"), new_handler).unwrap();
+        let socket_path = server.socket_path().to_path_buf();
+
+        thread::spawn(move || {
+            server.start().unwrap();
+        });
+
+        let busy_socket_path = socket_path.clone();
+        let busy = thread::spawn(move || {
+            let mut client: Client<WriterRequest> = Client::new(busy_socket_path);
+            client
+                .request(WriterRequest::Commit {
+                    directory: busy_dir,
+                    xid: 1,
+                })
+                .unwrap();
+        });
+
+        // If requests were handled one at a time, this would wait for the busy index forever.
+        let mut client: Client<WriterRequest> = Client::new(socket_path);
+        client
+            .request(WriterRequest::Commit {
+                directory: free_dir,
+                xid: 2,
+            })
+            .unwrap();
+
+        release.send(()).unwrap();
+        busy.join().unwrap();
+        client.stop_server().unwrap();
+    }
+
+    /// Start a server whose handler panics on vacuum requests.
+    fn panicking_server() -> (tempfile::TempDir, std::path::PathBuf) {
+        let new_handler = || {
+            TestHandler::new(|req: WriterRequest| {
+                if let WriterRequest::Vacuum { .. } = req {
+                    panic!("vacuum failed");
+                }
+            })
+        };
+        let socket_dir = tempfile::tempdir().unwrap();
+        let mut server = Server::new(socket_dir.path().join("writer.sock"), new_handler).unwrap();
+        let socket_path = server.socket_path().to_path_buf();
+        thread::spawn(move || {
+            server.start().unwrap();
+        });
+        (socket_dir, socket_path)
+    }
+
+    #[rstest]
+    /// Test that a handler that panics fails its request, and the next request for its index
+    /// gets a new handler.
+    fn test_handler_panic(mock_dir: MockWriterDirectory) {
+        let (_socket_dir, socket_path) = panicking_server();
+        let directory = mock_dir.writer_dir.clone();
+
+        let mut client: Client<WriterRequest> = Client::new(socket_path);
+        let err = client
+            .request(WriterRequest::Vacuum {
+                directory: directory.clone(),
+            })
+            .unwrap_err();
+        assert!(err.to_string().contains("vacuum failed"), "{err}");
+        client
+            .request(WriterRequest::Commit { directory, xid: 1 })
+            .unwrap();
+
+        client.stop_server().unwrap();
+    }
+
+    #[rstest]
+    /// Test that a request of a transfer that fails makes the transfer fail when it's stopped,
+    /// so that the request after it isn't sent.
+    fn test_transfer_error(mock_dir: MockWriterDirectory) {
+        let (_socket_dir, socket_path) = panicking_server();
+        let directory = mock_dir.writer_dir.clone();
+        let WriterTransferPipeFilePath(pipe_path) =
+            mock_dir.writer_transfer_pipe_path(true).unwrap();
+
+        let mut client: Client<WriterRequest> = Client::new(socket_path);
+        client
+            .transfer(
+                &pipe_path,
+                WriterRequest::Vacuum {
+                    directory: directory.clone(),
+                },
+            )
+            .unwrap();
+        let err = client
+            .request(WriterRequest::Commit {
+                directory: directory.clone(),
+                xid: 1,
+            })
+            .unwrap_err();
+        assert!(err.to_string().contains("vacuum failed"), "{err}");
+
+        // The failed transfer is over, and the client can be used again.
+        client
+            .request(WriterRequest::Commit { directory, xid: 1 })
+            .unwrap();
+
+        client.stop_server().unwrap();
+    }
 }
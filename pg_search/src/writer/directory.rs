@@ -6,6 +6,7 @@ use std::{
     fs::{self, File},
     io::{self, Write},
     path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
 use thiserror::Error;
 use walkdir::WalkDir;
@@ -15,6 +16,27 @@ static SEARCH_DIR_NAME: &str = "pg_search";
 static SEARCH_INDEX_CONFIG_FILE_NAME: &str = "search-index.json";
 static TANTIVY_DIR_NAME: &str = "tantivy";
 static WRITER_TRANSFER_DIR_NAME: &str = "writer_transfer";
+static WRITER_SOCKET_FILE_NAME: &str = "writer.sock";
+static LAST_MERGE_FILE_NAME: &str = "last_merge";
+static USER_DICTIONARY_DIR_NAME: &str = "dictionaries";
+
+/// The path of the Unix domain socket that the writer server listens on. It's inside the
+/// Postgres data directory, so that it's only reachable by the Postgres user.
+pub fn writer_socket_path() -> PathBuf {
+    env::postgres_data_dir_path()
+        .join(PARADE_DATA_DIR_NAME)
+        .join(SEARCH_DIR_NAME)
+        .join(WRITER_SOCKET_FILE_NAME)
+}
+
+/// The directory that the Lindera tokenizers read user dictionaries from. It's inside the
+/// Postgres data directory, so that an index can only read dictionaries put there on purpose.
+pub fn user_dictionary_dir_path() -> PathBuf {
+    env::postgres_data_dir_path()
+        .join(PARADE_DATA_DIR_NAME)
+        .join(SEARCH_DIR_NAME)
+        .join(USER_DICTIONARY_DIR_NAME)
+}
 
 /// The top-level folder name for ParadeDB extension inside the Postgres data directory.
 #[derive(AsRef)]
@@ -52,18 +74,20 @@ pub trait SearchFs {
 
 #[derive(Debug, Clone, Serialize, Deserialize, Hash, Eq, PartialEq)]
 pub struct WriterDirectory {
-    pub index_name: String,
+    /// The directory is keyed by the index's oid rather than its name, which can change with
+    /// ALTER INDEX ... RENAME, or be swapped to a rebuilt index by REINDEX CONCURRENTLY.
+    pub index_oid: u32,
     pub database_oid: u32,
     pub postgres_data_dir_path: PathBuf,
 }
 
 impl WriterDirectory {
     /// Useful in a connection process, where the database oid is available in the environment.
-    pub fn from_index_name(index_name: &str) -> Self {
+    pub fn from_index_oid(index_oid: u32) -> Self {
         let database_oid = env::postgres_database_oid();
         let postgres_data_dir_path = env::postgres_data_dir_path();
         Self {
-            index_name: index_name.into(),
+            index_oid,
             database_oid,
             postgres_data_dir_path,
         }
@@ -71,28 +95,32 @@ impl WriterDirectory {
 
     /// Useful in a background process where the database oid must be specified.
     #[allow(dead_code)]
-    pub fn from_db_id_and_index_name(database_oid: u32, index_name: &str) -> Self {
+    pub fn from_db_id_and_index_oid(database_oid: u32, index_oid: u32) -> Self {
         let postgres_data_dir_path = env::postgres_data_dir_path();
         Self {
-            index_name: index_name.into(),
+            index_oid,
             database_oid,
             postgres_data_dir_path,
         }
     }
 
+    /// The directory that holds the data of every index.
+    pub(super) fn search_dir_path(postgres_data_dir_path: &Path) -> PathBuf {
+        postgres_data_dir_path
+            .join(PARADE_DATA_DIR_NAME)
+            .join(SEARCH_DIR_NAME)
+    }
+
     /// The root path for the directory tree.
-    fn search_index_dir_path(
+    pub(super) fn search_index_dir_path(
         &self,
         ensure_exists: bool,
     ) -> Result<SearchIndexDirPath, SearchDirectoryError> {
         let database_oid = &self.database_oid;
-        let index_name = &self.index_name;
-        let unique_index_dir_name = format!("{database_oid}_{index_name}");
-        let search_index_dir_path = &self
-            .postgres_data_dir_path
-            .join(PARADE_DATA_DIR_NAME)
-            .join(SEARCH_DIR_NAME)
-            .join(unique_index_dir_name);
+        let index_oid = &self.index_oid;
+        let unique_index_dir_name = format!("{database_oid}_{index_oid}");
+        let search_index_dir_path =
+            &Self::search_dir_path(&self.postgres_data_dir_path).join(unique_index_dir_name);
 
         if ensure_exists {
             Self::ensure_dir(search_index_dir_path)?;
@@ -106,6 +134,54 @@ impl WriterDirectory {
         Ok(path.exists())
     }
 
+    /// The number of bytes in the files of the Tantivy index.
+    pub fn tantivy_dir_size(&self) -> Result<u64, SearchDirectoryError> {
+        let TantivyDirPath(path) = self.tantivy_dir_path(false)?;
+        let mut size = 0;
+        for entry in fs::read_dir(&path)
+            .map_err(|err| SearchDirectoryError::ReadDirectoryEntry(path.clone(), err))?
+        {
+            let metadata = entry
+                .and_then(|entry| entry.metadata())
+                .map_err(|err| SearchDirectoryError::ReadDirectoryEntry(path.clone(), err))?;
+            if metadata.is_file() {
+                size += metadata.len();
+            }
+        }
+        Ok(size)
+    }
+
+    /// The number of bytes in a file of the Tantivy index, which is 0 if there's no such file.
+    pub fn tantivy_file_size(&self, file: &Path) -> Result<u64, SearchDirectoryError> {
+        let TantivyDirPath(path) = self.tantivy_dir_path(false)?;
+        let file_path = path.join(file);
+        match fs::metadata(&file_path) {
+            Ok(metadata) => Ok(metadata.len()),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(0),
+            Err(err) => Err(SearchDirectoryError::ReadDirectoryEntry(file_path, err)),
+        }
+    }
+
+    /// Note that `optimize_bm25` has finished merging the index's segments. Tantivy doesn't keep
+    /// track of merges, so their time is kept next to the index.
+    pub fn record_merge(&self) -> Result<(), SearchDirectoryError> {
+        let SearchIndexDirPath(index_path) = self.search_index_dir_path(false)?;
+        let last_merge_path = index_path.join(LAST_MERGE_FILE_NAME);
+        let since_epoch = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        fs::write(&last_merge_path, since_epoch.as_millis().to_string())
+            .map_err(|err| SearchDirectoryError::RecordMerge(last_merge_path, err))
+    }
+
+    /// When the index's segments were last merged, if they ever were.
+    pub fn last_merge_time(&self) -> Option<SystemTime> {
+        let SearchIndexDirPath(index_path) = self.search_index_dir_path(false).ok()?;
+        let millis = fs::read_to_string(index_path.join(LAST_MERGE_FILE_NAME)).ok()?;
+        let millis = millis.trim().parse().ok()?;
+        Some(UNIX_EPOCH + Duration::from_millis(millis))
+    }
+
     fn search_index_config_file_path(
         &self,
         ensure_exists: bool,
@@ -116,7 +192,7 @@ impl WriterDirectory {
         Ok(SearchIndexConfigFilePath(search_index_config_file_path))
     }
 
-    fn ensure_dir(path: &Path) -> Result<(), SearchDirectoryError> {
+    pub(super) fn ensure_dir(path: &Path) -> Result<(), SearchDirectoryError> {
         if !path.exists() {
             Self::create_dir_all(path)?
         }
@@ -313,6 +389,24 @@ pub enum SearchDirectoryError {
 
     #[error("could not lock file for removal: {1}")]
     LockFileForRemoval(PathBuf, #[source] std::io::Error),
+
+    #[error("could not serialize journal entry at {0:?}: {1}")]
+    JournalSerialize(PathBuf, #[source] serde_json::Error),
+
+    #[error("could not write journal entry at {0:?}: {1}")]
+    JournalWrite(PathBuf, #[source] std::io::Error),
+
+    #[error("could not read journal entry at {0:?}: {1}")]
+    JournalRead(PathBuf, #[source] std::io::Error),
+
+    #[error("could not deserialize journal entry at {0:?}: {1}")]
+    JournalDeserialize(PathBuf, #[source] serde_json::Error),
+
+    #[error("could not mark index invalid at {0:?}: {1}")]
+    MarkInvalid(PathBuf, #[source] std::io::Error),
+
+    #[error("could not record merge of index at {0:?}: {1}")]
+    RecordMerge(PathBuf, #[source] std::io::Error),
 }
 
 #[cfg(test)]
@@ -365,4 +459,42 @@ mod tests {
 
         Ok(())
     }
+
+    #[rstest]
+    fn test_tantivy_dir_size(mock_dir: MockWriterDirectory) -> Result<()> {
+        let TantivyDirPath(tantivy_path) = mock_dir.writer_dir.tantivy_dir_path(true)?;
+        assert_eq!(mock_dir.writer_dir.tantivy_dir_size()?, 0);
+
+        fs::write(tantivy_path.join("meta.json"), [0; 10])?;
+        fs::write(tantivy_path.join("segment.idx"), [0; 32])?;
+
+        assert_eq!(mock_dir.writer_dir.tantivy_dir_size()?, 42);
+
+        Ok(())
+    }
+
+    #[rstest]
+    fn test_tantivy_file_size(mock_dir: MockWriterDirectory) -> Result<()> {
+        let TantivyDirPath(tantivy_path) = mock_dir.writer_dir.tantivy_dir_path(true)?;
+        fs::write(tantivy_path.join("segment.idx"), [0; 32])?;
+
+        let writer_dir = &mock_dir.writer_dir;
+        assert_eq!(writer_dir.tantivy_file_size(Path::new("segment.idx"))?, 32);
+        assert_eq!(writer_dir.tantivy_file_size(Path::new("segment.pos"))?, 0);
+
+        Ok(())
+    }
+
+    #[rstest]
+    fn test_record_merge(mock_dir: MockWriterDirectory) -> Result<()> {
+        mock_dir.writer_dir.search_index_dir_path(true)?;
+        assert_eq!(mock_dir.writer_dir.last_merge_time(), None);
+
+        let before = SystemTime::now() - Duration::from_secs(1);
+        mock_dir.writer_dir.record_merge()?;
+        let last_merge_time = mock_dir.writer_dir.last_merge_time().unwrap();
+        assert!(last_merge_time >= before && last_merge_time <= SystemTime::now());
+
+        Ok(())
+    }
 }
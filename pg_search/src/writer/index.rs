@@ -1,21 +1,29 @@
-use super::{Handler, IndexError, SearchFs, ServerError, WriterDirectory, WriterRequest};
+use super::{
+    journal, Handler, IndexError, JournalEntry, SearchFs, ServerError, WriterDirectory,
+    WriterRequest,
+};
 use crate::{index::SearchIndex, schema::SearchDocument};
 use std::collections::{
     hash_map::Entry::{Occupied, Vacant},
     HashMap,
 };
 use tantivy::{schema::Field, IndexWriter};
+use tracing::warn;
 
 /// The entity that interfaces with Tantivy indexes.
 pub struct Writer {
     /// Map of index directory path to Tantivy writer instance.
     tantivy_writers: HashMap<WriterDirectory, tantivy::IndexWriter>,
+    /// Map of index directory path to the documents added since its last commit by each
+    /// transaction, keyed by xid. They're journaled under that xid when they're committed.
+    uncommitted: HashMap<WriterDirectory, HashMap<u32, JournalEntry>>,
 }
 
 impl Writer {
     pub fn new() -> Self {
         Self {
             tantivy_writers: HashMap::new(),
+            uncommitted: HashMap::new(),
         }
     }
 
@@ -35,22 +43,54 @@ impl Writer {
     fn insert(
         &mut self,
         directory: WriterDirectory,
+        xid: u32,
         document: SearchDocument,
     ) -> Result<(), IndexError> {
-        let writer = self.get_writer(directory)?;
+        let ctid = document
+            .doc
+            .get_first(document.ctid.0)
+            .and_then(|value| value.as_u64());
+        let ctid_field = document.ctid.0;
+        let writer = self.get_writer(directory.clone())?;
 
         // Add the Tantivy document to the index.
         writer.add_document(document.into())?;
 
+        // Documents that don't belong to a transaction have nothing to be journaled under.
+        if let (Some(ctid), true) = (ctid, xid != 0) {
+            self.uncommitted
+                .entry(directory)
+                .or_default()
+                .entry(xid)
+                .or_insert_with(|| JournalEntry {
+                    xid,
+                    ctid_field,
+                    ctids: vec![],
+                })
+                .ctids
+                .push(ctid);
+        }
+
         Ok(())
     }
 
     fn delete(
         &mut self,
         directory: WriterDirectory,
+        xid: u32,
         ctid_field: &Field,
         ctid_values: &[u64],
     ) -> Result<(), IndexError> {
+        // The transaction's own documents that it deletes don't need to be removed again if
+        // it aborts.
+        if let Some(entry) = self
+            .uncommitted
+            .get_mut(&directory)
+            .and_then(|entries| entries.get_mut(&xid))
+        {
+            entry.ctids.retain(|ctid| !ctid_values.contains(ctid));
+        }
+
         let writer = self.get_writer(directory)?;
         for ctid in ctid_values {
             let ctid_term = tantivy::Term::from_field_u64(*ctid_field, *ctid);
@@ -61,24 +101,74 @@ impl Writer {
 
     fn commit(&mut self, directory: WriterDirectory) -> Result<(), IndexError> {
         if directory.exists()? {
-            let writer = self
-                .get_writer(directory.clone())
-                .expect("cannot lookup writer");
-            writer.prepare_commit()?;
-            writer.commit()?;
+            self.commit_writer(&directory)?;
         } else {
             // If the directory doesn't exist, then the index doesn't exist anymore.
             // Rare, but possible if a previous delete failed. Drop it to free the space.
             self.drop_index(directory.clone())?;
         }
         self.tantivy_writers.remove(&directory);
+        self.uncommitted.remove(&directory);
         Ok(())
     }
 
-    fn abort(&mut self, directory: WriterDirectory) -> Result<(), IndexError> {
-        // If the transaction was aborted, we should drop the writer.
-        // Otherwise, partialy written data could stick around for the next transaction.
-        self.tantivy_writers.remove(&directory);
+    /// Commit every document added to the index so far. Tantivy commits the documents of
+    /// every transaction at once, not just those of the transaction asking for the commit, so
+    /// each transaction's documents are journaled under its own xid before they're committed.
+    /// That way they can be found and removed if the transaction aborts, or if Postgres
+    /// crashes before it commits.
+    fn commit_writer(&mut self, directory: &WriterDirectory) -> Result<(), IndexError> {
+        if let Some(entries) = self.uncommitted.remove(directory) {
+            for entry in entries.values().filter(|entry| !entry.ctids.is_empty()) {
+                directory
+                    .write_journal_entry(entry)
+                    .map_err(IndexError::Journal)?;
+            }
+        }
+        let writer = self.get_writer(directory.clone())?;
+        writer.prepare_commit()?;
+        writer.commit()?;
+        Ok(())
+    }
+
+    fn clear_journal(&mut self, directory: WriterDirectory, xid: u32) -> Result<(), IndexError> {
+        directory
+            .remove_journal_entry(xid)
+            .map_err(IndexError::Journal)
+    }
+
+    fn abort(&mut self, directory: WriterDirectory, xid: u32) -> Result<(), IndexError> {
+        if xid == 0 {
+            // Without a transaction to go by, everything added since the last commit is
+            // discarded by dropping the writer.
+            self.tantivy_writers.remove(&directory);
+            self.uncommitted.remove(&directory);
+            return Ok(());
+        }
+
+        // The writer also holds the documents of other transactions, so only the aborted
+        // transaction's documents are deleted, rather than dropping the writer.
+        let pending = self
+            .uncommitted
+            .get_mut(&directory)
+            .and_then(|entries| entries.remove(&xid));
+        let journaled = directory.journal_entry(xid).map_err(IndexError::Journal)?;
+        if pending.is_none() && journaled.is_none() {
+            return Ok(());
+        }
+
+        let writer = self.get_writer(directory.clone())?;
+        for entry in pending.iter().chain(journaled.iter()) {
+            journal::delete_documents(writer, entry);
+        }
+
+        // Documents that were committed before the transaction aborted, like when it searched
+        // the index after inserting into it, must be deleted durably before their journal
+        // entry is removed. Uncommitted ones are deleted with the next commit.
+        if journaled.is_some() {
+            self.commit_writer(&directory)?;
+            self.clear_journal(directory, xid)?;
+        }
         Ok(())
     }
 
@@ -88,6 +178,58 @@ impl Writer {
         Ok(())
     }
 
+    /// Merge the index's smallest segments together until it has at most `max_segments`, and
+    /// rewrite the others that have deleted documents without them. Only committed segments
+    /// are merged, and each merge is saved to the index when it's done.
+    fn optimize(
+        &mut self,
+        directory: WriterDirectory,
+        max_segments: usize,
+    ) -> Result<(), IndexError> {
+        let max_segments = max_segments.max(1);
+        let writer = self.get_writer(directory.clone())?;
+        let mut segments = writer.index().searchable_segment_metas()?;
+        segments.sort_by_key(|segment| segment.num_docs());
+
+        let num_merged = if segments.len() > max_segments {
+            segments.len() - max_segments + 1
+        } else {
+            0
+        };
+        let (merged, rest) = segments.split_at(num_merged);
+        let mut merges: Vec<Vec<_>> = vec![];
+        if !merged.is_empty() {
+            merges.push(merged.iter().map(|segment| segment.id()).collect());
+        }
+        // Merging a segment on its own leaves out its deleted documents.
+        merges.extend(
+            rest.iter()
+                .filter(|segment| segment.has_deletes())
+                .map(|segment| vec![segment.id()]),
+        );
+
+        for segment_ids in &merges {
+            writer.merge(segment_ids).wait()?;
+        }
+        if !merges.is_empty() {
+            if let Err(err) = directory.record_merge() {
+                warn!("could not record merge of bm25 index {directory:?}: {err}");
+            }
+        }
+
+        // The files of the merged segments are no longer used.
+        writer.garbage_collect_files().wait()?;
+        Ok(())
+    }
+
+    fn prepare_commit(&mut self, directory: WriterDirectory) -> Result<(), IndexError> {
+        let writer = self.get_writer(directory)?;
+        // The prepared commit is dropped rather than committed. Its segments stay uncommitted
+        // until the next commit, or are discarded if the transaction aborts.
+        writer.prepare_commit()?;
+        Ok(())
+    }
+
     fn drop_index(&mut self, directory: WriterDirectory) -> Result<(), IndexError> {
         if let Ok(writer) = self.get_writer(directory.clone()) {
             writer.delete_all_documents()?;
@@ -112,25 +254,140 @@ impl Handler<WriterRequest> for Writer {
         match request {
             WriterRequest::Insert {
                 directory,
+                xid,
                 document,
-            } => self.insert(directory, document).map_err(ServerError::from),
+            } => self
+                .insert(directory, xid, document)
+                .map_err(ServerError::from),
             WriterRequest::Delete {
                 directory,
+                xid,
                 field,
                 ctids,
             } => self
-                .delete(directory, &field, &ctids)
+                .delete(directory, xid, &field, &ctids)
                 .map_err(ServerError::from),
             WriterRequest::DropIndex { directory } => {
                 self.drop_index(directory).map_err(ServerError::from)
             }
-            WriterRequest::Commit { directory } => {
+            WriterRequest::Commit { directory, .. } => {
                 self.commit(directory).map_err(ServerError::from)
             }
-            WriterRequest::Abort { directory } => self.abort(directory).map_err(ServerError::from),
+            WriterRequest::ClearJournal { directory, xid } => self
+                .clear_journal(directory, xid)
+                .map_err(ServerError::from),
+            // A prepared transaction that's rolled back is undone like any aborted one.
+            WriterRequest::RollbackPrepared { directory, xid }
+            | WriterRequest::Abort { directory, xid } => {
+                self.abort(directory, xid).map_err(ServerError::from)
+            }
             WriterRequest::Vacuum { directory } => {
                 self.vacuum(directory).map_err(ServerError::from)
             }
+            WriterRequest::Optimize {
+                directory,
+                max_segments,
+            } => self
+                .optimize(directory, max_segments)
+                .map_err(ServerError::from),
+            WriterRequest::PrepareCommit { directory } => {
+                self.prepare_commit(directory).map_err(ServerError::from)
+            }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::Writer;
+    use crate::{
+        fixtures::*,
+        index::{MergePolicyConfig, SearchIndex, SearchIndexSettings},
+        schema::{SearchDocument, SearchFieldConfig, SearchFieldName, SearchIndexSchema},
+        writer::{Handler, WriterRequest},
+    };
+    use anyhow::Result;
+
+    fn document(schema: &SearchIndexSchema, ctid: u64) -> SearchDocument {
+        let mut document = schema.new_document();
+        document.insert(schema.key_field().id, (ctid as i64).into());
+        document.insert(schema.ctid_field().id, ctid.into());
+        document
+    }
+
+    /// The index starts with four segments, of one to four documents, and one document of the
+    /// largest segment deleted. Whichever segments aren't merged together, the deleted document
+    /// is left out of the optimized index.
+    #[rstest]
+    #[case::merge_all(1, 1)]
+    #[case::at_least_one(0, 1)]
+    #[case::merge_smallest(3, 3)]
+    #[case::rewrite_deletes(4, 4)]
+    #[case::fewer_than_max(5, 4)]
+    fn test_optimize(
+        default_fields: Vec<(SearchFieldName, SearchFieldConfig)>,
+        #[case] max_segments: usize,
+        #[case] expected_segments: usize,
+    ) -> Result<()> {
+        let mock_dir = MockWriterDirectory::new("test_optimize");
+        let directory = mock_dir.writer_dir.clone();
+        // One indexing thread writes each commit's documents to a single segment, and the
+        // segments are only merged by optimizing.
+        let settings = SearchIndexSettings {
+            merge_policy: MergePolicyConfig {
+                no_merge: true,
+                ..Default::default()
+            },
+            writer_threads: Some(1),
+            ..Default::default()
+        };
+        let schema = SearchIndex::new(directory.clone(), default_fields, settings)?
+            .schema
+            .clone();
+
+        let mut writer = Writer::new();
+        let mut ctid = 0;
+        for num_docs in 1..=4 {
+            for _ in 0..num_docs {
+                ctid += 1;
+                writer.handle(WriterRequest::Insert {
+                    directory: directory.clone(),
+                    xid: 0,
+                    document: document(&schema, ctid),
+                })?;
+            }
+            writer.handle(WriterRequest::Commit {
+                directory: directory.clone(),
+                xid: 0,
+            })?;
+        }
+        writer.handle(WriterRequest::Delete {
+            directory: directory.clone(),
+            xid: 0,
+            field: schema.ctid_field().id.0,
+            ctids: vec![ctid],
+        })?;
+        writer.handle(WriterRequest::Commit {
+            directory: directory.clone(),
+            xid: 0,
+        })?;
+
+        assert_eq!(directory.last_merge_time(), None);
+        writer.handle(WriterRequest::Optimize {
+            directory: directory.clone(),
+            max_segments,
+        })?;
+        assert!(directory.last_merge_time().is_some());
+
+        let index: SearchIndex = directory.load_index()?;
+        let searcher = index.searcher();
+        assert_eq!(searcher.segment_readers().len(), expected_segments);
+        assert_eq!(searcher.num_docs(), 9);
+        assert!(searcher
+            .segment_readers()
+            .iter()
+            .all(|segment| segment.num_deleted_docs() == 0));
+
+        Ok(())
+    }
+}
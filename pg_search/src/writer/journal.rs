@@ -0,0 +1,437 @@
+use super::{IndexError, SearchDirectoryError, SearchIndexDirPath, WriterDirectory};
+use crate::index::SearchIndex;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashSet,
+    fs::{self, File},
+    io::Write,
+    path::{Path, PathBuf},
+};
+use tantivy::{schema::Field, IndexWriter};
+use tracing::{info, warn};
+
+static JOURNAL_DIR_NAME: &str = "journal";
+static JOURNAL_ENTRY_EXTENSION: &str = "json";
+static INVALID_FILE_NAME: &str = "invalid";
+
+/// The documents that a transaction has committed to an index. An entry is written before the
+/// documents are committed to Tantivy, which happens before Postgres commits the transaction,
+/// and it's removed once Postgres has committed. An entry that's left over after a crash
+/// belongs to a transaction whose documents may not match the outcome of the transaction.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct JournalEntry {
+    pub xid: u32,
+    pub ctid_field: Field,
+    pub ctids: Vec<u64>,
+}
+
+/// The outcome of a transaction, according to Postgres' transaction log.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransactionStatus {
+    Committed,
+    /// The transaction has been prepared for a two-phase commit, and is yet to be
+    /// committed or rolled back.
+    InProgress,
+    /// The transaction rolled back, or was still running when Postgres crashed.
+    Aborted,
+    /// The transaction is too old for its status to still be in the transaction log.
+    Unknown,
+}
+
+impl WriterDirectory {
+    /// Every index directory in the data directory of `postgres_data_dir_path`.
+    pub fn all(postgres_data_dir_path: &Path) -> Result<Vec<Self>, SearchDirectoryError> {
+        let search_dir_path = Self::search_dir_path(postgres_data_dir_path);
+        if !search_dir_path.exists() {
+            return Ok(vec![]);
+        }
+
+        let read_err = |err| SearchDirectoryError::ReadDirectoryEntry(search_dir_path.clone(), err);
+        let mut directories = vec![];
+        for entry in fs::read_dir(&search_dir_path).map_err(read_err)? {
+            let entry = entry.map_err(read_err)?;
+            let file_name = entry.file_name();
+            // Index directories are named after their database and index oids, and the
+            // directories used for other purposes, like transfer pipes, are skipped.
+            let oids = file_name
+                .to_str()
+                .and_then(|name| name.split_once('_'))
+                .and_then(|(db, index)| Some((db.parse().ok()?, index.parse().ok()?)));
+            if let Some((database_oid, index_oid)) = oids {
+                directories.push(Self {
+                    index_oid,
+                    database_oid,
+                    postgres_data_dir_path: postgres_data_dir_path.to_path_buf(),
+                });
+            }
+        }
+        Ok(directories)
+    }
+
+    fn journal_dir_path(&self, ensure_exists: bool) -> Result<PathBuf, SearchDirectoryError> {
+        let SearchIndexDirPath(index_path) = self.search_index_dir_path(ensure_exists)?;
+        let journal_dir_path = index_path.join(JOURNAL_DIR_NAME);
+        if ensure_exists {
+            Self::ensure_dir(&journal_dir_path)?;
+        }
+        Ok(journal_dir_path)
+    }
+
+    fn journal_entry_path(
+        &self,
+        xid: u32,
+        ensure_exists: bool,
+    ) -> Result<PathBuf, SearchDirectoryError> {
+        Ok(self
+            .journal_dir_path(ensure_exists)?
+            .join(format!("{xid}.{JOURNAL_ENTRY_EXTENSION}")))
+    }
+
+    /// Durably record the documents of a transaction, adding to the entry that the transaction
+    /// already has if it committed to the index before, like when it searched the index after
+    /// inserting into it.
+    pub fn write_journal_entry(&self, entry: &JournalEntry) -> Result<(), SearchDirectoryError> {
+        let entry_path = self.journal_entry_path(entry.xid, true)?;
+        let entry = match Self::read_journal_entry(&entry_path)? {
+            Some(mut existing) => {
+                // A commit that's replayed from the write-ahead log journals its documents again.
+                let journaled: HashSet<u64> = existing.ctids.iter().copied().collect();
+                existing
+                    .ctids
+                    .extend(entry.ctids.iter().filter(|ctid| !journaled.contains(ctid)));
+                existing
+            }
+            None => entry.clone(),
+        };
+
+        let serialized_data = serde_json::to_string(&entry)
+            .map_err(|err| SearchDirectoryError::JournalSerialize(entry_path.clone(), err))?;
+        let write_err = |err| SearchDirectoryError::JournalWrite(entry_path.clone(), err);
+
+        // The entry is written to a temporary file that's renamed into place, so that a crash
+        // while writing leaves either the previous entry or the new one.
+        let temp_path = entry_path.with_extension("tmp");
+        let mut file = File::create(&temp_path).map_err(write_err)?;
+        file.write_all(serialized_data.as_bytes())
+            .map_err(write_err)?;
+        file.sync_all().map_err(write_err)?;
+        fs::rename(&temp_path, &entry_path).map_err(write_err)?;
+        File::open(self.journal_dir_path(false)?)
+            .and_then(|dir| dir.sync_all())
+            .map_err(write_err)?;
+
+        Ok(())
+    }
+
+    /// Remove the entry of a transaction once its outcome is reflected in the index.
+    pub fn remove_journal_entry(&self, xid: u32) -> Result<(), SearchDirectoryError> {
+        let entry_path = self.journal_entry_path(xid, false)?;
+        match fs::remove_file(&entry_path) {
+            Err(err) if err.kind() != std::io::ErrorKind::NotFound => {
+                Err(SearchDirectoryError::RemoveFile(entry_path, err))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// The entry of transaction `xid`, if it has one.
+    pub fn journal_entry(&self, xid: u32) -> Result<Option<JournalEntry>, SearchDirectoryError> {
+        Self::read_journal_entry(&self.journal_entry_path(xid, false)?)
+    }
+
+    pub fn journal_entries(&self) -> Result<Vec<JournalEntry>, SearchDirectoryError> {
+        let journal_dir_path = self.journal_dir_path(false)?;
+        if !journal_dir_path.exists() {
+            return Ok(vec![]);
+        }
+
+        let read_err =
+            |err| SearchDirectoryError::ReadDirectoryEntry(journal_dir_path.clone(), err);
+        let mut entries = vec![];
+        for dir_entry in fs::read_dir(&journal_dir_path).map_err(read_err)? {
+            let entry_path = dir_entry.map_err(read_err)?.path();
+            // Temporary files are entries that were never completely written.
+            if entry_path.extension().and_then(|ext| ext.to_str()) != Some(JOURNAL_ENTRY_EXTENSION)
+            {
+                continue;
+            }
+            entries.extend(Self::read_journal_entry(&entry_path)?);
+        }
+        Ok(entries)
+    }
+
+    fn read_journal_entry(entry_path: &Path) -> Result<Option<JournalEntry>, SearchDirectoryError> {
+        let serialized_data = match fs::read_to_string(entry_path) {
+            Ok(data) => data,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(SearchDirectoryError::JournalRead(entry_path.into(), err)),
+        };
+        serde_json::from_str(&serialized_data)
+            .map(Some)
+            .map_err(|err| SearchDirectoryError::JournalDeserialize(entry_path.into(), err))
+    }
+
+    /// Mark the index as out of sync with its table. It can't be used until it's rebuilt,
+    /// which creates its directory anew.
+    pub fn mark_invalid(&self, reason: &str) -> Result<(), SearchDirectoryError> {
+        let SearchIndexDirPath(index_path) = self.search_index_dir_path(false)?;
+        let invalid_path = index_path.join(INVALID_FILE_NAME);
+        fs::write(&invalid_path, reason)
+            .map_err(|err| SearchDirectoryError::MarkInvalid(invalid_path, err))
+    }
+
+    /// Why the index was marked invalid, if it was.
+    pub fn invalid_reason(&self) -> Option<String> {
+        let SearchIndexDirPath(index_path) = self.search_index_dir_path(false).ok()?;
+        fs::read_to_string(index_path.join(INVALID_FILE_NAME)).ok()
+    }
+}
+
+/// Bring an index in line with the outcome of the transactions in its journal, after Postgres
+/// has restarted. The documents of transactions that committed are kept, and those of
+/// transactions that didn't are deleted. Prepared transactions keep their entries until they
+/// are resolved. If the index can't be reconciled, it's marked invalid.
+pub fn recover(directory: &WriterDirectory, status: impl Fn(u32) -> TransactionStatus) {
+    if let Err(err) = recover_journal(directory, status) {
+        warn!("could not recover bm25 index {directory:?}, it must be rebuilt: {err}");
+        if let Err(err) = directory.mark_invalid(&err.to_string()) {
+            warn!("could not mark bm25 index {directory:?} invalid: {err}");
+        }
+    }
+}
+
+fn recover_journal(
+    directory: &WriterDirectory,
+    status: impl Fn(u32) -> TransactionStatus,
+) -> Result<(), IndexError> {
+    let mut aborted = vec![];
+    for entry in directory.journal_entries().map_err(IndexError::Journal)? {
+        match status(entry.xid) {
+            TransactionStatus::Committed => directory
+                .remove_journal_entry(entry.xid)
+                .map_err(IndexError::Journal)?,
+            TransactionStatus::InProgress => {}
+            TransactionStatus::Aborted => aborted.push(entry),
+            TransactionStatus::Unknown => return Err(IndexError::UnknownTransaction(entry.xid)),
+        }
+    }
+
+    if aborted.is_empty() {
+        return Ok(());
+    }
+
+    let mut writer = SearchIndex::writer(directory)
+        .map_err(|err| IndexError::GetWriterFailed(directory.clone(), err.to_string()))?;
+    for entry in &aborted {
+        info!(
+            "removing {} documents of aborted transaction {} from bm25 index {directory:?}",
+            entry.ctids.len(),
+            entry.xid
+        );
+        delete_documents(&mut writer, entry);
+    }
+    writer.commit()?;
+
+    for entry in &aborted {
+        directory
+            .remove_journal_entry(entry.xid)
+            .map_err(IndexError::Journal)?;
+    }
+    Ok(())
+}
+
+/// Delete the documents of a journal entry. They're removed once the writer commits.
+pub(super) fn delete_documents(writer: &mut IndexWriter, entry: &JournalEntry) {
+    for ctid in &entry.ctids {
+        writer.delete_term(tantivy::Term::from_field_u64(entry.ctid_field, *ctid));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{recover, JournalEntry, TransactionStatus};
+    use crate::{
+        fixtures::*,
+        index::SearchIndex,
+        writer::{WriterClient, WriterRequest},
+    };
+    use anyhow::Result;
+    use rstest::*;
+    use tantivy::schema::Field;
+
+    #[rstest]
+    fn test_journal_entry_merge(mock_dir: MockWriterDirectory) -> Result<()> {
+        let directory = &mock_dir.writer_dir;
+        let entry = JournalEntry {
+            xid: 7,
+            ctid_field: Field::from_field_id(1),
+            ctids: vec![1, 2],
+        };
+
+        // A transaction that commits to the index twice keeps a single entry, with each of
+        // its documents once.
+        directory.write_journal_entry(&entry)?;
+        directory.write_journal_entry(&JournalEntry {
+            ctids: vec![2, 3],
+            ..entry.clone()
+        })?;
+        assert_eq!(
+            directory.journal_entries()?,
+            vec![JournalEntry {
+                ctids: vec![1, 2, 3],
+                ..entry
+            }]
+        );
+
+        directory.remove_journal_entry(7)?;
+        assert!(directory.journal_entries()?.is_empty());
+
+        Ok(())
+    }
+
+    #[rstest]
+    #[case::committed(TransactionStatus::Committed, 1, 0)]
+    #[case::aborted(TransactionStatus::Aborted, 0, 0)]
+    #[case::in_progress(TransactionStatus::InProgress, 1, 1)]
+    fn test_recover(
+        default_index: MockSearchIndex,
+        #[case] status: TransactionStatus,
+        #[case] expected_docs: u64,
+        #[case] expected_entries: usize,
+    ) -> Result<()> {
+        let client = TestClient::new_arc();
+        let index = default_index.index;
+        let directory = index.directory.clone();
+
+        index.insert(&client, 7, simple_doc(simple_schema(default_fields())))?;
+        client.lock().unwrap().request(WriterRequest::Commit {
+            directory: directory.clone(),
+            xid: 7,
+        })?;
+        assert_eq!(directory.journal_entries()?.len(), 1);
+
+        recover(&directory, |_| status);
+
+        let recovered: SearchIndex = directory.load_index()?;
+        assert_eq!(recovered.searcher().num_docs(), expected_docs);
+        assert_eq!(directory.journal_entries()?.len(), expected_entries);
+        assert_eq!(directory.invalid_reason(), None);
+
+        Ok(())
+    }
+
+    #[rstest]
+    #[case::commit_prepared(false, 1)]
+    #[case::rollback_prepared(true, 0)]
+    fn test_resolve_prepared(
+        default_index: MockSearchIndex,
+        #[case] rollback: bool,
+        #[case] expected_docs: u64,
+    ) -> Result<()> {
+        let client = TestClient::new_arc();
+        let index = default_index.index;
+        let directory = index.directory.clone();
+
+        index.insert(&client, 7, simple_doc(simple_schema(default_fields())))?;
+        client.lock().unwrap().request(WriterRequest::Commit {
+            directory: directory.clone(),
+            xid: 7,
+        })?;
+        assert!(directory.journal_entry(7)?.is_some());
+
+        let request = if rollback {
+            WriterRequest::RollbackPrepared {
+                directory: directory.clone(),
+                xid: 7,
+            }
+        } else {
+            WriterRequest::ClearJournal {
+                directory: directory.clone(),
+                xid: 7,
+            }
+        };
+        client.lock().unwrap().request(request)?;
+
+        let resolved: SearchIndex = directory.load_index()?;
+        assert_eq!(resolved.searcher().num_docs(), expected_docs);
+        assert_eq!(directory.journal_entry(7)?, None);
+
+        Ok(())
+    }
+
+    #[rstest]
+    fn test_journal_per_transaction(default_index: MockSearchIndex) -> Result<()> {
+        let client = TestClient::new_arc();
+        let mut index = default_index.index;
+        let directory = index.directory.clone();
+        let document = |ctid: u64| {
+            let mut document = index.schema.new_document();
+            document.insert(index.schema.key_field().id, (ctid as i64).into());
+            document.insert(index.schema.ctid_field().id, ctid.into());
+            document
+        };
+        let (first, second, third) = (document(0), document(1), document(2));
+
+        // Transaction 7's commit also commits transaction 8's document, which is journaled
+        // under transaction 8.
+        index.insert(&client, 7, first)?;
+        index.insert(&client, 8, second)?;
+        client.lock().unwrap().request(WriterRequest::Commit {
+            directory: directory.clone(),
+            xid: 7,
+        })?;
+        assert_eq!(
+            directory.journal_entry(7)?.map(|entry| entry.ctids),
+            Some(vec![0])
+        );
+        assert_eq!(
+            directory.journal_entry(8)?.map(|entry| entry.ctids),
+            Some(vec![1])
+        );
+
+        // Aborting transaction 8 removes its committed document, and leaves transaction 7's.
+        client.lock().unwrap().request(WriterRequest::Abort {
+            directory: directory.clone(),
+            xid: 8,
+        })?;
+        let aborted: SearchIndex = directory.load_index()?;
+        assert_eq!(aborted.searcher().num_docs(), 1);
+        assert_eq!(directory.journal_entry(8)?, None);
+
+        // A transaction that aborts before its documents are committed leaves nothing behind.
+        index.insert(&client, 9, third)?;
+        client.lock().unwrap().request(WriterRequest::Abort {
+            directory: directory.clone(),
+            xid: 9,
+        })?;
+        client.lock().unwrap().request(WriterRequest::Commit {
+            directory: directory.clone(),
+            xid: 7,
+        })?;
+        let committed: SearchIndex = directory.load_index()?;
+        assert_eq!(committed.searcher().num_docs(), 1);
+        assert_eq!(directory.journal_entry(9)?, None);
+        assert_eq!(
+            directory.journal_entry(7)?.map(|entry| entry.ctids),
+            Some(vec![0])
+        );
+
+        Ok(())
+    }
+
+    #[rstest]
+    fn test_recover_unknown_transaction(default_index: MockSearchIndex) -> Result<()> {
+        let directory = default_index.index.directory.clone();
+        directory.write_journal_entry(&JournalEntry {
+            xid: 7,
+            ctid_field: Field::from_field_id(1),
+            ctids: vec![0],
+        })?;
+
+        recover(&directory, |_| TransactionStatus::Unknown);
+
+        assert!(directory.invalid_reason().is_some());
+
+        Ok(())
+    }
+}
@@ -1,6 +1,7 @@
 mod client;
 mod directory;
 mod index;
+mod journal;
 mod server;
 mod transfer;
 
@@ -8,37 +9,98 @@ use crate::schema::SearchDocument;
 pub use client::{Client, ClientError};
 pub use directory::*;
 pub use index::Writer;
+pub use journal::{recover, JournalEntry, TransactionStatus};
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
 pub use server::{Server, ServerError};
+use std::hash::Hash;
 use std::path::Path;
 use tantivy::schema::Field;
 use thiserror::Error;
+pub use transfer::WriterTransferProducer;
 
 // A layer of the client-server request structure that handles
 // details about the action to be performed by the index writer.
 #[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
 pub enum WriterRequest {
+    /// Add a document on behalf of Postgres transaction `xid`, which is 0 if the document
+    /// doesn't belong to a transaction.
     Insert {
         directory: WriterDirectory,
+        xid: u32,
         document: SearchDocument,
     },
+    /// Delete documents by ctid on behalf of Postgres transaction `xid`, which is 0 if the
+    /// delete doesn't belong to a transaction.
     Delete {
         directory: WriterDirectory,
+        xid: u32,
         field: Field,
         ctids: Vec<u64>,
     },
     DropIndex {
         directory: WriterDirectory,
     },
+    /// Remove the documents that Postgres transaction `xid` added, once it has aborted. An
+    /// `xid` of 0 discards every document added since the last commit.
     Abort {
         directory: WriterDirectory,
+        xid: u32,
     },
+    /// Commit the documents added so far on behalf of Postgres transaction `xid`. Tantivy
+    /// commits the documents of every transaction at once, so each transaction's documents
+    /// are journaled under its own xid first, to be removed if that transaction doesn't commit.
     Commit {
         directory: WriterDirectory,
+        xid: u32,
+    },
+    /// Forget the journaled documents of transaction `xid`, once Postgres has committed it.
+    ClearJournal {
+        directory: WriterDirectory,
+        xid: u32,
+    },
+    /// Remove the journaled documents of prepared transaction `xid`, once Postgres has
+    /// rolled it back.
+    RollbackPrepared {
+        directory: WriterDirectory,
+        xid: u32,
     },
     Vacuum {
         directory: WriterDirectory,
     },
+    /// Merge the index's committed segments until it has at most `max_segments` of them.
+    Optimize {
+        directory: WriterDirectory,
+        max_segments: usize,
+    },
+    /// Write the documents added so far into segments, without committing them, so that
+    /// the commit at the end of the transaction has little left to do.
+    PrepareCommit {
+        directory: WriterDirectory,
+    },
+}
+
+impl Routed for WriterRequest {
+    type Route = WriterDirectory;
+
+    fn route(&self) -> WriterDirectory {
+        match self {
+            WriterRequest::Insert { directory, .. }
+            | WriterRequest::Delete { directory, .. }
+            | WriterRequest::DropIndex { directory }
+            | WriterRequest::Abort { directory, .. }
+            | WriterRequest::Commit { directory, .. }
+            | WriterRequest::ClearJournal { directory, .. }
+            | WriterRequest::RollbackPrepared { directory, .. }
+            | WriterRequest::Vacuum { directory }
+            | WriterRequest::Optimize { directory, .. }
+            | WriterRequest::PrepareCommit { directory } => directory.clone(),
+        }
+    }
+
+    fn ends_route(&self) -> bool {
+        // Nothing is left to do for an index that's dropped.
+        matches!(self, WriterRequest::DropIndex { .. })
+    }
 }
 
 // A layer of the client-server request structure that handles
@@ -49,11 +111,22 @@ enum ServerRequest<T: Serialize> {
     Request(T),
     /// Initiate a data transfer using the pipe path given.
     Transfer(String),
+    /// Initiate data transfers from several producers at once, one for each pipe path given.
+    /// The transfer ends when every producer is done.
+    ParallelTransfer(Vec<String>),
     /// Close the writer server, should only be called by
     /// shutdown background worker.
     Shutdown,
 }
 
+/// The server's reply to a `ServerRequest`. Transfers are acknowledged before the server
+/// starts reading from their pipes.
+#[derive(Deserialize, Serialize)]
+enum ServerResponse {
+    Ok,
+    Error(String),
+}
+
 /// This trait is the interface that binds the writer to the server.
 /// The two systems are otherwise decoupled, so they can be tested
 /// and re-used independently.
@@ -61,6 +134,21 @@ pub trait Handler<T: DeserializeOwned> {
     fn handle(&mut self, request: T) -> Result<(), ServerError>;
 }
 
+/// Decides which of the server's queues handles a request. Requests with the same route are
+/// handled in the order they arrive by the same handler, and requests with different routes
+/// are handled concurrently.
+pub trait Routed {
+    type Route: Eq + Hash + Clone + Send + 'static;
+
+    fn route(&self) -> Self::Route;
+
+    /// Whether this is the last request for its route, after which the route's handler is
+    /// dropped. A later request for the route is handled by a new handler.
+    fn ends_route(&self) -> bool {
+        false
+    }
+}
+
 pub trait WriterClient<T: Serialize> {
     fn request(&mut self, request: T) -> Result<(), ClientError>;
 
@@ -92,6 +180,12 @@ pub enum IndexError {
 
     #[error("couldn't remove index files on drop_index: {0}")]
     DeleteDirectory(#[from] SearchDirectoryError),
+
+    #[error("couldn't update the index journal: {0}")]
+    Journal(SearchDirectoryError),
+
+    #[error("the outcome of transaction {0} in the index journal is unknown")]
+    UnknownTransaction(u32),
 }
 
 #[cfg(test)]
@@ -109,6 +203,7 @@ mod tests {
         // Setup insert writer request.
         let insert_request = WriterRequest::Insert {
             directory: mock_dir.writer_dir.clone(),
+            xid: 7,
             document,
         };
 
@@ -121,6 +216,7 @@ mod tests {
         // Setup delete writer request.
         let delete_request = WriterRequest::Delete {
             directory: mock_dir.writer_dir.clone(),
+            xid: 7,
             field: Field::from_field_id(100),
             ctids: vec![99, 98, 97],
         };
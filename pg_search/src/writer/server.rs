@@ -1,120 +1,415 @@
-use super::{Handler, IndexError, ServerRequest};
+use super::{Handler, IndexError, Routed, ServerRequest, ServerResponse};
 use crate::writer::transfer;
 use serde::de::DeserializeOwned;
 use serde::Serialize;
-use std::io;
-use std::marker::PhantomData;
-use std::path::Path;
-use std::{cell::RefCell, io::Cursor};
+use std::collections::HashMap;
+use std::fs::{self, Permissions};
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::io::AsRawFd;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
 use thiserror::Error;
-use tracing::{error, info};
+use tracing::{error, info, warn};
 
 /// A generic server for receiving requests and transfers from a client.
-pub struct Server<'a, T, H>
+///
+/// The server listens on a Unix domain socket, and only accepts connections from processes
+/// running as the same operating system user as itself, which are the Postgres backends.
+///
+/// Each connection is served on its own thread. Requests are handled by a queue per route,
+/// each with its own handler and thread, so requests for one index are handled in the order
+/// they arrive, while requests for different indexes are handled concurrently.
+pub struct Server<T, H>
 where
-    T: DeserializeOwned,
+    T: Routed + DeserializeOwned,
     H: Handler<T>,
 {
-    addr: std::net::SocketAddr,
-    http: tiny_http::Server,
-    handler: RefCell<H>,
-    marker: PhantomData<&'a T>,
+    socket_path: PathBuf,
+    listener: UnixListener,
+    queues: Arc<Queues<T, H>>,
 }
 
-impl<'a, T, H> Server<'a, T, H>
+/// The number of requests that can wait to be handled by the queue of a single route.
+/// Clients and transfers block once it's reached, so that a slow handler doesn't buffer
+/// an entire index build.
+const REQUEST_QUEUE_SIZE: usize = 1024;
+
+impl<T, H> Server<T, H>
 where
-    T: Serialize + DeserializeOwned + 'a,
-    H: Handler<T>,
+    T: Routed + Serialize + DeserializeOwned + Send + 'static,
+    H: Handler<T> + Send + 'static,
 {
-    pub fn new(handler: H) -> Result<Self, ServerError> {
-        let http = tiny_http::Server::http("0.0.0.0:0")
-            .map_err(|err| ServerError::AddressBindFailed(err.to_string()))?;
-
-        let addr = match http.server_addr() {
-            tiny_http::ListenAddr::IP(addr) => addr,
-            // It's not clear when tiny_http would choose to use a Unix socket address,
-            // but we have to handle the enum variant, so we'll consider this outcome
-            // an irrecovereable error, although its not expected to happen.
-            tiny_http::ListenAddr::Unix(addr) => {
-                return Err(ServerError::UnixSocketBindAttempt(format!("{addr:?}")))
-            }
-        };
+    /// Create a server listening on `socket_path`. `new_handler` is called to create the
+    /// handler of each route's queue when its first request arrives.
+    pub fn new<P, F>(socket_path: P, new_handler: F) -> Result<Self, ServerError>
+    where
+        P: AsRef<Path>,
+        F: Fn() -> H + Send + Sync + 'static,
+    {
+        let socket_path = socket_path.as_ref().to_path_buf();
+        let bind_err = |err| ServerError::SocketBindFailed(socket_path.clone(), err);
+
+        if let Some(parent) = socket_path.parent() {
+            fs::create_dir_all(parent).map_err(bind_err)?;
+        }
+
+        // A socket file left behind by a writer that didn't shut down cleanly would
+        // otherwise make binding fail.
+        match fs::remove_file(&socket_path) {
+            Err(err) if err.kind() != std::io::ErrorKind::NotFound => return Err(bind_err(err)),
+            _ => {}
+        }
+
+        let listener = UnixListener::bind(&socket_path).map_err(bind_err)?;
+        fs::set_permissions(&socket_path, Permissions::from_mode(0o600)).map_err(bind_err)?;
 
         Ok(Self {
-            addr,
-            http,
-            handler: RefCell::new(handler),
-            marker: PhantomData,
+            socket_path,
+            listener,
+            queues: Arc::new(Queues {
+                senders: Arc::new(Mutex::new(HashMap::new())),
+                new_handler: Box::new(new_handler),
+            }),
         })
     }
 
-    pub fn addr(&self) -> std::net::SocketAddr {
-        self.addr
+    pub fn socket_path(&self) -> &Path {
+        &self.socket_path
     }
 
     pub fn start(&mut self) -> Result<(), ServerError> {
         self.listen_request()
     }
 
-    fn listen_transfer<P: AsRef<Path>>(&self, pipe_path: P) -> Result<(), ServerError> {
+    fn respond(stream: &mut UnixStream, response: ServerResponse) {
+        if let Err(err) = bincode::serialize_into(stream, &response) {
+            error!("server error responding to client: {err}");
+        }
+    }
+
+    fn listen_request(&mut self) -> Result<(), ServerError> {
+        info!("listening to incoming requests at {:?}", self.socket_path);
+        for incoming in self.listener.incoming() {
+            let mut stream = match incoming {
+                Ok(stream) => stream,
+                Err(err) => {
+                    error!("server error accepting connection: {err}");
+                    continue;
+                }
+            };
+
+            if let Err(err) = check_peer_credentials(&stream) {
+                warn!("writer server rejected connection: {err}");
+                Self::respond(&mut stream, ServerResponse::Error(err.to_string()));
+                continue;
+            }
+
+            let request: ServerRequest<T> = match bincode::deserialize_from(&mut stream) {
+                Ok(request) => request,
+                Err(err) => {
+                    Self::respond(&mut stream, ServerResponse::Error(err.to_string()));
+                    continue;
+                }
+            };
+
+            if let ServerRequest::Shutdown = request {
+                Self::respond(&mut stream, ServerResponse::Ok);
+                return Ok(());
+            }
+
+            // A request can wait on a busy queue, and a transfer lasts as long as its producers
+            // keep writing, so neither may hold up accepting connections for other indexes.
+            let queues = self.queues.clone();
+            let spawned = thread::Builder::new()
+                .name("pg_search_connection".into())
+                .spawn(move || Self::serve(&queues, stream, request));
+            if let Err(err) = spawned {
+                error!("server error spawning connection thread: {err}");
+            }
+        }
+
+        unreachable!("server should never stop listening");
+    }
+
+    fn serve(queues: &Queues<T, H>, mut stream: UnixStream, request: ServerRequest<T>) {
+        match request {
+            ServerRequest::Request(req) => {
+                let (reply, result) = mpsc::channel();
+                let response = match queues
+                    .dispatch(req, reply)
+                    .and_then(|_| result.recv().unwrap_or(Err(ServerError::QueueClosed)))
+                {
+                    Ok(()) => ServerResponse::Ok,
+                    Err(err) => ServerResponse::Error(err.to_string()),
+                };
+                Self::respond(&mut stream, response);
+            }
+            ServerRequest::Transfer(pipe_path) => {
+                // We must respond with OK before initiating the transfer, and respond again
+                // once it's done, so that the client knows its requests have been handled,
+                // or that one of them failed, in which case its transaction mustn't commit.
+                Self::respond(&mut stream, ServerResponse::Ok);
+                let response = match Self::listen_transfer(queues, pipe_path) {
+                    Ok(()) => ServerResponse::Ok,
+                    Err(err) => {
+                        error!("error listening to transfer: {err}");
+                        ServerResponse::Error(err.to_string())
+                    }
+                };
+                Self::respond(&mut stream, response);
+            }
+            ServerRequest::ParallelTransfer(pipe_paths) => {
+                // As with a transfer, we must respond with OK before listening, and respond
+                // again once done, with the error that the transfer failed with, if any, so
+                // that the index build fails.
+                Self::respond(&mut stream, ServerResponse::Ok);
+                let response = match Self::listen_parallel_transfer(queues, pipe_paths) {
+                    Ok(()) => ServerResponse::Ok,
+                    Err(err) => {
+                        error!("error listening to parallel transfer: {err}");
+                        ServerResponse::Error(err.to_string())
+                    }
+                };
+                Self::respond(&mut stream, response);
+            }
+            ServerRequest::Shutdown => unreachable!("shutdown is handled by the listener"),
+        }
+    }
+
+    /// Send every request from a producer's pipe to its queue, without waiting for them to be
+    /// handled, so that requests for different indexes in the same transfer are handled
+    /// concurrently.
+    fn forward_transfer<P: AsRef<Path>>(
+        queues: &Queues<T, H>,
+        pipe_path: P,
+        reply: &Reply,
+    ) -> Result<(), ServerError> {
         // Our consumer will receive messages suitable for our handler.
         for incoming in transfer::read_stream::<T, P>(pipe_path)? {
-            self.handler.borrow_mut().handle(incoming?)?;
+            queues.dispatch(incoming?, reply.clone())?;
         }
         Ok(())
     }
 
-    fn response_ok() -> tiny_http::Response<io::Empty> {
-        tiny_http::Response::empty(200)
+    fn listen_transfer<P: AsRef<Path>>(
+        queues: &Queues<T, H>,
+        pipe_path: P,
+    ) -> Result<(), ServerError> {
+        let (reply, results) = mpsc::channel();
+        let forwarded = Self::forward_transfer(queues, pipe_path, &reply);
+        drop(reply);
+        let handled = wait_for_results(results);
+        forwarded.and(handled)
     }
 
-    fn response_err(err: ServerError) -> tiny_http::Response<Cursor<Vec<u8>>> {
-        tiny_http::Response::from_string(err.to_string()).with_status_code(500)
+    /// Read from the pipe of every producer at once, each on its own thread. Reading continues
+    /// after a reader or handler error, so that producers aren't left blocked on a full pipe,
+    /// and the first error is returned once every producer is done and its requests are
+    /// handled.
+    fn listen_parallel_transfer(
+        queues: &Queues<T, H>,
+        pipe_paths: Vec<String>,
+    ) -> Result<(), ServerError> {
+        let (reply, results) = mpsc::channel();
+
+        let forwarded = thread::scope(|scope| {
+            let readers: Vec<_> = pipe_paths
+                .iter()
+                .map(|pipe_path| {
+                    let reply = &reply;
+                    scope.spawn(move || Self::forward_transfer(queues, pipe_path, reply))
+                })
+                .collect();
+
+            let mut first = Ok(());
+            for reader in readers {
+                let result = reader.join().unwrap_or_else(|_| {
+                    Err(ServerError::Unexpected(
+                        "parallel transfer reader panicked".into(),
+                    ))
+                });
+                if first.is_ok() {
+                    first = result;
+                }
+            }
+            first
+        });
+
+        drop(reply);
+        let handled = wait_for_results(results);
+        forwarded.and(handled)
     }
+}
 
-    fn listen_request(&mut self) -> Result<(), ServerError> {
-        info!("listening to incoming requests at {:?}", self.addr);
-        for mut incoming in self.http.incoming_requests() {
-            let reader = incoming.as_reader();
-            let request: Result<ServerRequest<T>, ServerError> = bincode::deserialize_from(reader)
-                .map_err(|err| ServerError::Unexpected(err.into()));
-
-            match request {
-                Ok(req) => match req {
-                    ServerRequest::Shutdown => {
-                        if let Err(err) = incoming.respond(Self::response_ok()) {
-                            error!("server error responding to shutdown: {err}");
-                        }
-                        return Ok(());
-                    }
-                    ServerRequest::Transfer(pipe_path) => {
-                        // We must respond with OK before initiating the transfer.
-                        if let Err(err) = incoming.respond(Self::response_ok()) {
-                            error!("server error responding to transfer: {err}");
-                        } else if let Err(err) = self.listen_transfer(pipe_path) {
-                            error!("error listening to transfer: {err}")
-                        }
-                    }
-                    ServerRequest::Request(req) => {
-                        if let Err(err) = self.handler.borrow_mut().handle(req) {
-                            if let Err(err) = incoming.respond(Self::response_err(err)) {
-                                error!("server error responding to handler error: {err}");
-                            }
-                        } else if let Err(err) = incoming.respond(Self::response_ok()) {
-                            error!("server error responding to handler success: {err}")
-                        }
-                    }
-                },
-                Err(err) => {
-                    if let Err(err) = incoming.respond(Self::response_err(err)) {
-                        error!("server error responding to client on deserialize error: {err}");
+/// Wait for every forwarded request to be handled, which is when the queues have dropped every
+/// `Reply` sender, and return the first error.
+fn wait_for_results(results: mpsc::Receiver<Result<(), ServerError>>) -> Result<(), ServerError> {
+    let mut first = Ok(());
+    for result in results {
+        if first.is_ok() {
+            first = result;
+        }
+    }
+    first
+}
+
+impl<T, H> Drop for Server<T, H>
+where
+    T: Routed + DeserializeOwned,
+    H: Handler<T>,
+{
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.socket_path);
+    }
+}
+
+/// Where a queue sends the result of handling a request.
+type Reply = mpsc::Sender<Result<(), ServerError>>;
+
+struct Job<T> {
+    request: T,
+    reply: Reply,
+}
+
+type Senders<T> = Mutex<HashMap<<T as Routed>::Route, mpsc::SyncSender<Job<T>>>>;
+
+/// The request queues of a server, one for each route that has received a request. A queue
+/// stops after the last request of its route, or once its handler panics. The requests that
+/// were already sent to it fail, and the next request for the route starts a new queue, once
+/// the old handler is gone, so a route is never handled by two handlers at once.
+struct Queues<T: Routed, H> {
+    senders: Arc<Senders<T>>,
+    new_handler: Box<dyn Fn() -> H + Send + Sync>,
+}
+
+impl<T, H> Queues<T, H>
+where
+    T: Routed + DeserializeOwned + Send + 'static,
+    H: Handler<T> + Send + 'static,
+{
+    /// Add a request to the queue of its route, starting the queue if it's the first request
+    /// for the route. Blocks while the queue is full.
+    fn dispatch(&self, request: T, reply: Reply) -> Result<(), ServerError> {
+        let sender = {
+            let mut senders = self.senders.lock().map_err(|_| ServerError::QueueClosed)?;
+            let route = request.route();
+            match senders.get(&route) {
+                Some(sender) => sender.clone(),
+                None => {
+                    let sender = self.spawn_queue(route.clone())?;
+                    senders.insert(route, sender.clone());
+                    sender
+                }
+            }
+        };
+
+        // The lock is released before sending, so that a full queue only blocks its own route.
+        sender
+            .send(Job { request, reply })
+            .map_err(|_| ServerError::QueueClosed)
+    }
+
+    fn spawn_queue(&self, route: T::Route) -> Result<mpsc::SyncSender<Job<T>>, ServerError> {
+        let (sender, receiver) = mpsc::sync_channel::<Job<T>>(REQUEST_QUEUE_SIZE);
+        let mut handler = (self.new_handler)();
+        let senders = self.senders.clone();
+
+        thread::Builder::new()
+            .name("pg_search_queue".into())
+            .spawn(move || {
+                for Job { request, reply } in &receiver {
+                    let ends_route = request.ends_route();
+                    let result = handle(&mut handler, request);
+                    let panicked = matches!(result, Err(ServerError::HandlerPanicked(_)));
+                    // The requester may have gone away, in which case nobody needs the result.
+                    let _ = reply.send(result);
+                    // A handler that panicked may be left in any state, so it isn't used for
+                    // the route's next requests.
+                    if ends_route || panicked {
+                        break;
                     }
                 }
-            };
-        }
 
-        unreachable!("server should never stop listening");
+                // The handler is dropped before the route's next requests can start a new queue,
+                // so that its writers are closed before a new handler opens them. The requests
+                // that were already sent to this queue aren't handled, so they can't run out of
+                // order with the new queue's, and fail instead.
+                drop(handler);
+                if let Ok(mut senders) = senders.lock() {
+                    senders.remove(&route);
+                }
+                for Job { reply, .. } in receiver {
+                    let _ = reply.send(Err(ServerError::QueueClosed));
+                }
+            })?;
+
+        Ok(sender)
+    }
+}
+
+/// Handle a request, turning a panic of the handler into an error, so that it doesn't take
+/// down the queue's thread with it.
+fn handle<T, H: Handler<T>>(handler: &mut H, request: T) -> Result<(), ServerError>
+where
+    T: DeserializeOwned,
+{
+    panic::catch_unwind(AssertUnwindSafe(|| handler.handle(request))).unwrap_or_else(|payload| {
+        let message = payload
+            .downcast_ref::<&str>()
+            .map(|message| message.to_string())
+            .or_else(|| payload.downcast_ref::<String>().cloned())
+            .unwrap_or_default();
+        Err(ServerError::HandlerPanicked(message))
+    })
+}
+
+/// Only accept connections from processes running as the same user as the server. The socket
+/// file is already private to that user, but this doesn't rely on the permissions of the
+/// directories above it.
+fn check_peer_credentials(stream: &UnixStream) -> Result<(), ServerError> {
+    let peer_uid = peer_uid(stream).map_err(ServerError::PeerCredentials)?;
+    let server_uid = unsafe { libc::geteuid() };
+    if peer_uid != server_uid {
+        return Err(ServerError::UnauthorizedPeer(peer_uid));
+    }
+    Ok(())
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn peer_uid(stream: &UnixStream) -> std::io::Result<libc::uid_t> {
+    let mut cred = libc::ucred {
+        pid: 0,
+        uid: 0,
+        gid: 0,
+    };
+    let mut len = std::mem::size_of::<libc::ucred>() as libc::socklen_t;
+    let ret = unsafe {
+        libc::getsockopt(
+            stream.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_PEERCRED,
+            &mut cred as *mut libc::ucred as *mut libc::c_void,
+            &mut len,
+        )
+    };
+    if ret != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(cred.uid)
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+fn peer_uid(stream: &UnixStream) -> std::io::Result<libc::uid_t> {
+    let mut uid: libc::uid_t = 0;
+    let mut gid: libc::gid_t = 0;
+    let ret = unsafe { libc::getpeereid(stream.as_raw_fd(), &mut uid, &mut gid) };
+    if ret != 0 {
+        return Err(std::io::Error::last_os_error());
     }
+    Ok(uid)
 }
 
 #[derive(Error, Debug)]
@@ -122,11 +417,17 @@ pub enum ServerError {
     #[error("couldn't open the consumer pipe file: {0}")]
     OpenPipeFile(std::io::Error),
 
-    #[error("error binding writer server to address: {0}")]
-    AddressBindFailed(String),
+    #[error("the producer of transfer pipe {0:?} did not connect in time")]
+    TransferTimeout(PathBuf),
+
+    #[error("error binding writer server to socket {0:?}: {1}")]
+    SocketBindFailed(PathBuf, std::io::Error),
 
-    #[error("writer server must not bind to unix socket, attemped: {0}")]
-    UnixSocketBindAttempt(String),
+    #[error("could not read the credentials of the connecting process: {0}")]
+    PeerCredentials(std::io::Error),
+
+    #[error("connection from user id {0} is not allowed, only the postgres user may connect")]
+    UnauthorizedPeer(libc::uid_t),
 
     #[error(transparent)]
     WriterError(#[from] IndexError),
@@ -134,29 +435,67 @@ pub enum ServerError {
     #[error(transparent)]
     IOError(#[from] std::io::Error),
 
-    #[error(transparent)]
-    ReqwestError(#[from] reqwest::Error),
-
     #[error(transparent)]
     SerdeJson(#[from] serde_json::Error),
 
     #[error(transparent)]
     Bincode(#[from] bincode::Error),
 
+    #[error("writer server request queue has stopped")]
+    QueueClosed,
+
+    #[error("writer server request handler panicked: {0}")]
+    HandlerPanicked(String),
+
     #[error("unexpected error: {0}")]
-    Unexpected(#[from] Box<dyn std::error::Error>),
+    Unexpected(#[from] Box<dyn std::error::Error + Send + Sync>),
 }
 
 #[cfg(test)]
 mod tests {
+    use super::{check_peer_credentials, Queues, ServerError};
     use crate::{
         fixtures::*,
         schema::{SearchDocument, SearchIndexSchema},
+        writer::{Handler, WriterRequest},
     };
     use anyhow::Result;
     use rstest::*;
+    use std::collections::HashMap;
+    use std::os::unix::net::UnixStream;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{mpsc, Arc, Mutex};
     use tantivy::Index;
 
+    /// Panics on a vacuum once it's let through, and counts the handlers that are alive.
+    struct PanickingHandler {
+        vacuum_gate: Arc<Mutex<mpsc::Receiver<()>>>,
+        alive: Arc<AtomicUsize>,
+    }
+
+    impl Handler<WriterRequest> for PanickingHandler {
+        fn handle(&mut self, request: WriterRequest) -> Result<(), ServerError> {
+            if let WriterRequest::Vacuum { .. } = request {
+                self.vacuum_gate.lock().unwrap().recv().unwrap();
+                panic!("vacuum failed");
+            }
+            Ok(())
+        }
+    }
+
+    impl Drop for PanickingHandler {
+        fn drop(&mut self) {
+            self.alive.fetch_sub(1, Ordering::SeqCst);
+        }
+    }
+
+    #[rstest]
+    fn test_peer_credentials_same_user() -> Result<()> {
+        let (stream, _peer) = UnixStream::pair()?;
+        check_peer_credentials(&stream)?;
+        Ok(())
+    }
+
     #[rstest]
     fn test_index_commit(
         simple_schema: SearchIndexSchema,
@@ -175,4 +514,59 @@ mod tests {
 
         Ok(())
     }
+
+    #[rstest]
+    fn test_queue_after_panic(mock_dir: MockWriterDirectory) {
+        let (open_gate, vacuum_gate) = mpsc::channel();
+        let vacuum_gate = Arc::new(Mutex::new(vacuum_gate));
+        let alive = Arc::new(AtomicUsize::new(0));
+        let max_alive = Arc::new(AtomicUsize::new(0));
+        let queues = Queues {
+            senders: Arc::new(Mutex::new(HashMap::new())),
+            new_handler: Box::new({
+                let alive = alive.clone();
+                let max_alive = max_alive.clone();
+                move || {
+                    let now_alive = alive.fetch_add(1, Ordering::SeqCst) + 1;
+                    max_alive.fetch_max(now_alive, Ordering::SeqCst);
+                    PanickingHandler {
+                        vacuum_gate: vacuum_gate.clone(),
+                        alive: alive.clone(),
+                    }
+                }
+            }),
+        };
+        let directory = mock_dir.writer_dir;
+
+        // The commit is queued behind the vacuum before the vacuum panics.
+        let (vacuum_reply, vacuum_result) = mpsc::channel();
+        let vacuum = WriterRequest::Vacuum {
+            directory: directory.clone(),
+        };
+        queues.dispatch(vacuum, vacuum_reply).unwrap();
+        let (commit_reply, commit_result) = mpsc::channel();
+        let commit = WriterRequest::Commit {
+            directory: directory.clone(),
+            xid: 0,
+        };
+        queues.dispatch(commit, commit_reply).unwrap();
+        open_gate.send(()).unwrap();
+
+        assert!(matches!(
+            vacuum_result.recv().unwrap(),
+            Err(ServerError::HandlerPanicked(message)) if message == "vacuum failed"
+        ));
+        assert!(matches!(
+            commit_result.recv().unwrap(),
+            Err(ServerError::QueueClosed)
+        ));
+
+        // The next request for the route is handled by a new handler, once the old one is gone.
+        let (reply, result) = mpsc::channel();
+        queues
+            .dispatch(WriterRequest::Commit { directory, xid: 0 }, reply)
+            .unwrap();
+        assert!(result.recv().unwrap().is_ok());
+        assert_eq!(max_alive.load(Ordering::SeqCst), 1);
+    }
 }
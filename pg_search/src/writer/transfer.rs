@@ -1,13 +1,15 @@
 use interprocess::os::unix::fifo_file;
 use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
-use std::fs::File;
-use std::io::{BufReader, Read, Write};
+use std::fs::{File, OpenOptions};
+use std::io::{BufReader, BufWriter, Read, Write};
 use std::marker::PhantomData;
+use std::os::unix::fs::OpenOptionsExt;
 use std::os::unix::prelude::PermissionsExt;
 use std::path::{Path, PathBuf};
+use std::sync::mpsc;
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use tracing::error;
 
 use crate::writer::ServerError;
@@ -50,35 +52,72 @@ where
     }
 }
 
+/// The number of bytes of messages that a producer buffers before writing them to its pipe.
+/// Buffering lets a backend send the documents of many inserted rows in a single write, and
+/// whatever is left is written when the transfer ends, before the transaction commits.
+const TRANSFER_BUFFER_SIZE: usize = 1024 * 1024;
+
+/// How long the consumer of a transfer waits for its producer to create the pipe and open it.
+/// Producers do so as soon as the transfer starts, or before, so one that takes longer than
+/// this has failed, and the transfer is given up on rather than waited for forever.
+const PRODUCER_CONNECT_TIMEOUT: Duration = Duration::from_secs(60);
+
 pub struct WriterTransferProducer<T: Serialize> {
-    pipe: File,
+    pipe: BufWriter<File>,
     pipe_path: PathBuf,
+    /// Whether the done message was sent by `finish`, rather than being left to `drop`.
+    finished: bool,
     marker: PhantomData<T>,
 }
 
 impl<T: Serialize> WriterTransferProducer<T> {
     pub fn new<P: AsRef<Path>>(pipe_path: P) -> std::io::Result<Self> {
+        Self::create_pipe(pipe_path.as_ref())?;
+        Self::open(pipe_path)
+    }
+
+    /// Create the named pipe of a producer, without opening it. A producer that must tell the
+    /// consumer about its pipe, like a parallel build worker, creates it first, so that the
+    /// consumer never waits for a pipe that isn't coming.
+    pub fn create_pipe<P: AsRef<Path>>(pipe_path: P) -> std::io::Result<()> {
         // It's important that this process is the "owner" of the named pipe file path.
         // We'll remove any existing pipe_path, and connect to the first producer
         // process who creates a new one.
         Self::delete_named_pipe_file(pipe_path.as_ref())?;
-        let pipe = Self::create_named_pipe_file(pipe_path.as_ref())?;
+        Self::create_named_pipe_file(pipe_path.as_ref())
+    }
+
+    /// Open a pipe made with `create_pipe`, which blocks until the consumer opens it too.
+    pub fn open<P: AsRef<Path>>(pipe_path: P) -> std::io::Result<Self> {
+        // This is expected to block until a consumer connects.
+        let pipe = File::create(pipe_path.as_ref())?;
         Ok(Self {
-            pipe,
+            pipe: BufWriter::with_capacity(TRANSFER_BUFFER_SIZE, pipe),
             pipe_path: pipe_path.as_ref().to_path_buf(),
+            finished: false,
             marker: PhantomData,
         })
     }
 
+    /// Buffer a message, writing the buffer to the pipe if it's full. The consumer only
+    /// receives the message once the buffer is written, at the latest when the producer
+    /// finishes.
     pub fn write_message(&mut self, data: &T) -> std::io::Result<()> {
         let message = WriterTransferMessage::Data(data);
         let serialized = bincode::serialize(&message)
             .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidData, err))?;
-        self.write_all(&serialized)?;
-        self.flush()
+        self.write_all(&serialized)
+    }
+
+    /// End the transfer, writing out the buffered messages and the done message. Dropping the
+    /// producer does the same, but can only log an error, so a producer whose messages must
+    /// all arrive is finished instead.
+    pub fn finish(mut self) -> std::io::Result<()> {
+        self.finished = true;
+        self.write_done_message()
     }
 
-    pub fn write_done_message(&mut self) -> std::io::Result<()> {
+    fn write_done_message(&mut self) -> std::io::Result<()> {
         let message: WriterTransferMessage<T> = WriterTransferMessage::Done;
         let serialized = bincode::serialize(&message)
             .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidData, err))?;
@@ -86,7 +125,7 @@ impl<T: Serialize> WriterTransferProducer<T> {
         self.flush()
     }
 
-    fn create_named_pipe_file(pipe_path: &Path) -> std::io::Result<File> {
+    fn create_named_pipe_file(pipe_path: &Path) -> std::io::Result<()> {
         if pipe_path.exists() {
             std::fs::remove_file(pipe_path)?;
         }
@@ -94,10 +133,7 @@ impl<T: Serialize> WriterTransferProducer<T> {
         fifo_file::create_fifo(pipe_path, 0o600)?;
 
         let permissions = std::fs::Permissions::from_mode(0o666);
-        std::fs::set_permissions(pipe_path, permissions)?;
-
-        // This is expected to block until a consumer connects.
-        File::create(pipe_path)
+        std::fs::set_permissions(pipe_path, permissions)
     }
 
     fn delete_named_pipe_file(pipe_path: &Path) -> std::io::Result<()> {
@@ -122,9 +158,11 @@ impl<T: Serialize> Write for WriterTransferProducer<T> {
 impl<T: Serialize> Drop for WriterTransferProducer<T> {
     fn drop(&mut self) {
         let pipe_path = self.pipe_path.clone();
-        if let Err(err) = self.write_done_message() {
-            error!("error sending writer transfer done message: {err:?}")
-        };
+        if !self.finished {
+            if let Err(err) = self.write_done_message() {
+                error!("error sending writer transfer done message: {err:?}")
+            };
+        }
         if let Err(err) = std::fs::remove_file(&pipe_path) {
             error!("error removing named pipe path {pipe_path:?}: {err:?}");
         }
@@ -138,31 +176,59 @@ where
     P: AsRef<Path>,
     T: DeserializeOwned + 'a,
 {
-    let pipe_path_ref = pipe_path.as_ref();
+    let pipe_file = open_pipe(pipe_path.as_ref(), PRODUCER_CONNECT_TIMEOUT)?;
+    let reader = BufReader::new(pipe_file);
+    Ok(WriterTransferMessageIterator::new(reader))
+}
+
+/// Open a producer's pipe for reading, once the producer has created it and opened it too.
+/// Gives up with `ServerError::TransferTimeout` if that takes longer than `timeout`.
+fn open_pipe(pipe_path: &Path, timeout: Duration) -> Result<File, ServerError> {
+    let deadline = Instant::now() + timeout;
 
     // Wait for the client to create the pipe.
-    while !pipe_path_ref.exists() {
+    while !pipe_path.exists() {
+        if Instant::now() >= deadline {
+            return Err(ServerError::TransferTimeout(pipe_path.to_path_buf()));
+        }
         thread::sleep(Duration::from_millis(10));
     }
 
-    let pipe_file = std::fs::OpenOptions::new()
-        .read(true)
-        .open(pipe_path_ref)
-        .map_err(ServerError::OpenPipeFile)?;
+    // Opening a pipe for reading blocks until it's opened for writing, so it's opened on
+    // another thread, which we can stop waiting for.
+    let (sender, receiver) = mpsc::channel();
+    let path = pipe_path.to_path_buf();
+    thread::Builder::new()
+        .name("pg_search_open_pipe".into())
+        .spawn(move || {
+            let _ = sender.send(OpenOptions::new().read(true).open(path));
+        })?;
 
-    let reader = BufReader::new(pipe_file);
-    Ok(WriterTransferMessageIterator::new(reader))
+    match receiver.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+        Ok(opened) => opened.map_err(ServerError::OpenPipeFile),
+        Err(_) => {
+            // Opening the pipe for writing without blocking releases the waiting thread. It
+            // only fails if the thread isn't waiting, because it's about to open the pipe.
+            let _ = OpenOptions::new()
+                .write(true)
+                .custom_flags(libc::O_NONBLOCK)
+                .open(pipe_path);
+            Err(ServerError::TransferTimeout(pipe_path.to_path_buf()))
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use crate::{
         fixtures::*,
-        writer::{transfer, SearchDocument, WriterRequest, WriterTransferPipeFilePath},
+        writer::{
+            transfer, SearchDocument, ServerError, WriterRequest, WriterTransferPipeFilePath,
+        },
     };
     use pretty_assertions::assert_eq;
     use rstest::*;
-    use std::{path::Path, thread};
+    use std::{path::Path, thread, time::Duration};
 
     #[rstest]
     fn test_producer_consumer_read_write(
@@ -174,6 +240,7 @@ mod tests {
 
         let writer_request = WriterRequest::Insert {
             directory: mock_dir.writer_dir,
+            xid: 0,
             document: simple_doc,
         };
 
@@ -194,4 +261,82 @@ mod tests {
             assert_eq!(incoming.unwrap(), writer_request)
         }
     }
+
+    #[rstest]
+    fn test_producer_finish(mock_dir: MockWriterDirectory, simple_doc: SearchDocument) {
+        let WriterTransferPipeFilePath(pipe_path) =
+            mock_dir.writer_transfer_pipe_path(true).unwrap();
+        let writer_request = WriterRequest::Insert {
+            directory: mock_dir.writer_dir,
+            xid: 0,
+            document: simple_doc,
+        };
+
+        let consumer_pipe_path = pipe_path.clone();
+        let consumer = thread::spawn(move || {
+            transfer::read_stream::<WriterRequest, &Path>(&consumer_pipe_path)
+                .unwrap()
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap()
+        });
+
+        // The messages fit in the producer's buffer, so they're only written when it finishes.
+        let mut producer = super::WriterTransferProducer::<WriterRequest>::new(&pipe_path).unwrap();
+        producer.write_message(&writer_request).unwrap();
+        producer.write_message(&writer_request).unwrap();
+        assert!(!producer.pipe.buffer().is_empty());
+        producer.finish().unwrap();
+
+        assert_eq!(
+            consumer.join().unwrap(),
+            vec![writer_request.clone(), writer_request]
+        );
+        assert!(!pipe_path.exists());
+    }
+
+    #[rstest]
+    fn test_producer_finish_error(mock_dir: MockWriterDirectory, simple_doc: SearchDocument) {
+        let WriterTransferPipeFilePath(pipe_path) =
+            mock_dir.writer_transfer_pipe_path(true).unwrap();
+        let writer_request = WriterRequest::Insert {
+            directory: mock_dir.writer_dir,
+            xid: 0,
+            document: simple_doc,
+        };
+
+        // The consumer goes away without reading anything.
+        let consumer_pipe_path = pipe_path.clone();
+        let consumer = thread::spawn(move || {
+            drop(transfer::read_stream::<WriterRequest, &Path>(&consumer_pipe_path).unwrap());
+        });
+
+        let mut producer = super::WriterTransferProducer::<WriterRequest>::new(&pipe_path).unwrap();
+        consumer.join().unwrap();
+
+        // The buffered message can't be written, which finishing reports.
+        producer.write_message(&writer_request).unwrap();
+        let err = producer.finish().unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::BrokenPipe);
+        assert!(!pipe_path.exists());
+    }
+
+    #[rstest]
+    fn test_open_pipe_timeout(mock_dir: MockWriterDirectory) {
+        let WriterTransferPipeFilePath(pipe_path) =
+            mock_dir.writer_transfer_pipe_path(true).unwrap();
+        let timeout = Duration::from_millis(100);
+
+        // The producer never creates its pipe.
+        assert!(matches!(
+            transfer::open_pipe(&pipe_path, timeout),
+            Err(ServerError::TransferTimeout(_))
+        ));
+
+        // The producer creates its pipe, but never opens it.
+        super::WriterTransferProducer::<WriterRequest>::create_pipe(&pipe_path).unwrap();
+        assert!(matches!(
+            transfer::open_pipe(&pipe_path, timeout),
+            Err(ServerError::TransferTimeout(_))
+        ));
+    }
 }
@@ -1,6 +1,7 @@
 mod fixtures;
 
 use approx::assert_relative_eq;
+use async_std::task::block_on;
 use core::panic;
 use fixtures::*;
 use pretty_assertions::assert_eq;
@@ -443,3 +444,762 @@ fn alias(mut conn: PgConnection) {
     assert_relative_eq!(rows[2].1, 2.8772602, epsilon = 1e-6);
     assert_relative_eq!(rows[3].1, 3.3322046, epsilon = 1e-6);
 }
+
+#[rstest]
+fn bitmap_scan_with_btree_predicate(mut conn: PgConnection) {
+    SimpleProductsTable::setup().execute(&mut conn);
+    r#"
+    CREATE INDEX bm25_search_rating_idx ON paradedb.bm25_search (rating);
+    SET enable_indexscan = off;
+    SET enable_seqscan = off;
+    "#
+    .execute(&mut conn);
+
+    let query = r#"
+        SELECT id FROM paradedb.bm25_search
+        WHERE bm25_search @@@ '{
+            "index_name": "bm25_search_bm25_index",
+            "key_field": "id",
+            "query": {"Parse": {"query_string": "category:electronics"}}
+        }'::jsonb
+        AND rating = 4
+        ORDER BY id"#;
+
+    let plan: Vec<(String,)> = format!("EXPLAIN {query}").fetch(&mut conn);
+    let plan: Vec<_> = plan.into_iter().map(|(line,)| line).collect();
+    assert!(
+        plan.iter()
+            .any(|line| line.contains("Bitmap Index Scan on bm25_search_bm25_index")),
+        "expected a bitmap scan on the bm25 index, got: {plan:#?}"
+    );
+
+    let rows: Vec<(i32,)> = query.fetch(&mut conn);
+    assert_eq!(rows, vec![(1,), (2,), (22,)]);
+}
+
+#[rstest]
+fn order_by_score_operator(mut conn: PgConnection) {
+    SimpleProductsTable::setup().execute(&mut conn);
+    "SET enable_seqscan = off".execute(&mut conn);
+
+    let config = r#"'{
+        "index_name": "bm25_search_bm25_index",
+        "key_field": "id",
+        "stable_sort": true,
+        "query": {"Parse": {"query_string": "description:keyboard OR category:electronics"}}
+    }'::jsonb"#;
+    let query = format!(
+        "SELECT id FROM paradedb.bm25_search
+        WHERE bm25_search @@@ {config}
+        ORDER BY bm25_search <=> {config}
+        LIMIT 3"
+    );
+
+    // The index returns rows in score order, so there's no need for a separate sort.
+    let plan: Vec<(String,)> = format!("EXPLAIN {query}").fetch(&mut conn);
+    let plan: Vec<_> = plan.into_iter().map(|(line,)| line).collect();
+    assert!(
+        plan.iter()
+            .any(|line| line.contains("Index Scan using bm25_search_bm25_index")),
+        "expected an index scan on the bm25 index, got: {plan:#?}"
+    );
+    assert!(
+        !plan.iter().any(|line| line.contains("Sort")),
+        "expected no sort, got: {plan:#?}"
+    );
+
+    let rows: Vec<(i32,)> = query.fetch(&mut conn);
+    assert_eq!(rows, vec![(2,), (1,), (12,)]);
+
+    // Without a WHERE clause, the rows that don't match come after those that do.
+    let rows: Vec<(i32,)> = format!(
+        "SELECT id FROM paradedb.bm25_search
+        ORDER BY bm25_search <=> {config}"
+    )
+    .fetch(&mut conn);
+    let (count,): (i64,) = "SELECT count(*) FROM paradedb.bm25_search".fetch_one(&mut conn);
+    assert_eq!(rows.len() as i64, count);
+    assert_eq!(rows[..3], [(2,), (1,), (12,)]);
+    assert!(rows.contains(&(3,)));
+
+    // Without the index, the operator returns the negated score of matching rows.
+    "SET enable_indexscan = off; SET enable_bitmapscan = off; SET enable_seqscan = on"
+        .execute(&mut conn);
+    let rows: Vec<(i32, Option<f32>)> = format!(
+        "SELECT id, bm25_search <=> {config} FROM paradedb.bm25_search
+        ORDER BY bm25_search <=> {config}, id
+        LIMIT 3"
+    )
+    .fetch(&mut conn);
+    assert_eq!(
+        rows.iter().map(|(id, _)| *id).collect::<Vec<_>>(),
+        vec![2, 1, 12]
+    );
+    assert!(rows.iter().all(|(_, distance)| distance.unwrap() < 0.0));
+}
+
+#[rstest]
+fn multiple_scan_keys(mut conn: PgConnection) {
+    SimpleProductsTable::setup().execute(&mut conn);
+    "SET enable_seqscan = off".execute(&mut conn);
+
+    let config = |query_string: &str| {
+        format!(
+            r#"'{{
+                "index_name": "bm25_search_bm25_index",
+                "key_field": "id",
+                "query": {{"Parse": {{"query_string": "{query_string}"}}}}
+            }}'::jsonb"#
+        )
+    };
+
+    // Both predicates are evaluated by the same index scan, with no filter on top of it.
+    let query = format!(
+        "SELECT id FROM paradedb.bm25_search
+        WHERE bm25_search @@@ {} AND bm25_search @@@ {}
+        ORDER BY id",
+        config("category:electronics"),
+        config("description:keyboard")
+    );
+    let plan: Vec<(String,)> = format!("EXPLAIN {query}").fetch(&mut conn);
+    let plan: Vec<_> = plan.into_iter().map(|(line,)| line).collect();
+    assert!(
+        !plan.iter().any(|line| line.contains("Filter")),
+        "expected no filter, got: {plan:#?}"
+    );
+    let rows: Vec<(i32,)> = query.fetch(&mut conn);
+    assert_eq!(rows, vec![(1,), (2,)]);
+
+    // Ordering by one of the predicates' scores still requires both predicates to match.
+    let mut rows: Vec<(i32,)> = format!(
+        "SELECT id FROM paradedb.bm25_search
+        WHERE bm25_search @@@ {keyboard} AND bm25_search @@@ {electronics}
+        ORDER BY bm25_search <=> {keyboard}",
+        keyboard = config("description:keyboard"),
+        electronics = config("category:electronics")
+    )
+    .fetch(&mut conn);
+    rows.sort();
+    assert_eq!(rows, vec![(1,), (2,)]);
+
+    let rows: Vec<(i32,)> = format!(
+        "SELECT id FROM paradedb.bm25_search
+        WHERE bm25_search @@@ ANY(ARRAY[{}, {}])
+        ORDER BY id",
+        config("description:keyboard"),
+        config("description:shoes")
+    )
+    .fetch(&mut conn);
+    assert_eq!(rows, vec![(1,), (2,), (3,), (4,), (5,)]);
+}
+
+#[rstest]
+fn partitioned_table(mut conn: PgConnection) {
+    r#"
+    CREATE TABLE paradedb.events (id bigint, created date, message text) PARTITION BY RANGE (created);
+    CREATE TABLE paradedb.events_2024_01 PARTITION OF paradedb.events
+        FOR VALUES FROM ('2024-01-01') TO ('2024-02-01');
+    CREATE TABLE paradedb.events_2024_02 PARTITION OF paradedb.events
+        FOR VALUES FROM ('2024-02-01') TO ('2024-03-01');
+    INSERT INTO paradedb.events VALUES
+        (1, '2024-01-05', 'disk full on primary'),
+        (2, '2024-01-10', 'login succeeded'),
+        (3, '2024-01-20', 'login succeeded'),
+        (4, '2024-01-25', 'login failed'),
+        (5, '2024-02-03', 'disk full on primary'),
+        (6, '2024-02-10', 'disk disk full');
+    CALL paradedb.create_bm25(
+        index_name => 'events',
+        table_name => 'events',
+        schema_name => 'paradedb',
+        key_field => 'id',
+        text_fields => '{message: {}}'
+    );
+    "#
+    .execute(&mut conn);
+
+    // Each partition has its own bm25 index.
+    let indexes: Vec<(String,)> = "SELECT indexname::text FROM pg_indexes
+        WHERE schemaname = 'paradedb' AND indexname LIKE 'events_%_bm25_index' ORDER BY indexname"
+        .fetch(&mut conn);
+    assert_eq!(
+        indexes,
+        vec![
+            ("events_events_2024_01_bm25_index".into(),),
+            ("events_events_2024_02_bm25_index".into(),)
+        ]
+    );
+
+    // A search through the partitioned table finds rows in every partition, and scores them
+    // with statistics from all partitions, so identical rows score the same.
+    let rows: Vec<(i64, f32)> =
+        "SELECT id, paradedb.rank_bm25(id) FROM events.search('message:disk') ORDER BY id"
+            .fetch(&mut conn);
+    assert_eq!(
+        rows.iter().map(|(id, _)| *id).collect::<Vec<_>>(),
+        vec![1, 5, 6]
+    );
+    assert_relative_eq!(rows[0].1, rows[1].1, epsilon = 1e-6);
+    assert!(rows[2].1 > rows[0].1);
+
+    // The best match across the partitions comes first.
+    let config = r#"'{
+        "index_name": "events_bm25_index",
+        "key_field": "id",
+        "query": {"Parse": {"query_string": "message:disk"}}
+    }'::jsonb"#;
+    let rows: Vec<(i64,)> = format!(
+        "SELECT id FROM paradedb.events
+        WHERE events @@@ {config}
+        ORDER BY events <=> {config}
+        LIMIT 1"
+    )
+    .fetch(&mut conn);
+    assert_eq!(rows, vec![(6,)]);
+
+    // The offset and limit apply to the results of all the partitions, ranked together.
+    let rows: Vec<(i64,)> = "SELECT id FROM events.search('message:disk', offset_rows => 1, limit_rows => 1, stable_sort => true)"
+        .fetch(&mut conn);
+    assert_eq!(rows, vec![(1,)]);
+    let rows: Vec<(i64,)> = "SELECT id FROM events.search('message:disk', offset_rows => 1, stable_sort => true) ORDER BY id"
+        .fetch(&mut conn);
+    assert_eq!(rows, vec![(1,), (5,)]);
+    let rows: Vec<(i64,)> =
+        "SELECT id FROM events.search('message:disk', offset_rows => 3)".fetch(&mut conn);
+    assert_eq!(rows, vec![]);
+
+    // Rows inserted through the partitioned table are indexed by their partition.
+    "INSERT INTO paradedb.events VALUES (7, '2024-02-20', 'disk replaced')".execute(&mut conn);
+    let rows: Vec<(i64,)> = "SELECT id FROM events.search('message:replaced')".fetch(&mut conn);
+    assert_eq!(rows, vec![(7,)]);
+
+    // Dropping the index drops the index of each partition.
+    "CALL paradedb.drop_bm25('events', schema_name => 'paradedb')".execute(&mut conn);
+    let indexes: Vec<(String,)> = "SELECT indexname::text FROM pg_indexes
+        WHERE schemaname = 'paradedb' AND indexname LIKE 'events_%'"
+        .fetch(&mut conn);
+    assert_eq!(indexes, vec![]);
+}
+
+#[rstest]
+fn parallel_index_build(mut conn: PgConnection) {
+    r#"
+    CREATE TABLE paradedb.parallel_items (id bigint, description text);
+    INSERT INTO paradedb.parallel_items
+        SELECT n, CASE WHEN n % 2 = 0 THEN 'even item' ELSE 'odd item' END
+        FROM generate_series(1, 100000) n;
+    ALTER TABLE paradedb.parallel_items SET (parallel_workers = 2);
+    SET max_parallel_maintenance_workers = 2;
+    SET maintenance_work_mem = '256MB';
+    CALL paradedb.create_bm25(
+        index_name => 'parallel_items',
+        table_name => 'parallel_items',
+        schema_name => 'paradedb',
+        key_field => 'id',
+        text_fields => '{description: {}}'
+    );
+    "#
+    .execute(&mut conn);
+
+    // Every row is indexed exactly once, whichever participant scanned it.
+    let (count, distinct) = "SELECT count(*), count(DISTINCT id)
+        FROM parallel_items.search('description:even', limit_rows => 100000)"
+        .fetch_one::<(i64, i64)>(&mut conn);
+    assert_eq!(count, 50000);
+    assert_eq!(distinct, 50000);
+
+    let rows: Vec<(i64,)> =
+        "SELECT id FROM parallel_items.search('description:odd', limit_rows => 3) ORDER BY id"
+            .fetch(&mut conn);
+    assert_eq!(rows.len(), 3);
+    assert!(rows.iter().all(|(id,)| id % 2 == 1));
+
+    // Rows inserted after the build are indexed as usual.
+    "INSERT INTO paradedb.parallel_items VALUES (100001, 'brand new item')".execute(&mut conn);
+    let rows: Vec<(i64,)> =
+        "SELECT id FROM parallel_items.search('description:brand')".fetch(&mut conn);
+    assert_eq!(rows, vec![(100001,)]);
+}
+
+#[rstest]
+fn build_progress_phases(mut conn: PgConnection) {
+    // pg_stat_progress_create_index shows the name of each phase of a bm25 index build.
+    let phases: Vec<(Option<String>,)> = "SELECT pg_indexam_progress_phasename(am.oid, phase)
+        FROM pg_am am, generate_series(1, 4) phase WHERE am.amname = 'bm25' ORDER BY phase"
+        .fetch(&mut conn);
+    assert_eq!(
+        phases,
+        vec![
+            (Some("initializing".into()),),
+            (Some("scanning table".into()),),
+            (Some("writing segments".into()),),
+            (None,)
+        ]
+    );
+
+    // Only builds in progress are shown.
+    "CALL paradedb.create_bm25_test_table(table_name => 'progress_items', schema_name => 'paradedb');
+    CALL paradedb.create_bm25(
+        index_name => 'progress_items',
+        table_name => 'progress_items',
+        schema_name => 'paradedb',
+        key_field => 'id',
+        text_fields => '{description: {}}'
+    )"
+    .execute(&mut conn);
+    let (count,) =
+        "SELECT count(*) FROM paradedb.bm25_build_progress".fetch_one::<(i64,)>(&mut conn);
+    assert_eq!(count, 0);
+}
+
+#[rstest]
+fn rename_and_reindex_concurrently(mut conn: PgConnection) {
+    SimpleProductsTable::setup().execute(&mut conn);
+
+    // A renamed index keeps its data, and is searched by its new name.
+    "ALTER INDEX paradedb.bm25_search_bm25_index RENAME TO renamed_bm25_index".execute(&mut conn);
+    let rows: Vec<(i32,)> = r#"SELECT id FROM paradedb.bm25_search
+        WHERE bm25_search @@@ '{
+            "index_name": "renamed_bm25_index",
+            "key_field": "id",
+            "query": {"Parse": {"query_string": "description:keyboard"}}
+        }'::jsonb ORDER BY id"#
+        .fetch(&mut conn);
+    assert_eq!(rows, vec![(1,), (2,)]);
+    "ALTER INDEX paradedb.renamed_bm25_index RENAME TO bm25_search_bm25_index".execute(&mut conn);
+
+    // REINDEX CONCURRENTLY builds a copy of the index alongside it, while rows are written,
+    // then swaps it in under the same name.
+    "REINDEX INDEX CONCURRENTLY paradedb.bm25_search_bm25_index".execute(&mut conn);
+    let rows: Vec<(i32,)> =
+        "SELECT id FROM bm25_search.search('description:keyboard') ORDER BY id".fetch(&mut conn);
+    assert_eq!(rows, vec![(1,), (2,)]);
+
+    "INSERT INTO paradedb.bm25_search (description, rating, category, in_stock, metadata)
+    VALUES ('Mechanical keyboard with RGB', 5, 'Electronics', true, '{}')"
+        .execute(&mut conn);
+    let rows: Vec<(i32,)> =
+        "SELECT id FROM bm25_search.search('description:keyboard') ORDER BY id".fetch(&mut conn);
+    assert_eq!(rows.len(), 3);
+
+    // Only the rebuilt index is left.
+    let indexes: Vec<(String,)> = "SELECT indexname::text FROM pg_indexes
+        WHERE schemaname = 'paradedb' AND indexname LIKE 'bm25_search_bm25_index%'"
+        .fetch(&mut conn);
+    assert_eq!(indexes, vec![("bm25_search_bm25_index".into(),)]);
+}
+
+#[rstest]
+fn index_name_in_two_schemas(mut conn: PgConnection) {
+    SimpleProductsTable::setup().execute(&mut conn);
+    r#"
+    CREATE SCHEMA other;
+    CREATE TABLE other.bm25_search AS SELECT * FROM paradedb.bm25_search WHERE id = 2;
+    CREATE INDEX bm25_search_bm25_index ON other.bm25_search
+        USING bm25 ((bm25_search.*)) WITH (key_field='id', text_fields='{"description": {}}');
+    "#
+    .execute(&mut conn);
+
+    let search = |conn: &mut PgConnection, index_name: &str| -> Result<Vec<(i32,)>, sqlx::Error> {
+        format!(
+            r#"SELECT id FROM paradedb.bm25_search
+            WHERE paradedb.search_tantivy(
+                bm25_search.*,
+                jsonb_build_object(
+                    'index_name', '{index_name}',
+                    'key_field', 'id',
+                    'query', '{{"Parse": {{"query_string": "description:keyboard"}}}}'::jsonb
+                )
+            ) ORDER BY id"#
+        )
+        .fetch_result(conn)
+    };
+
+    // A name that's in more than one schema must be qualified, or be on the search_path.
+    match search(&mut conn, "bm25_search_bm25_index") {
+        Err(err) => assert!(err.to_string().contains("ambiguous"), "{err}"),
+        Ok(rows) => panic!("expected an ambiguous index name, got {rows:?}"),
+    }
+    assert_eq!(
+        search(&mut conn, "paradedb.bm25_search_bm25_index").unwrap(),
+        vec![(1,), (2,)]
+    );
+    assert_eq!(
+        search(&mut conn, "other.bm25_search_bm25_index").unwrap(),
+        vec![(2,)]
+    );
+    "SET search_path TO other, public".execute(&mut conn);
+    assert_eq!(
+        search(&mut conn, "bm25_search_bm25_index").unwrap(),
+        vec![(2,)]
+    );
+}
+
+#[rstest]
+fn selectivity_estimates(mut conn: PgConnection) {
+    SimpleProductsTable::setup().execute(&mut conn);
+    "ANALYZE paradedb.bm25_search; SET enable_seqscan = off".execute(&mut conn);
+
+    let estimated_rows = |conn: &mut PgConnection, query_string: &str| -> i64 {
+        let plan: Vec<(String,)> = format!(
+            r#"EXPLAIN SELECT id FROM paradedb.bm25_search
+            WHERE bm25_search @@@ '{{
+                "index_name": "bm25_search_bm25_index",
+                "key_field": "id",
+                "query": {{"Parse": {{"query_string": "{query_string}"}}}}
+            }}'::jsonb"#
+        )
+        .fetch(conn);
+        let (line,) = &plan[0];
+        line.split("rows=")
+            .nth(1)
+            .unwrap()
+            .split(' ')
+            .next()
+            .unwrap()
+            .parse()
+            .unwrap()
+    };
+
+    // Estimates come from the document frequencies of the query's terms.
+    assert_eq!(estimated_rows(&mut conn, "description:keyboard"), 2);
+    assert_eq!(estimated_rows(&mut conn, "description:nonexistent"), 1);
+    assert!(
+        estimated_rows(&mut conn, "category:electronics")
+            > estimated_rows(&mut conn, "description:keyboard")
+    );
+}
+
+#[rstest]
+fn bulk_insert_into_indexed_table(mut conn: PgConnection) {
+    r#"
+    CREATE TABLE paradedb.bulk_items (id bigint, description text);
+    CALL paradedb.create_bm25(
+        index_name => 'bulk_items',
+        table_name => 'bulk_items',
+        schema_name => 'paradedb',
+        key_field => 'id',
+        text_fields => '{description: {}}'
+    );
+    INSERT INTO paradedb.bulk_items
+        SELECT n, CASE WHEN n % 2 = 0 THEN 'even item' ELSE 'odd item' END
+        FROM generate_series(1, 100000) n;
+    "#
+    .execute(&mut conn);
+
+    let (count, distinct) = "SELECT count(*), count(DISTINCT id)
+        FROM bulk_items.search('description:even', limit_rows => 100000)"
+        .fetch_one::<(i64, i64)>(&mut conn);
+    assert_eq!(count, 50000);
+    assert_eq!(distinct, 50000);
+
+    // Buffered inserts are discarded when their transaction rolls back.
+    r#"
+    BEGIN;
+    INSERT INTO paradedb.bulk_items
+        SELECT n, 'late item' FROM generate_series(100001, 100010) n;
+    ROLLBACK;
+    "#
+    .execute(&mut conn);
+    let rows: Vec<(i64,)> = "SELECT id FROM bulk_items.search('description:late')".fetch(&mut conn);
+    assert!(rows.is_empty());
+
+    // Buffered inserts are committed along with the rest of their transaction.
+    r#"
+    BEGIN;
+    INSERT INTO paradedb.bulk_items
+        SELECT n, 'late item' FROM generate_series(100001, 100010) n;
+    UPDATE paradedb.bulk_items SET description = 'later item' WHERE id = 100001;
+    COMMIT;
+    "#
+    .execute(&mut conn);
+    let rows: Vec<(i64,)> =
+        "SELECT id FROM bulk_items.search('description:later')".fetch(&mut conn);
+    assert_eq!(rows, vec![(100001,)]);
+}
+
+#[rstest]
+fn prepared_transactions(mut conn: PgConnection) {
+    SimpleProductsTable::setup().execute(&mut conn);
+
+    r#"
+    BEGIN;
+    INSERT INTO paradedb.bm25_search (description, rating, category, in_stock, metadata)
+        VALUES ('Committed prepared toaster', 4, 'Electronics', true, '{}');
+    PREPARE TRANSACTION 'bm25_search_commit_prepared';
+    COMMIT PREPARED 'bm25_search_commit_prepared';
+    "#
+    .execute(&mut conn);
+    let rows: Vec<(String,)> =
+        "SELECT description FROM bm25_search.search('description:toaster')".fetch(&mut conn);
+    assert_eq!(rows, vec![("Committed prepared toaster".into(),)]);
+
+    r#"
+    BEGIN;
+    INSERT INTO paradedb.bm25_search (description, rating, category, in_stock, metadata)
+        VALUES ('Rolled back prepared blender', 4, 'Electronics', true, '{}');
+    PREPARE TRANSACTION 'bm25_search_rollback_prepared';
+    ROLLBACK PREPARED 'bm25_search_rollback_prepared';
+    "#
+    .execute(&mut conn);
+    let rows: Vec<(String,)> =
+        "SELECT description FROM bm25_search.search('description:blender')".fetch(&mut conn);
+    assert!(rows.is_empty());
+
+    // The index keeps working for the transactions that follow.
+    "INSERT INTO paradedb.bm25_search (description, rating, category, in_stock, metadata)
+        VALUES ('Plain blender', 4, 'Electronics', true, '{}')"
+        .execute(&mut conn);
+    let rows: Vec<(String,)> =
+        "SELECT description FROM bm25_search.search('description:blender')".fetch(&mut conn);
+    assert_eq!(rows, vec![("Plain blender".into(),)]);
+}
+
+#[rstest]
+fn rollback_prepared_concurrent(database: Db) {
+    let mut conn = block_on(database.connection());
+    let mut other_conn = block_on(database.connection());
+    "CREATE EXTENSION pg_search".execute(&mut conn);
+    SimpleProductsTable::setup().execute(&mut conn);
+
+    // Both transactions' documents are committed to the index when the first one prepares,
+    // but rolling it back only removes its own.
+    r#"
+    BEGIN;
+    INSERT INTO paradedb.bm25_search (description, rating, category, in_stock, metadata)
+        VALUES ('Concurrent toaster', 4, 'Electronics', true, '{}');
+    "#
+    .execute(&mut other_conn);
+    r#"
+    BEGIN;
+    INSERT INTO paradedb.bm25_search (description, rating, category, in_stock, metadata)
+        VALUES ('Rolled back prepared toaster', 4, 'Electronics', true, '{}');
+    PREPARE TRANSACTION 'bm25_search_concurrent_rollback';
+    "#
+    .execute(&mut conn);
+    "COMMIT".execute(&mut other_conn);
+    "ROLLBACK PREPARED 'bm25_search_concurrent_rollback'".execute(&mut conn);
+
+    let rows: Vec<(String,)> =
+        "SELECT description FROM bm25_search.search('description:toaster')".fetch(&mut conn);
+    assert_eq!(rows, vec![("Concurrent toaster".into(),)]);
+}
+
+#[rstest]
+fn verify_index(mut conn: PgConnection) {
+    SimpleProductsTable::setup().execute(&mut conn);
+
+    let problems: Vec<(String, String)> =
+        "SELECT problem, detail FROM paradedb.verify_bm25('bm25_search', heapallindexed => true)"
+            .fetch(&mut conn);
+    assert_eq!(problems, vec![]);
+
+    // Deleted rows keep their documents until the table is vacuumed.
+    "DELETE FROM paradedb.bm25_search WHERE id IN (1, 2)".execute(&mut conn);
+    let problems: Vec<(String, String, Option<i64>)> = "SELECT index_name, problem, key
+        FROM paradedb.verify_bm25('bm25_search') ORDER BY key"
+        .fetch(&mut conn);
+    assert_eq!(
+        problems,
+        vec![
+            (
+                "bm25_search_bm25_index".into(),
+                "orphaned_document".into(),
+                Some(1)
+            ),
+            (
+                "bm25_search_bm25_index".into(),
+                "orphaned_document".into(),
+                Some(2)
+            ),
+        ]
+    );
+
+    let repaired: Vec<(bool,)> =
+        "SELECT repaired FROM paradedb.verify_bm25('bm25_search', repair => true)".fetch(&mut conn);
+    assert_eq!(repaired, vec![(true,), (true,)]);
+
+    let problems: Vec<(String, String)> =
+        "SELECT problem, detail FROM paradedb.verify_bm25('bm25_search', heapallindexed => true)"
+            .fetch(&mut conn);
+    assert_eq!(problems, vec![]);
+}
+
+#[rstest]
+fn optimize_index(mut conn: PgConnection) {
+    r#"
+    CREATE TABLE optimize_table (id SERIAL PRIMARY KEY, description TEXT);
+    CALL paradedb.create_bm25(
+        index_name => 'optimize_table',
+        table_name => 'optimize_table',
+        key_field => 'id',
+        text_fields => '{"description": {}}',
+        merge_policy => '{"no_merge": true}'
+    )"#
+    .execute(&mut conn);
+
+    // Each statement commits its own segment, and none of them are merged in the background.
+    for description in ["red shoes", "blue shoes", "red hat", "green shoes"] {
+        format!("INSERT INTO optimize_table (description) VALUES ('{description}')")
+            .execute(&mut conn);
+    }
+    "DELETE FROM optimize_table WHERE id = 2".execute(&mut conn);
+    "VACUUM optimize_table".execute(&mut conn);
+
+    "SELECT paradedb.optimize_bm25('optimize_table')".execute(&mut conn);
+
+    let rows: Vec<(i32,)> =
+        "SELECT id FROM optimize_table.search('description:shoes') ORDER BY id".fetch(&mut conn);
+    assert_eq!(rows, vec![(1,), (4,)]);
+
+    match "SELECT paradedb.optimize_bm25('optimize_table', max_segments => 0)"
+        .execute_result(&mut conn)
+    {
+        Err(err) => assert!(err.to_string().contains("max_segments must be at least 1")),
+        _ => panic!("optimizing to no segments should fail"),
+    };
+
+    match r#"
+    CALL paradedb.create_bm25(
+        index_name => 'optimize_table',
+        table_name => 'optimize_table',
+        key_field => 'id',
+        text_fields => '{"description": {}}',
+        merge_policy => '{"max_segments": 4}'
+    )"#
+    .execute_result(&mut conn)
+    {
+        Err(err) => assert!(err.to_string().contains("merge policy")),
+        _ => panic!("unknown merge policy settings should be rejected"),
+    };
+}
+
+#[rstest]
+fn writer_settings(mut conn: PgConnection) {
+    r#"
+    CREATE TABLE writer_settings_table (id SERIAL PRIMARY KEY, description TEXT);
+    INSERT INTO writer_settings_table (description) VALUES ('red shoes'), ('blue hat');
+    CALL paradedb.create_bm25(
+        index_name => 'writer_settings_table',
+        table_name => 'writer_settings_table',
+        key_field => 'id',
+        text_fields => '{"description": {}}',
+        writer_memory_budget => 64,
+        writer_threads => 2
+    )"#
+    .execute(&mut conn);
+
+    "INSERT INTO writer_settings_table (description) VALUES ('green shoes')".execute(&mut conn);
+    let rows: Vec<(i32,)> =
+        "SELECT id FROM writer_settings_table.search('description:shoes') ORDER BY id"
+            .fetch(&mut conn);
+    assert_eq!(rows, vec![(1,), (3,)]);
+
+    let (budget,): (String,) = "SHOW paradedb.pg_search_writer_memory_budget".fetch_one(&mut conn);
+    assert_eq!(budget, "500MB");
+    let (threads,): (String,) = "SHOW paradedb.pg_search_search_threads".fetch_one(&mut conn);
+    assert_eq!(threads, "1");
+
+    // The insert worker only reads the writer settings when the server starts.
+    match "SET paradedb.pg_search_writer_threads = 2".execute_result(&mut conn) {
+        Ok(_) => panic!("writer settings should only be set when the server starts"),
+        Err(err) => assert!(err.to_string().contains("cannot be changed"), "{err}"),
+    };
+
+    match r#"
+    CALL paradedb.create_bm25(
+        index_name => 'writer_settings_table',
+        table_name => 'writer_settings_table',
+        key_field => 'id',
+        text_fields => '{"description": {}}',
+        writer_threads => 9
+    )"#
+    .execute_result(&mut conn)
+    {
+        Err(err) => assert!(err.to_string().contains("writer_threads")),
+        _ => panic!("more writer threads than Tantivy supports should be rejected"),
+    };
+}
+
+#[rstest]
+fn index_stats(mut conn: PgConnection) {
+    SimpleProductsTable::setup().execute(&mut conn);
+    let (rows,): (i64,) = "SELECT COUNT(*) FROM paradedb.bm25_search".fetch_one(&mut conn);
+
+    let (index_name, live_docs, deleted_docs, last_merge_time): (String, i64, i64, Option<String>) =
+        "SELECT index_name, live_docs, deleted_docs, last_merge_time::text
+        FROM paradedb.bm25_index_stats('bm25_search')"
+            .fetch_one(&mut conn);
+    assert_eq!(index_name, "bm25_search_bm25_index");
+    assert_eq!(live_docs, rows);
+    assert_eq!(deleted_docs, 0);
+    assert_eq!(last_merge_time, None);
+
+    // The components add up to the total, which is what's on disk.
+    let (total_bytes, components_bytes): (i64, i64) = "SELECT total_bytes,
+        postings_bytes + positions_bytes + fast_fields_bytes + store_bytes + other_bytes
+        FROM paradedb.bm25_index_stats('bm25_search')"
+        .fetch_one(&mut conn);
+    assert!(total_bytes > 0);
+    assert_eq!(total_bytes, components_bytes);
+
+    "DELETE FROM paradedb.bm25_search WHERE id IN (1, 2)".execute(&mut conn);
+    "VACUUM paradedb.bm25_search".execute(&mut conn);
+    let (live_docs, deleted_docs): (i64, i64) =
+        "SELECT live_docs, deleted_docs FROM paradedb.bm25_index_stats('bm25_search')"
+            .fetch_one(&mut conn);
+    assert_eq!(live_docs, rows - 2);
+    assert_eq!(deleted_docs, 2);
+
+    "SELECT paradedb.optimize_bm25('bm25_search')".execute(&mut conn);
+    let (num_segments, deleted_docs, merged): (i64, i64, bool) =
+        "SELECT num_segments, deleted_docs, last_merge_time IS NOT NULL
+        FROM paradedb.bm25_index_stats('bm25_search')"
+            .fetch_one(&mut conn);
+    assert_eq!(num_segments, 1);
+    assert_eq!(deleted_docs, 0);
+    assert!(merged);
+
+    // Without a name, every bm25 index is listed.
+    let listed: Vec<(String,)> =
+        "SELECT index_name FROM paradedb.bm25_index_stats() WHERE index_name = 'bm25_search_bm25_index'"
+            .fetch(&mut conn);
+    assert_eq!(listed.len(), 1);
+}
+
+#[cfg(any(feature = "pg15", feature = "pg16"))]
+#[rstest]
+fn wal_logged_insert_and_commit(mut conn: PgConnection) {
+    SimpleProductsTable::setup().execute(&mut conn);
+    "CREATE EXTENSION pg_walinspect".execute(&mut conn);
+
+    let (start_lsn,): (String,) = "SELECT pg_current_wal_insert_lsn()::text".fetch_one(&mut conn);
+    let (xid,): (String,) = r#"
+    INSERT INTO paradedb.bm25_search (description, rating, category, in_stock, metadata)
+        VALUES ('Logged toaster', 4, 'Electronics', true, '{}')
+        RETURNING xmin::text
+    "#
+    .fetch_one(&mut conn);
+    let (index_oid,): (i64,) =
+        "SELECT 'paradedb.bm25_search_bm25_index'::regclass::oid::bigint".fetch_one(&mut conn);
+
+    // A standby, or a primary recovering from a crash, replays the insert and then its commit.
+    // The journal is cleared after Postgres commits, so that record may not be flushed yet.
+    let records: Vec<(String, String)> = format!(
+        "SELECT record_type, description
+         FROM pg_get_wal_records_info('{start_lsn}', pg_current_wal_flush_lsn())
+         WHERE resource_manager = 'pg_search' AND description NOT LIKE 'clear journal%'
+         ORDER BY start_lsn"
+    )
+    .fetch(&mut conn);
+    assert_eq!(
+        records,
+        vec![
+            ("REQUEST".into(), format!("insert into index {index_oid}")),
+            (
+                "REQUEST".into(),
+                format!("commit index {index_oid}, xid {xid}")
+            ),
+        ]
+    );
+}
@@ -49,6 +49,106 @@ fn invalid_create_bm25(mut conn: PgConnection) {
     };
 }
 
+#[rstest]
+fn invalid_token_filter(mut conn: PgConnection) {
+    "CALL paradedb.create_bm25_test_table(table_name => 'index_config', schema_name => 'paradedb')"
+        .execute(&mut conn);
+
+    match "CALL paradedb.create_bm25(
+	    index_name => 'index_config',
+	    table_name => 'index_config',
+	    schema_name => 'paradedb',
+	    key_field => 'id',
+	    text_fields => '{description: {filters: [{type: \"edge_ngram\", min_gram: 5, max_gram: 2}]}}'
+    )"
+    .execute_result(&mut conn)
+    {
+        Ok(_) => panic!("should fail with min_gram greater than max_gram"),
+        Err(err) => assert!(
+            err.to_string()
+                .contains("min_gram must not be greater than max_gram"),
+            "{}",
+            fmt_err(err)
+        ),
+    };
+
+    match "CALL paradedb.create_bm25(
+	    index_name => 'index_config',
+	    table_name => 'index_config',
+	    schema_name => 'paradedb',
+	    key_field => 'id',
+	    text_fields => '{description: {filters: [{type: \"shingle\", min_shingle_size: 1}]}}'
+    )"
+    .execute_result(&mut conn)
+    {
+        Ok(_) => panic!("should fail with a shingle size of 1"),
+        Err(err) => assert!(
+            err.to_string()
+                .contains("min_shingle_size must be at least 2"),
+            "{}",
+            fmt_err(err)
+        ),
+    };
+}
+
+#[rstest]
+fn unregistered_custom_tokenizer(mut conn: PgConnection) {
+    "CALL paradedb.create_bm25_test_table(table_name => 'index_config', schema_name => 'paradedb')"
+        .execute(&mut conn);
+
+    match "CALL paradedb.create_bm25(
+	    index_name => 'index_config',
+	    table_name => 'index_config',
+	    schema_name => 'paradedb',
+	    key_field => 'id',
+	    text_fields => '{description: {tokenizer: {type: \"custom\", name: \"unregistered\"}}}'
+    )"
+    .execute_result(&mut conn)
+    {
+        Ok(_) => panic!("should fail with an unregistered custom tokenizer"),
+        Err(err) => assert!(
+            err.to_string()
+                .contains("no custom tokenizer named 'unregistered' is registered"),
+            "{}",
+            fmt_err(err)
+        ),
+    };
+}
+
+#[rstest]
+#[case::missing("missing.csv", "could not load Lindera user dictionary 'missing.csv'")]
+#[case::absolute(
+    "/etc/passwd",
+    "Lindera user dictionary '/etc/passwd' must be a relative path without '..'"
+)]
+#[case::parent(
+    "../../postgresql.conf",
+    "Lindera user dictionary '../../postgresql.conf' must be a relative path without '..'"
+)]
+fn invalid_lindera_user_dictionary(
+    mut conn: PgConnection,
+    #[case] user_dictionary: &str,
+    #[case] message: &str,
+) {
+    "CALL paradedb.create_bm25_test_table(table_name => 'index_config', schema_name => 'paradedb')"
+        .execute(&mut conn);
+
+    match format!(
+        "CALL paradedb.create_bm25(
+	    index_name => 'index_config',
+	    table_name => 'index_config',
+	    schema_name => 'paradedb',
+	    key_field => 'id',
+	    text_fields => '{{description: {{tokenizer: {{type: \"japanese_lindera\", user_dictionary: \"{user_dictionary}\"}}}}}}'
+    )"
+    )
+    .execute_result(&mut conn)
+    {
+        Ok(_) => panic!("should fail with user dictionary '{user_dictionary}'"),
+        Err(err) => assert!(err.to_string().contains(message), "{}", fmt_err(err)),
+    };
+}
+
 #[rstest]
 fn default_text_field(mut conn: PgConnection) {
     "CALL paradedb.create_bm25_test_table(table_name => 'index_config', schema_name => 'paradedb')"
@@ -283,3 +383,96 @@ fn multiple_fields(mut conn: PgConnection) {
     assert_eq!(rows[5], ("metadata".into(), "JsonObject".into()));
     assert_eq!(rows[6], ("rating".into(), "I64".into()));
 }
+
+#[rstest]
+fn partial_index_predicates(mut conn: PgConnection) {
+    "CALL paradedb.create_bm25_test_table(table_name => 'index_config', schema_name => 'paradedb')"
+        .execute(&mut conn);
+
+    "CALL paradedb.create_bm25(
+	    index_name => 'index_config',
+	    table_name => 'index_config',
+	    schema_name => 'paradedb',
+	    key_field => 'id',
+	    text_fields => '{description: {}}',
+	    predicates => 'in_stock'
+    )"
+    .execute(&mut conn);
+
+    // Only rows that satisfy the predicate were indexed.
+    let rows: Vec<(i32,)> =
+        "SELECT id FROM index_config.search('description:shoes') ORDER BY id".fetch(&mut conn);
+    assert_eq!(rows, vec![(3,), (5,)]);
+
+    // New rows are only indexed if they satisfy the predicate.
+    "INSERT INTO paradedb.index_config (description, rating, category, in_stock)
+    VALUES ('Blue canvas shoes', 4, 'Footwear', false), ('Red canvas shoes', 4, 'Footwear', true)"
+        .execute(&mut conn);
+    let rows: Vec<(String,)> =
+        "SELECT description FROM index_config.search('description:canvas')".fetch(&mut conn);
+    assert_eq!(rows, vec![("Red canvas shoes".into(),)]);
+
+    // The index only covers rows that satisfy the predicate, so it can't answer queries
+    // that don't imply it.
+    let plan: Vec<(String,)> = "EXPLAIN SELECT * FROM paradedb.index_config
+        WHERE index_config @@@ '{\"index_name\": \"index_config_bm25_index\", \"key_field\": \"id\", \"query\": \"All\"}'::jsonb"
+        .fetch(&mut conn);
+    assert!(!plan.iter().any(|(line,)| line.contains("Index Scan")));
+}
+
+#[rstest]
+fn expression_fields(mut conn: PgConnection) {
+    "CALL paradedb.create_bm25_test_table(table_name => 'index_config', schema_name => 'paradedb')"
+        .execute(&mut conn);
+
+    "CALL paradedb.create_bm25(
+	    index_name => 'index_config',
+	    table_name => 'index_config',
+	    schema_name => 'paradedb',
+	    key_field => 'id',
+	    text_fields => '{category_description: {}}',
+	    numeric_fields => '{double_rating: {}}',
+	    expressions => '{\"category_description\": \"category || '' '' || description\", \"double_rating\": \"rating * 2\"}'
+    )"
+    .execute(&mut conn);
+
+    let rows: Vec<(i32,)> = "SELECT id FROM index_config.search('category_description:electronics AND category_description:keyboard') ORDER BY id"
+        .fetch(&mut conn);
+    assert_eq!(rows, vec![(1,), (2,)]);
+
+    let rows: Vec<(i32,)> = "SELECT id FROM index_config.search('category_description:electronics AND double_rating:10') ORDER BY id"
+        .fetch(&mut conn);
+    assert_eq!(rows, vec![(12,)]);
+
+    // Expressions are evaluated for new rows, and NULL values are left out of the document.
+    "INSERT INTO paradedb.index_config (description, rating, category, in_stock)
+    VALUES ('Mechanical keyboard', 4, 'Electronics', true), ('Unrated keyboard', NULL, 'Electronics', true)"
+        .execute(&mut conn);
+    let rows: Vec<(String,)> =
+        "SELECT description FROM index_config.search('category_description:mechanical')"
+            .fetch(&mut conn);
+    assert_eq!(rows, vec![("Mechanical keyboard".into(),)]);
+    let rows: Vec<(String,)> =
+        "SELECT description FROM index_config.search('category_description:unrated')"
+            .fetch(&mut conn);
+    assert_eq!(rows, vec![("Unrated keyboard".into(),)]);
+
+    // An expression field can't shadow a column of the table.
+    match "CALL paradedb.create_bm25(
+	    index_name => 'index_config',
+	    table_name => 'index_config',
+	    schema_name => 'paradedb',
+	    key_field => 'id',
+	    text_fields => '{description: {}}',
+	    expressions => '{\"description\": \"lower(description)\"}'
+    )"
+    .execute_result(&mut conn)
+    {
+        Ok(_) => panic!("expression fields should not shadow columns"),
+        Err(err) => assert!(
+            err.to_string().contains("same name as a column"),
+            "{}",
+            fmt_err(err)
+        ),
+    };
+}
@@ -19,6 +19,9 @@ static TRANSACTION_CALL_ONCE_ON_COMMIT_CACHE: TransactionCallbackCache =
 static TRANSACTION_CALL_ONCE_ON_ABORT_CACHE: TransactionCallbackCache =
     Lazy::new(|| Arc::new(Mutex::new(HashSet::new())));
 
+static TRANSACTION_CALL_ONCE_ON_PREPREPARE_CACHE: TransactionCallbackCache =
+    Lazy::new(|| Arc::new(Mutex::new(HashSet::new())));
+
 pub struct Transaction {}
 
 impl Transaction {
@@ -41,6 +44,10 @@ impl Transaction {
             .clone()
             .lock()?
             .remove(id);
+        TRANSACTION_CALL_ONCE_ON_PREPREPARE_CACHE
+            .clone()
+            .lock()?
+            .remove(id);
         Ok(())
     }
 
@@ -113,6 +120,30 @@ impl Transaction {
 
         Ok(())
     }
+
+    /// Call `callback` when the transaction is about to be prepared for a two-phase commit with
+    /// PREPARE TRANSACTION, in place of the precommit callback. Neither the commit nor the abort
+    /// callbacks are called for a prepared transaction, as it's finished by COMMIT PREPARED or
+    /// ROLLBACK PREPARED, which may run in another session.
+    pub fn call_once_on_preprepare<F>(id: &'static str, callback: F) -> Result<(), TransactionError>
+    where
+        F: FnOnce() + Send + UnwindSafe + RefUnwindSafe + 'static,
+    {
+        let mut cache = TRANSACTION_CALL_ONCE_ON_PREPREPARE_CACHE.lock()?;
+        if !cache.contains(id) {
+            register_xact_callback(PgXactCallbackEvent::PrePrepare, move || {
+                // The transaction ends here, so clear the caches for the next transaction.
+                Self::clear_commit_abort_caches(id)
+                    .expect("could not acquire lock in register transaction preprepare callback");
+                // Actually call the callback.
+                callback();
+            });
+
+            cache.insert(id.into());
+        }
+
+        Ok(())
+    }
 }
 
 #[derive(Error, Debug)]
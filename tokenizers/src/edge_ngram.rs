@@ -0,0 +1,157 @@
+use tantivy::tokenizer::{Token, TokenFilter, TokenStream, Tokenizer};
+use tantivy::TantivyError;
+
+/// Replaces each token with its prefixes of `min_gram` to `max_gram` characters, so that
+/// partially typed words match, while keeping the word boundaries of the base tokenizer.
+/// All prefixes of a token share its position and offsets. Tokens shorter than `min_gram`
+/// are dropped.
+#[derive(Clone)]
+pub struct EdgeNgramFilter {
+    min_gram: usize,
+    max_gram: usize,
+}
+
+impl EdgeNgramFilter {
+    pub fn new(min_gram: usize, max_gram: usize) -> tantivy::Result<Self> {
+        if min_gram == 0 {
+            return Err(TantivyError::InvalidArgument(
+                "min_gram must be greater than 0".to_string(),
+            ));
+        }
+        if min_gram > max_gram {
+            return Err(TantivyError::InvalidArgument(
+                "min_gram must not be greater than max_gram".to_string(),
+            ));
+        }
+        Ok(Self { min_gram, max_gram })
+    }
+}
+
+impl TokenFilter for EdgeNgramFilter {
+    type Tokenizer<T: Tokenizer> = EdgeNgramFilterWrapper<T>;
+
+    fn transform<T: Tokenizer>(self, tokenizer: T) -> EdgeNgramFilterWrapper<T> {
+        EdgeNgramFilterWrapper {
+            min_gram: self.min_gram,
+            max_gram: self.max_gram,
+            inner: tokenizer,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct EdgeNgramFilterWrapper<T> {
+    min_gram: usize,
+    max_gram: usize,
+    inner: T,
+}
+
+impl<T: Tokenizer> Tokenizer for EdgeNgramFilterWrapper<T> {
+    type TokenStream<'a> = EdgeNgramTokenStream<T::TokenStream<'a>>;
+
+    fn token_stream<'a>(&'a mut self, text: &'a str) -> Self::TokenStream<'a> {
+        EdgeNgramTokenStream {
+            min_gram: self.min_gram,
+            max_gram: self.max_gram,
+            pending: Vec::new(),
+            tail: self.inner.token_stream(text),
+        }
+    }
+}
+
+pub struct EdgeNgramTokenStream<T> {
+    min_gram: usize,
+    max_gram: usize,
+    /// Prefixes of the current token that have yet to be emitted, longest first.
+    pending: Vec<String>,
+    tail: T,
+}
+
+impl<T: TokenStream> TokenStream for EdgeNgramTokenStream<T> {
+    fn advance(&mut self) -> bool {
+        loop {
+            if let Some(gram) = self.pending.pop() {
+                self.tail.token_mut().text = gram;
+                return true;
+            }
+
+            if !self.tail.advance() {
+                return false;
+            }
+
+            let text = &self.tail.token().text;
+            // The byte offset at which each prefix of 1, 2, ... characters ends.
+            let prefix_ends = text
+                .char_indices()
+                .map(|(offset, c)| offset + c.len_utf8())
+                .skip(self.min_gram - 1)
+                .take(self.max_gram - self.min_gram + 1);
+            self.pending = prefix_ends
+                .rev()
+                .map(|end| text[..end].to_string())
+                .collect();
+        }
+    }
+
+    fn token(&self) -> &Token {
+        self.tail.token()
+    }
+
+    fn token_mut(&mut self) -> &mut Token {
+        self.tail.token_mut()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::*;
+    use tantivy::tokenizer::{LowerCaser, SimpleTokenizer, TextAnalyzer, Token};
+
+    fn test_helper(min_gram: usize, max_gram: usize, text: &str) -> Vec<Token> {
+        let mut analyzer = TextAnalyzer::builder(SimpleTokenizer::default())
+            .filter(LowerCaser)
+            .filter(EdgeNgramFilter::new(min_gram, max_gram).unwrap())
+            .build();
+        let mut token_stream = analyzer.token_stream(text);
+        let mut tokens: Vec<Token> = vec![];
+        while token_stream.advance() {
+            tokens.push(token_stream.token().clone());
+        }
+        tokens
+    }
+
+    fn texts(tokens: &[Token]) -> Vec<&str> {
+        tokens.iter().map(|token| token.text.as_str()).collect()
+    }
+
+    #[rstest]
+    fn test_edge_ngram_filter() {
+        let tokens = test_helper(2, 4, "Hello a Wonderful World");
+        assert_eq!(
+            texts(&tokens),
+            vec!["he", "hel", "hell", "wo", "won", "wond", "wo", "wor", "worl"]
+        );
+
+        // Prefixes keep the position and offsets of the word they were taken from.
+        let positions: Vec<_> = tokens.iter().map(|token| token.position).collect();
+        assert_eq!(positions, vec![0, 0, 0, 2, 2, 2, 3, 3, 3]);
+        assert_eq!(tokens[3].offset_from, 8);
+        assert_eq!(tokens[3].offset_to, 17);
+    }
+
+    #[rstest]
+    fn test_edge_ngram_filter_multibyte() {
+        let tokens = test_helper(1, 3, "Straße");
+        assert_eq!(texts(&tokens), vec!["s", "st", "str"]);
+
+        let tokens = test_helper(2, 10, "ÿök");
+        assert_eq!(texts(&tokens), vec!["ÿö", "ÿök"]);
+    }
+
+    #[rstest]
+    fn test_edge_ngram_filter_invalid() {
+        assert!(EdgeNgramFilter::new(0, 2).is_err());
+        assert!(EdgeNgramFilter::new(3, 2).is_err());
+    }
+}
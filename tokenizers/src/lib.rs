@@ -1,13 +1,19 @@
 pub mod cjk;
 pub mod code;
+pub mod edge_ngram;
 #[cfg(feature = "icu")]
 pub mod icu;
 pub mod lindera;
 pub mod manager;
+pub mod normalizer;
+pub mod phonetic;
+pub mod registry;
+pub mod shingle;
 
 use cjk::ChineseTokenizer;
 use code::CodeTokenizer;
 use lindera::{LinderaChineseTokenizer, LinderaJapaneseTokenizer, LinderaKoreanTokenizer};
+use normalizer::{NormalizationFilter, TextNormalization};
 use tantivy::tokenizer::{
     AsciiFoldingFilter, LowerCaser, NgramTokenizer, RawTokenizer, RemoveLongFilter, TextAnalyzer,
     TokenizerManager,
@@ -17,15 +23,24 @@ use tracing::info;
 #[cfg(feature = "icu")]
 use icu::ICUTokenizer;
 
-pub use manager::{SearchNormalizer, SearchTokenizer};
+pub use manager::{SearchNormalizer, SearchTokenFilter, SearchTokenizer};
+pub use registry::{
+    custom_tokenizer_names, register_custom_tokenizer, CustomTokenizerError,
+    CustomTokenizerRegistry, CUSTOM_TOKENIZER_REGISTRY, CUSTOM_TOKENIZER_RENDEZVOUS,
+};
 
 pub const DEFAULT_REMOVE_TOKEN_LENGTH: usize = 255;
 
-pub fn create_tokenizer_manager(search_tokenizers: Vec<&SearchTokenizer>) -> TokenizerManager {
+pub fn create_tokenizer_manager(
+    search_tokenizers: Vec<(&SearchTokenizer, &[SearchTokenFilter])>,
+) -> Result<TokenizerManager, String> {
     let tokenizer_manager = TokenizerManager::default();
 
-    for search_tokenizer in search_tokenizers {
+    for (search_tokenizer, filters) in search_tokenizers {
         let tokenizer_option = match search_tokenizer {
+            // A tokenizer with filters is registered under its own name, even
+            // when Tantivy already provides the unfiltered tokenizer.
+            _ if !filters.is_empty() => Some(search_tokenizer.text_analyzer(filters)?),
             SearchTokenizer::Raw => Some(
                 TextAnalyzer::builder(RawTokenizer::default())
                     .filter(RemoveLongFilter::limit(DEFAULT_REMOVE_TOKEN_LENGTH))
@@ -56,23 +71,41 @@ pub fn create_tokenizer_manager(search_tokenizers: Vec<&SearchTokenizer>) -> Tok
                 .filter(LowerCaser)
                 .build(),
             ),
-            SearchTokenizer::ChineseLindera => Some(
-                TextAnalyzer::builder(LinderaChineseTokenizer::default())
-                    .filter(RemoveLongFilter::limit(DEFAULT_REMOVE_TOKEN_LENGTH))
-                    .filter(LowerCaser)
-                    .build(),
+            SearchTokenizer::ChineseLindera {
+                mode,
+                user_dictionary,
+            } => Some(
+                TextAnalyzer::builder(LinderaChineseTokenizer::new(
+                    *mode,
+                    user_dictionary.as_deref(),
+                )?)
+                .filter(RemoveLongFilter::limit(DEFAULT_REMOVE_TOKEN_LENGTH))
+                .filter(LowerCaser)
+                .build(),
             ),
-            SearchTokenizer::JapaneseLindera => Some(
-                TextAnalyzer::builder(LinderaJapaneseTokenizer::default())
-                    .filter(RemoveLongFilter::limit(DEFAULT_REMOVE_TOKEN_LENGTH))
-                    .filter(LowerCaser)
-                    .build(),
+            SearchTokenizer::JapaneseLindera {
+                mode,
+                user_dictionary,
+            } => Some(
+                TextAnalyzer::builder(LinderaJapaneseTokenizer::new(
+                    *mode,
+                    user_dictionary.as_deref(),
+                )?)
+                .filter(RemoveLongFilter::limit(DEFAULT_REMOVE_TOKEN_LENGTH))
+                .filter(LowerCaser)
+                .build(),
             ),
-            SearchTokenizer::KoreanLindera => Some(
-                TextAnalyzer::builder(LinderaKoreanTokenizer::default())
-                    .filter(RemoveLongFilter::limit(DEFAULT_REMOVE_TOKEN_LENGTH))
-                    .filter(LowerCaser)
-                    .build(),
+            SearchTokenizer::KoreanLindera {
+                mode,
+                user_dictionary,
+            } => Some(
+                TextAnalyzer::builder(LinderaKoreanTokenizer::new(
+                    *mode,
+                    user_dictionary.as_deref(),
+                )?)
+                .filter(RemoveLongFilter::limit(DEFAULT_REMOVE_TOKEN_LENGTH))
+                .filter(LowerCaser)
+                .build(),
             ),
             #[cfg(feature = "icu")]
             SearchTokenizer::ICUTokenizer => Some(
@@ -81,19 +114,18 @@ pub fn create_tokenizer_manager(search_tokenizers: Vec<&SearchTokenizer>) -> Tok
                     .filter(LowerCaser)
                     .build(),
             ),
+            SearchTokenizer::Custom { .. } => Some(search_tokenizer.text_analyzer(&[])?),
             _ => None,
         };
 
         if let Some(text_analyzer) = tokenizer_option {
-            info!(
-                tokenizer_name = &search_tokenizer.name(),
-                "registering tokenizer",
-            );
-            tokenizer_manager.register(&search_tokenizer.name(), text_analyzer);
+            let tokenizer_name = search_tokenizer.name_with_filters(filters);
+            info!(tokenizer_name, "registering tokenizer");
+            tokenizer_manager.register(&tokenizer_name, text_analyzer);
         }
     }
 
-    tokenizer_manager
+    Ok(tokenizer_manager)
 }
 
 pub fn create_normalizer_manager() -> TokenizerManager {
@@ -104,8 +136,44 @@ pub fn create_normalizer_manager() -> TokenizerManager {
         .filter(LowerCaser)
         .filter(RemoveLongFilter::limit(DEFAULT_REMOVE_TOKEN_LENGTH))
         .build();
+    let trim_tokenizer = TextAnalyzer::builder(RawTokenizer::default())
+        .filter(NormalizationFilter(TextNormalization::Trim))
+        .filter(RemoveLongFilter::limit(DEFAULT_REMOVE_TOKEN_LENGTH))
+        .build();
+    let ascii_folding_tokenizer = TextAnalyzer::builder(RawTokenizer::default())
+        .filter(LowerCaser)
+        .filter(AsciiFoldingFilter)
+        .filter(RemoveLongFilter::limit(DEFAULT_REMOVE_TOKEN_LENGTH))
+        .build();
+    let unicode_folding_tokenizer = TextAnalyzer::builder(RawTokenizer::default())
+        .filter(LowerCaser)
+        .filter(NormalizationFilter(TextNormalization::UnicodeFolding))
+        .filter(RemoveLongFilter::limit(DEFAULT_REMOVE_TOKEN_LENGTH))
+        .build();
     let tokenizer_manager = TokenizerManager::new();
-    tokenizer_manager.register("raw", raw_tokenizer);
-    tokenizer_manager.register("lowercase", lower_case_tokenizer);
+    tokenizer_manager.register(SearchNormalizer::Raw.name(), raw_tokenizer);
+    tokenizer_manager.register(SearchNormalizer::Lowercase.name(), lower_case_tokenizer);
+    tokenizer_manager.register(SearchNormalizer::Trim.name(), trim_tokenizer);
+    tokenizer_manager.register(
+        SearchNormalizer::AsciiFolding.name(),
+        ascii_folding_tokenizer,
+    );
+    tokenizer_manager.register(
+        SearchNormalizer::UnicodeFolding.name(),
+        unicode_folding_tokenizer,
+    );
+
+    #[cfg(feature = "icu")]
+    {
+        let icu_nfkc_casefold_tokenizer = TextAnalyzer::builder(RawTokenizer::default())
+            .filter(NormalizationFilter(TextNormalization::IcuNfkcCasefold))
+            .filter(RemoveLongFilter::limit(DEFAULT_REMOVE_TOKEN_LENGTH))
+            .build();
+        tokenizer_manager.register(
+            SearchNormalizer::IcuNfkcCasefold.name(),
+            icu_nfkc_casefold_tokenizer,
+        );
+    }
+
     tokenizer_manager
 }
@@ -8,56 +8,244 @@
  *
  */
 
-use lindera_core::mode::Mode;
-use lindera_dictionary::{load_dictionary_from_config, DictionaryConfig, DictionaryKind};
+use lindera_core::mode::{Mode, Penalty};
+use lindera_dictionary::{
+    load_dictionary_from_config, load_user_dictionary_from_config, DictionaryConfig,
+    DictionaryKind, UserDictionaryConfig,
+};
 use lindera_tokenizer::token::Token as LinderaToken;
 use lindera_tokenizer::tokenizer::Tokenizer as LinderaTokenizer;
-use once_cell::sync::Lazy;
+use once_cell::sync::{Lazy, OnceCell};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
 use tantivy::tokenizer::{Token, TokenStream, Tokenizer};
 
-static CMN_TOKENIZER: Lazy<LinderaTokenizer> = Lazy::new(|| {
-    let dictionary_config = DictionaryConfig {
-        kind: Some(DictionaryKind::CcCedict),
-        path: None,
-    };
-    let dictionary = load_dictionary_from_config(dictionary_config)
-        .expect("Lindera `CcCedict` dictionary must be present");
-    LinderaTokenizer::new(dictionary, None, Mode::Normal)
-});
+/// Loading a Lindera dictionary is expensive, so tokenizers are cached for the lifetime of
+/// the process, keyed by dictionary name, segmentation mode, and the path and modification
+/// time of the user dictionary. A user dictionary that's edited is loaded again.
+type LinderaTokenizerKey = (&'static str, LinderaMode, Option<(PathBuf, SystemTime)>);
 
-static JPN_TOKENIZER: Lazy<LinderaTokenizer> = Lazy::new(|| {
-    let dictionary_config = DictionaryConfig {
-        kind: Some(DictionaryKind::IPADIC),
-        path: None,
+static LINDERA_TOKENIZERS: Lazy<Mutex<HashMap<LinderaTokenizerKey, Arc<LinderaTokenizer>>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// The directory that user dictionaries are read from. It must be set with
+/// `set_user_dictionary_dir` before a tokenizer with a user dictionary can be created.
+static USER_DICTIONARY_DIR: OnceCell<PathBuf> = OnceCell::new();
+
+/// Set the directory that user dictionaries are read from. Only the first call has an effect.
+pub fn set_user_dictionary_dir(path: PathBuf) {
+    let _ = USER_DICTIONARY_DIR.set(path);
+}
+
+/// The path of a user dictionary and its modification time. A user dictionary is named by its
+/// path relative to the user dictionary directory, and must not be outside of it, so that an
+/// index can't be used to read arbitrary files of the server.
+fn user_dictionary_file(name: &str) -> Result<(PathBuf, SystemTime), String> {
+    if !Path::new(name)
+        .components()
+        .all(|component| matches!(component, Component::Normal(_)))
+    {
+        return Err(format!(
+            "Lindera user dictionary '{name}' must be a relative path without '..'"
+        ));
+    }
+    let dir = USER_DICTIONARY_DIR
+        .get()
+        .ok_or_else(|| "the Lindera user dictionary directory is not set".to_string())?;
+
+    let not_found = |err| {
+        format!(
+            "could not load Lindera user dictionary '{name}' in '{}': {err}",
+            dir.display()
+        )
     };
-    let dictionary = load_dictionary_from_config(dictionary_config)
-        .expect("Lindera `IPADIC` dictionary must be present");
-    LinderaTokenizer::new(dictionary, None, Mode::Normal)
-});
+    // A symbolic link could still point outside of the directory.
+    let path = fs::canonicalize(dir.join(name)).map_err(not_found)?;
+    if !fs::canonicalize(dir).is_ok_and(|dir| path.starts_with(dir)) {
+        return Err(format!(
+            "Lindera user dictionary '{name}' is outside of '{}'",
+            dir.display()
+        ));
+    }
+    let modified = fs::metadata(&path)
+        .and_then(|metadata| metadata.modified())
+        .map_err(not_found)?;
+    Ok((path, modified))
+}
+
+/// The segmentation mode used by the Lindera tokenizers.
+#[derive(Default, Copy, Clone, Deserialize, Serialize, Debug, PartialEq, Eq, Hash)]
+pub enum LinderaMode {
+    /// Segments text using the dictionary entries as-is.
+    #[serde(rename = "normal")]
+    #[default]
+    Normal,
+    /// Additionally splits long compound nouns into their components.
+    #[serde(rename = "decompose")]
+    Decompose,
+}
+
+impl LinderaMode {
+    pub fn name(&self) -> &str {
+        match self {
+            LinderaMode::Normal => "normal",
+            LinderaMode::Decompose => "decompose",
+        }
+    }
+}
+
+impl From<LinderaMode> for Mode {
+    fn from(value: LinderaMode) -> Self {
+        match value {
+            LinderaMode::Normal => Mode::Normal,
+            LinderaMode::Decompose => Mode::Decompose(Penalty::default()),
+        }
+    }
+}
+
+/// The cached tokenizer for the given dictionary and options, or an error if its user
+/// dictionary can't be loaded. Only tokenizers that could be loaded are cached.
+fn lindera_tokenizer(
+    kind: DictionaryKind,
+    dictionary_name: &'static str,
+    mode: LinderaMode,
+    user_dictionary: Option<&str>,
+) -> Result<Arc<LinderaTokenizer>, String> {
+    let user_dictionary_file = user_dictionary.map(user_dictionary_file).transpose()?;
+    let key = (dictionary_name, mode, user_dictionary_file);
+    if let Some(tokenizer) = LINDERA_TOKENIZERS
+        .lock()
+        .expect("could not lock Lindera tokenizer cache")
+        .get(&key)
+    {
+        return Ok(tokenizer.clone());
+    }
+
+    // The dictionaries are loaded without holding the lock, so that other tokenizers can be
+    // looked up in the meantime. A user dictionary can be a CSV file of additional entries,
+    // or a binary dictionary compiled from one with the Lindera CLI.
+    let user_dictionary = key
+        .2
+        .as_ref()
+        .map(|(path, _)| {
+            let user_dictionary_config = UserDictionaryConfig {
+                kind: Some(kind.clone()),
+                path: path.clone(),
+            };
+            load_user_dictionary_from_config(user_dictionary_config).map_err(|err| {
+                format!(
+                    "could not load Lindera user dictionary at '{}': {err}",
+                    path.display()
+                )
+            })
+        })
+        .transpose()?;
 
-static KOR_TOKENIZER: Lazy<LinderaTokenizer> = Lazy::new(|| {
     let dictionary_config = DictionaryConfig {
-        kind: Some(DictionaryKind::KoDic),
+        kind: Some(kind),
         path: None,
     };
     let dictionary = load_dictionary_from_config(dictionary_config)
-        .expect("Lindera `KoDic` dictionary must be present");
-    LinderaTokenizer::new(dictionary, None, Mode::Normal)
-});
+        .map_err(|err| format!("could not load Lindera `{dictionary_name}` dictionary: {err}"))?;
+
+    let tokenizer = Arc::new(LinderaTokenizer::new(
+        dictionary,
+        user_dictionary,
+        mode.into(),
+    ));
+
+    let mut tokenizers = LINDERA_TOKENIZERS
+        .lock()
+        .expect("could not lock Lindera tokenizer cache");
+    // The tokenizers of earlier versions of the user dictionary won't be used again.
+    if let Some((path, modified)) = &key.2 {
+        tokenizers.retain(|(name, cached_mode, file), _| {
+            *name != dictionary_name
+                || *cached_mode != mode
+                || !file.as_ref().is_some_and(|(cached_path, cached_modified)| {
+                    cached_path == path && cached_modified != modified
+                })
+        });
+    }
+    Ok(tokenizers.entry(key).or_insert(tokenizer).clone())
+}
 
-#[derive(Clone, Default)]
+#[derive(Clone)]
 pub struct LinderaChineseTokenizer {
+    tokenizer: Arc<LinderaTokenizer>,
     token: Token,
 }
-#[derive(Clone, Default)]
+#[derive(Clone)]
 pub struct LinderaJapaneseTokenizer {
+    tokenizer: Arc<LinderaTokenizer>,
     token: Token,
 }
-#[derive(Clone, Default)]
+#[derive(Clone)]
 pub struct LinderaKoreanTokenizer {
+    tokenizer: Arc<LinderaTokenizer>,
     token: Token,
 }
 
+impl LinderaChineseTokenizer {
+    /// Fails if the dictionary or the user dictionary, named by its path in the user
+    /// dictionary directory, can't be loaded.
+    pub fn new(mode: LinderaMode, user_dictionary: Option<&str>) -> Result<Self, String> {
+        Ok(Self {
+            tokenizer: lindera_tokenizer(
+                DictionaryKind::CcCedict,
+                "CcCedict",
+                mode,
+                user_dictionary,
+            )?,
+            token: Token::default(),
+        })
+    }
+}
+
+impl LinderaJapaneseTokenizer {
+    /// Fails if the dictionary or the user dictionary, named by its path in the user
+    /// dictionary directory, can't be loaded.
+    pub fn new(mode: LinderaMode, user_dictionary: Option<&str>) -> Result<Self, String> {
+        Ok(Self {
+            tokenizer: lindera_tokenizer(DictionaryKind::IPADIC, "IPADIC", mode, user_dictionary)?,
+            token: Token::default(),
+        })
+    }
+}
+
+impl LinderaKoreanTokenizer {
+    /// Fails if the dictionary or the user dictionary, named by its path in the user
+    /// dictionary directory, can't be loaded.
+    pub fn new(mode: LinderaMode, user_dictionary: Option<&str>) -> Result<Self, String> {
+        Ok(Self {
+            tokenizer: lindera_tokenizer(DictionaryKind::KoDic, "KoDic", mode, user_dictionary)?,
+            token: Token::default(),
+        })
+    }
+}
+
+impl Default for LinderaChineseTokenizer {
+    fn default() -> Self {
+        Self::new(LinderaMode::default(), None).unwrap_or_else(|err| panic!("{err}"))
+    }
+}
+
+impl Default for LinderaJapaneseTokenizer {
+    fn default() -> Self {
+        Self::new(LinderaMode::default(), None).unwrap_or_else(|err| panic!("{err}"))
+    }
+}
+
+impl Default for LinderaKoreanTokenizer {
+    fn default() -> Self {
+        Self::new(LinderaMode::default(), None).unwrap_or_else(|err| panic!("{err}"))
+    }
+}
+
 impl Tokenizer for LinderaChineseTokenizer {
     type TokenStream<'a> = MultiLanguageTokenStream<'a>;
 
@@ -66,11 +254,12 @@ impl Tokenizer for LinderaChineseTokenizer {
             return MultiLanguageTokenStream::Empty;
         }
 
+        let Self { tokenizer, token } = self;
         let lindera_token_stream = LinderaTokenStream {
-            tokens: CMN_TOKENIZER
+            tokens: tokenizer
                 .tokenize(text)
                 .expect("Lindera Chinese tokenizer failed"),
-            token: &mut self.token,
+            token,
         };
 
         MultiLanguageTokenStream::Lindera(lindera_token_stream)
@@ -85,11 +274,12 @@ impl Tokenizer for LinderaJapaneseTokenizer {
             return MultiLanguageTokenStream::Empty;
         }
 
+        let Self { tokenizer, token } = self;
         let lindera_token_stream = LinderaTokenStream {
-            tokens: JPN_TOKENIZER
+            tokens: tokenizer
                 .tokenize(text)
                 .expect("Lindera Japanese tokenizer failed"),
-            token: &mut self.token,
+            token,
         };
 
         MultiLanguageTokenStream::Lindera(lindera_token_stream)
@@ -104,11 +294,12 @@ impl Tokenizer for LinderaKoreanTokenizer {
             return MultiLanguageTokenStream::Empty;
         }
 
+        let Self { tokenizer, token } = self;
         let lindera_token_stream = LinderaTokenStream {
-            tokens: KOR_TOKENIZER
+            tokens: tokenizer
                 .tokenize(text)
                 .expect("Lindera Korean tokenizer failed"),
-            token: &mut self.token,
+            token,
         };
 
         MultiLanguageTokenStream::Lindera(lindera_token_stream)
@@ -243,6 +434,85 @@ mod tests {
         }
     }
 
+    #[rstest]
+    fn test_japanese_tokenizer_decompose_mode() {
+        let mut tokenizer = LinderaJapaneseTokenizer::new(LinderaMode::Normal, None).unwrap();
+        let tokens = test_helper(&mut tokenizer, "関西国際空港限定トートバッグ");
+        assert_eq!(tokens.len(), 3);
+        assert_eq!(tokens[0].text, "関西国際空港");
+
+        let mut tokenizer = LinderaJapaneseTokenizer::new(LinderaMode::Decompose, None).unwrap();
+        let tokens = test_helper(&mut tokenizer, "関西国際空港限定トートバッグ");
+        assert_eq!(tokens.len(), 5);
+        assert_eq!(tokens[0].text, "関西");
+        assert_eq!(tokens[1].text, "国際");
+        assert_eq!(tokens[2].text, "空港");
+    }
+
+    /// The user dictionary directory of the tests, which is the same for every test because
+    /// it can only be set once.
+    fn user_dictionary_dir() -> PathBuf {
+        let dir = std::env::temp_dir().join("pg_search_lindera_dictionaries");
+        fs::create_dir_all(&dir).unwrap();
+        set_user_dictionary_dir(dir.clone());
+        dir
+    }
+
+    #[rstest]
+    fn test_japanese_tokenizer_user_dictionary() {
+        let text = "東京スカイツリーの最寄り駅はとうきょうスカイツリー駅です";
+
+        let mut tokenizer = LinderaJapaneseTokenizer::default();
+        let tokens = test_helper(&mut tokenizer, text);
+        assert_eq!(tokens[0].text, "東京");
+
+        fs::write(
+            user_dictionary_dir().join("userdic.csv"),
+            "東京スカイツリー,カスタム名詞,トウキョウスカイツリー\n",
+        )
+        .unwrap();
+
+        let mut tokenizer =
+            LinderaJapaneseTokenizer::new(LinderaMode::Normal, Some("userdic.csv")).unwrap();
+        let tokens = test_helper(&mut tokenizer, text);
+        {
+            let token = &tokens[0];
+            assert_eq!(token.text, "東京スカイツリー");
+            assert_eq!(token.offset_from, 0);
+            assert_eq!(token.offset_to, 24);
+        }
+    }
+
+    #[rstest]
+    #[case::absolute("/etc/passwd")]
+    #[case::parent("../userdic.csv")]
+    #[case::nested_parent("nested/../../userdic.csv")]
+    fn test_japanese_tokenizer_user_dictionary_outside_dir(#[case] name: &str) {
+        user_dictionary_dir();
+
+        let Err(err) = LinderaJapaneseTokenizer::new(LinderaMode::Normal, Some(name)) else {
+            panic!("user dictionary '{name}' should be rejected");
+        };
+        assert!(
+            err.contains("must be a relative path without '..'"),
+            "{err}"
+        );
+    }
+
+    #[rstest]
+    fn test_japanese_tokenizer_user_dictionary_missing() {
+        user_dictionary_dir();
+
+        let Err(err) = LinderaJapaneseTokenizer::new(LinderaMode::Normal, Some("missing.csv"))
+        else {
+            panic!("a missing user dictionary should be rejected");
+        };
+        assert!(
+            err.contains("could not load Lindera user dictionary 'missing.csv'"),
+            "{err}"
+        );
+    }
+
     #[rstest]
     fn test_lindera_chinese_tokenizer_with_empty_string() {
         let mut tokenizer = LinderaChineseTokenizer::default();
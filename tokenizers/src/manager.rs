@@ -1,13 +1,18 @@
 use serde::*;
 use tantivy::tokenizer::{
     AsciiFoldingFilter, Language, LowerCaser, NgramTokenizer, RawTokenizer, RemoveLongFilter,
-    SimpleTokenizer, Stemmer, TextAnalyzer, WhitespaceTokenizer,
+    SimpleTokenizer, Stemmer, TextAnalyzer, TextAnalyzerBuilder, WhitespaceTokenizer,
 };
+use tantivy::TantivyError;
 
 use crate::code::CodeTokenizer;
+use crate::edge_ngram::EdgeNgramFilter;
 #[cfg(feature = "icu")]
 use crate::icu::ICUTokenizer;
-use crate::lindera::{LinderaJapaneseTokenizer, LinderaKoreanTokenizer};
+use crate::lindera::{LinderaJapaneseTokenizer, LinderaKoreanTokenizer, LinderaMode};
+use crate::phonetic::{PhoneticEncoder, PhoneticFilter};
+use crate::registry::custom_text_analyzer_builder;
+use crate::shingle::ShingleFilter;
 use crate::{cjk::ChineseTokenizer, lindera::LinderaChineseTokenizer};
 
 pub const DEFAULT_REMOVE_TOKEN_LENGTH: usize = 255;
@@ -15,7 +20,7 @@ pub const DEFAULT_REMOVE_TOKEN_LENGTH: usize = 255;
 // Serde will pick a SearchTokenizer variant based on the value of the
 // "type" key, which needs to match one of the variant names below.
 // The "type" field will not be present on the deserialized value.
-#[derive(Default, Copy, Clone, Deserialize, Debug, Serialize, PartialEq, Eq)]
+#[derive(Default, Clone, Deserialize, Debug, Serialize, PartialEq, Eq)]
 #[serde(tag = "type")]
 pub enum SearchTokenizer {
     #[serde(rename = "default")]
@@ -38,14 +43,32 @@ pub enum SearchTokenizer {
         prefix_only: bool,
     },
     #[serde(rename = "chinese_lindera")]
-    ChineseLindera,
+    ChineseLindera {
+        #[serde(default)]
+        mode: LinderaMode,
+        #[serde(default)]
+        user_dictionary: Option<String>,
+    },
     #[serde(rename = "japanese_lindera")]
-    JapaneseLindera,
+    JapaneseLindera {
+        #[serde(default)]
+        mode: LinderaMode,
+        #[serde(default)]
+        user_dictionary: Option<String>,
+    },
     #[serde(rename = "korean_lindera")]
-    KoreanLindera,
+    KoreanLindera {
+        #[serde(default)]
+        mode: LinderaMode,
+        #[serde(default)]
+        user_dictionary: Option<String>,
+    },
     #[cfg(feature = "icu")]
     #[serde(rename = "icu")]
     ICUTokenizer,
+    /// A tokenizer registered at runtime with `register_custom_tokenizer`.
+    #[serde(rename = "custom")]
+    Custom { name: String },
 }
 
 impl SearchTokenizer {
@@ -62,43 +85,124 @@ impl SearchTokenizer {
                 max_gram,
                 prefix_only,
             } => format!("ngram_mingram:{min_gram}_maxgram:{max_gram}_prefixonly:{prefix_only}"),
-            SearchTokenizer::ChineseLindera => "chinese_lindera".into(),
-            SearchTokenizer::JapaneseLindera => "japanese_lindera".into(),
-            SearchTokenizer::KoreanLindera => "korean_lindera".into(),
+            SearchTokenizer::ChineseLindera {
+                mode,
+                user_dictionary,
+            } => lindera_tokenizer_name("chinese_lindera", mode, user_dictionary),
+            SearchTokenizer::JapaneseLindera {
+                mode,
+                user_dictionary,
+            } => lindera_tokenizer_name("japanese_lindera", mode, user_dictionary),
+            SearchTokenizer::KoreanLindera {
+                mode,
+                user_dictionary,
+            } => lindera_tokenizer_name("korean_lindera", mode, user_dictionary),
             #[cfg(feature = "icu")]
             SearchTokenizer::ICUTokenizer => "icu".into(),
+            SearchTokenizer::Custom { name } => format!("custom_name:{name}"),
         }
     }
 }
 
-impl From<SearchTokenizer> for TextAnalyzer {
-    fn from(val: SearchTokenizer) -> Self {
-        match val {
+/// The default Lindera configuration keeps its original name, so that indexes created before
+/// segmentation modes and user dictionaries were configurable can still find their tokenizer.
+fn lindera_tokenizer_name(
+    base_name: &str,
+    mode: &LinderaMode,
+    user_dictionary: &Option<String>,
+) -> String {
+    match (mode, user_dictionary) {
+        (LinderaMode::Normal, None) => base_name.into(),
+        (mode, None) => format!("{base_name}_mode:{}", mode.name()),
+        (mode, Some(path)) => format!("{base_name}_mode:{}_userdict:{path}", mode.name()),
+    }
+}
+
+impl SearchTokenizer {
+    /// Build a text analyzer for this tokenizer, running the given token filters,
+    /// in order, on its output. Fails if the tokenizer can't be created, like when its
+    /// dictionary can't be loaded.
+    pub fn text_analyzer(&self, filters: &[SearchTokenFilter]) -> Result<TextAnalyzer, String> {
+        Ok(filters
+            .iter()
+            .fold(self.clone().text_analyzer_builder()?, |builder, filter| {
+                filter.apply(builder)
+            })
+            .build())
+    }
+
+    /// Checks the tokenizer's options, so that an index with invalid options is rejected when
+    /// it's created, rather than failing when its tokenizer is first used.
+    pub fn validate(&self) -> Result<(), String> {
+        match self {
+            SearchTokenizer::Ngram {
+                min_gram,
+                max_gram,
+                prefix_only,
+            } => NgramTokenizer::new(*min_gram, *max_gram, *prefix_only)
+                .map(|_| ())
+                .map_err(|err| match err {
+                    TantivyError::InvalidArgument(message) => message,
+                    err => err.to_string(),
+                }),
+            SearchTokenizer::Custom { name } => custom_text_analyzer_builder(name)
+                .map(|_| ())
+                .map_err(|err| err.to_string()),
+            // The built-in dictionaries are always present, so only a user dictionary needs to
+            // be loaded to know that the tokenizer can be built.
+            SearchTokenizer::ChineseLindera {
+                mode,
+                user_dictionary: Some(path),
+            } => LinderaChineseTokenizer::new(*mode, Some(path)).map(|_| ()),
+            SearchTokenizer::JapaneseLindera {
+                mode,
+                user_dictionary: Some(path),
+            } => LinderaJapaneseTokenizer::new(*mode, Some(path)).map(|_| ()),
+            SearchTokenizer::KoreanLindera {
+                mode,
+                user_dictionary: Some(path),
+            } => LinderaKoreanTokenizer::new(*mode, Some(path)).map(|_| ()),
+            _ => Ok(()),
+        }
+    }
+
+    /// The name to register the tokenizer under when the given token filters are applied.
+    pub fn name_with_filters(&self, filters: &[SearchTokenFilter]) -> String {
+        if filters.is_empty() {
+            return self.name();
+        }
+
+        let filter_names: Vec<_> = filters.iter().map(|filter| filter.name()).collect();
+        format!("{}_filters:{}", self.name(), filter_names.join(","))
+    }
+
+    fn text_analyzer_builder(self) -> Result<TextAnalyzerBuilder, String> {
+        Ok(match self {
             SearchTokenizer::Default => TextAnalyzer::builder(SimpleTokenizer::default())
                 .filter(RemoveLongFilter::limit(DEFAULT_REMOVE_TOKEN_LENGTH))
                 .filter(LowerCaser)
-                .build(),
+                .dynamic(),
             SearchTokenizer::WhiteSpace => TextAnalyzer::builder(WhitespaceTokenizer::default())
                 .filter(RemoveLongFilter::limit(DEFAULT_REMOVE_TOKEN_LENGTH))
                 .filter(LowerCaser)
-                .build(),
+                .dynamic(),
             SearchTokenizer::EnStem => TextAnalyzer::builder(SimpleTokenizer::default())
                 .filter(RemoveLongFilter::limit(40))
                 .filter(LowerCaser)
                 .filter(Stemmer::new(Language::English))
-                .build(),
+                .dynamic(),
             SearchTokenizer::Raw => TextAnalyzer::builder(RawTokenizer::default())
                 .filter(RemoveLongFilter::limit(DEFAULT_REMOVE_TOKEN_LENGTH))
-                .build(),
+                .dynamic(),
             SearchTokenizer::ChineseCompatible => TextAnalyzer::builder(ChineseTokenizer)
                 .filter(RemoveLongFilter::limit(DEFAULT_REMOVE_TOKEN_LENGTH))
                 .filter(LowerCaser)
-                .build(),
+                .dynamic(),
             SearchTokenizer::SourceCode => TextAnalyzer::builder(CodeTokenizer::default())
                 .filter(RemoveLongFilter::limit(DEFAULT_REMOVE_TOKEN_LENGTH))
                 .filter(LowerCaser)
                 .filter(AsciiFoldingFilter)
-                .build(),
+                .dynamic(),
             SearchTokenizer::Ngram {
                 min_gram,
                 max_gram,
@@ -107,31 +211,153 @@ impl From<SearchTokenizer> for TextAnalyzer {
                 TextAnalyzer::builder(NgramTokenizer::new(min_gram, max_gram, prefix_only).unwrap())
                     .filter(RemoveLongFilter::limit(DEFAULT_REMOVE_TOKEN_LENGTH))
                     .filter(LowerCaser)
-                    .build()
-            }
-            SearchTokenizer::ChineseLindera => {
-                TextAnalyzer::builder(LinderaChineseTokenizer::default())
-                    .filter(RemoveLongFilter::limit(DEFAULT_REMOVE_TOKEN_LENGTH))
-                    .filter(LowerCaser)
-                    .build()
-            }
-            SearchTokenizer::JapaneseLindera => {
-                TextAnalyzer::builder(LinderaJapaneseTokenizer::default())
-                    .filter(RemoveLongFilter::limit(DEFAULT_REMOVE_TOKEN_LENGTH))
-                    .filter(LowerCaser)
-                    .build()
-            }
-            SearchTokenizer::KoreanLindera => {
-                TextAnalyzer::builder(LinderaKoreanTokenizer::default())
-                    .filter(RemoveLongFilter::limit(DEFAULT_REMOVE_TOKEN_LENGTH))
-                    .filter(LowerCaser)
-                    .build()
+                    .dynamic()
             }
+            SearchTokenizer::ChineseLindera {
+                mode,
+                user_dictionary,
+            } => TextAnalyzer::builder(LinderaChineseTokenizer::new(
+                mode,
+                user_dictionary.as_deref(),
+            )?)
+            .filter(RemoveLongFilter::limit(DEFAULT_REMOVE_TOKEN_LENGTH))
+            .filter(LowerCaser)
+            .dynamic(),
+            SearchTokenizer::JapaneseLindera {
+                mode,
+                user_dictionary,
+            } => TextAnalyzer::builder(LinderaJapaneseTokenizer::new(
+                mode,
+                user_dictionary.as_deref(),
+            )?)
+            .filter(RemoveLongFilter::limit(DEFAULT_REMOVE_TOKEN_LENGTH))
+            .filter(LowerCaser)
+            .dynamic(),
+            SearchTokenizer::KoreanLindera {
+                mode,
+                user_dictionary,
+            } => TextAnalyzer::builder(LinderaKoreanTokenizer::new(
+                mode,
+                user_dictionary.as_deref(),
+            )?)
+            .filter(RemoveLongFilter::limit(DEFAULT_REMOVE_TOKEN_LENGTH))
+            .filter(LowerCaser)
+            .dynamic(),
             #[cfg(feature = "icu")]
             SearchTokenizer::ICUTokenizer => TextAnalyzer::builder(ICUTokenizer)
                 .filter(RemoveLongFilter::limit(DEFAULT_REMOVE_TOKEN_LENGTH))
                 .filter(LowerCaser)
-                .build(),
+                .dynamic(),
+            SearchTokenizer::Custom { name } => {
+                custom_text_analyzer_builder(&name).map_err(|err| err.to_string())?
+            }
+        })
+    }
+}
+
+impl TryFrom<SearchTokenizer> for TextAnalyzer {
+    type Error = String;
+
+    fn try_from(val: SearchTokenizer) -> Result<Self, Self::Error> {
+        val.text_analyzer(&[])
+    }
+}
+
+// Token filters run in order on the output of a field's tokenizer. As with
+// SearchTokenizer, serde picks a variant based on the value of the "type" key.
+#[derive(Clone, Deserialize, Debug, Serialize, PartialEq, Eq)]
+#[serde(tag = "type")]
+pub enum SearchTokenFilter {
+    #[serde(rename = "phonetic")]
+    Phonetic {
+        encoder: PhoneticEncoder,
+        #[serde(default)]
+        keep_original: bool,
+    },
+    #[serde(rename = "edge_ngram")]
+    EdgeNgram { min_gram: usize, max_gram: usize },
+    #[serde(rename = "shingle")]
+    Shingle {
+        #[serde(default = "default_shingle_size")]
+        min_shingle_size: usize,
+        #[serde(default = "default_shingle_size")]
+        max_shingle_size: usize,
+        #[serde(default = "default_as_true")]
+        output_unigrams: bool,
+    },
+}
+
+fn default_shingle_size() -> usize {
+    2
+}
+
+fn default_as_true() -> bool {
+    true
+}
+
+impl SearchTokenFilter {
+    pub fn name(&self) -> String {
+        match self {
+            SearchTokenFilter::Phonetic {
+                encoder,
+                keep_original,
+            } => format!(
+                "phonetic_encoder:{}_keeporiginal:{keep_original}",
+                encoder.name()
+            ),
+            SearchTokenFilter::EdgeNgram { min_gram, max_gram } => {
+                format!("edge_ngram_mingram:{min_gram}_maxgram:{max_gram}")
+            }
+            SearchTokenFilter::Shingle {
+                min_shingle_size,
+                max_shingle_size,
+                output_unigrams,
+            } => format!(
+                "shingle_minsize:{min_shingle_size}_maxsize:{max_shingle_size}_unigrams:{output_unigrams}"
+            ),
+        }
+    }
+
+    /// Checks the filter's options, so that an index with invalid options is rejected when
+    /// it's created, rather than failing when its tokenizer is first used.
+    pub fn validate(&self) -> Result<(), String> {
+        let result = match self {
+            SearchTokenFilter::Phonetic { .. } => Ok(()),
+            SearchTokenFilter::EdgeNgram { min_gram, max_gram } => {
+                EdgeNgramFilter::new(*min_gram, *max_gram).map(|_| ())
+            }
+            SearchTokenFilter::Shingle {
+                min_shingle_size,
+                max_shingle_size,
+                output_unigrams,
+            } => ShingleFilter::new(*min_shingle_size, *max_shingle_size, *output_unigrams)
+                .map(|_| ()),
+        };
+
+        result.map_err(|err| match err {
+            TantivyError::InvalidArgument(message) => message,
+            err => err.to_string(),
+        })
+    }
+
+    fn apply(&self, builder: TextAnalyzerBuilder) -> TextAnalyzerBuilder {
+        match self {
+            SearchTokenFilter::Phonetic {
+                encoder,
+                keep_original,
+            } => builder.filter_dynamic(PhoneticFilter::new(*encoder, *keep_original)),
+            SearchTokenFilter::EdgeNgram { min_gram, max_gram } => builder.filter_dynamic(
+                EdgeNgramFilter::new(*min_gram, *max_gram)
+                    .expect("edge_ngram options should be validated when the index is created"),
+            ),
+            SearchTokenFilter::Shingle {
+                min_shingle_size,
+                max_shingle_size,
+                output_unigrams,
+            } => builder.filter_dynamic(
+                ShingleFilter::new(*min_shingle_size, *max_shingle_size, *output_unigrams)
+                    .expect("shingle options should be validated when the index is created"),
+            ),
         }
     }
 }
@@ -144,6 +370,15 @@ pub enum SearchNormalizer {
     Raw,
     #[serde(rename = "lowercase")]
     Lowercase,
+    #[serde(rename = "trim")]
+    Trim,
+    #[serde(rename = "ascii_folding")]
+    AsciiFolding,
+    #[serde(rename = "unicode_folding")]
+    UnicodeFolding,
+    #[cfg(feature = "icu")]
+    #[serde(rename = "icu_nfkc_casefold")]
+    IcuNfkcCasefold,
 }
 
 impl SearchNormalizer {
@@ -151,6 +386,11 @@ impl SearchNormalizer {
         match self {
             SearchNormalizer::Raw => "raw",
             SearchNormalizer::Lowercase => "lowercase",
+            SearchNormalizer::Trim => "trim",
+            SearchNormalizer::AsciiFolding => "ascii_folding",
+            SearchNormalizer::UnicodeFolding => "unicode_folding",
+            #[cfg(feature = "icu")]
+            SearchNormalizer::IcuNfkcCasefold => "icu_nfkc_casefold",
         }
     }
 }
@@ -185,9 +425,172 @@ mod tests {
         );
     }
 
+    #[rstest]
+    fn test_search_tokenizer_lindera() {
+        let json = r#"{"type": "japanese_lindera"}"#;
+        let tokenizer: SearchTokenizer = serde_json::from_str(json).unwrap();
+        assert_eq!(
+            tokenizer,
+            SearchTokenizer::JapaneseLindera {
+                mode: LinderaMode::Normal,
+                user_dictionary: None
+            }
+        );
+        assert_eq!(tokenizer.name(), "japanese_lindera");
+
+        let json = r#"{
+            "type": "japanese_lindera",
+            "mode": "decompose",
+            "user_dictionary": "products.csv"
+        }"#;
+        let tokenizer: SearchTokenizer = serde_json::from_str(json).unwrap();
+        assert_eq!(
+            tokenizer,
+            SearchTokenizer::JapaneseLindera {
+                mode: LinderaMode::Decompose,
+                user_dictionary: Some("products.csv".into())
+            }
+        );
+        assert_eq!(
+            tokenizer.name(),
+            "japanese_lindera_mode:decompose_userdict:products.csv"
+        );
+    }
+
+    #[rstest]
+    fn test_search_tokenizer_custom() {
+        let json = r#"{"type": "custom", "name": "part_numbers"}"#;
+        let tokenizer: SearchTokenizer = serde_json::from_str(json).unwrap();
+        assert_eq!(
+            tokenizer,
+            SearchTokenizer::Custom {
+                name: "part_numbers".into()
+            }
+        );
+        assert_eq!(tokenizer.name(), "custom_name:part_numbers");
+    }
+
+    #[rstest]
+    fn test_search_tokenizer_validate() {
+        assert_eq!(SearchTokenizer::Default.validate(), Ok(()));
+        assert!(SearchTokenizer::Ngram {
+            min_gram: 4,
+            max_gram: 2,
+            prefix_only: false
+        }
+        .validate()
+        .is_err());
+
+        let tokenizer = SearchTokenizer::Custom {
+            name: "test_manager_unregistered".into(),
+        };
+        assert_eq!(
+            tokenizer.validate(),
+            Err("no custom tokenizer named 'test_manager_unregistered' is registered".into())
+        );
+
+        let tokenizer = SearchTokenizer::JapaneseLindera {
+            mode: LinderaMode::Normal,
+            user_dictionary: Some("/etc/passwd".into()),
+        };
+        assert_eq!(
+            tokenizer.validate(),
+            Err(
+                "Lindera user dictionary '/etc/passwd' must be a relative path without '..'".into()
+            )
+        );
+
+        let tokenizer = SearchTokenizer::KoreanLindera {
+            mode: LinderaMode::Normal,
+            user_dictionary: Some("../userdic.csv".into()),
+        };
+        assert_eq!(
+            tokenizer.validate(),
+            Err(
+                "Lindera user dictionary '../userdic.csv' must be a relative path without '..'"
+                    .into()
+            )
+        );
+    }
+
+    #[rstest]
+    fn test_search_token_filter() {
+        let json = r#"[{"type": "phonetic", "encoder": "double_metaphone"}]"#;
+        let filters: Vec<SearchTokenFilter> = serde_json::from_str(json).unwrap();
+        assert_eq!(
+            filters,
+            vec![SearchTokenFilter::Phonetic {
+                encoder: PhoneticEncoder::DoubleMetaphone,
+                keep_original: false
+            }]
+        );
+
+        let tokenizer = SearchTokenizer::Default;
+        assert_eq!(tokenizer.name_with_filters(&[]), "default");
+        assert_eq!(
+            tokenizer.name_with_filters(&filters),
+            "default_filters:phonetic_encoder:double_metaphone_keeporiginal:false"
+        );
+    }
+
+    #[rstest]
+    fn test_search_token_filter_ngrams() {
+        let json = r#"[
+            {"type": "edge_ngram", "min_gram": 2, "max_gram": 10},
+            {"type": "shingle", "max_shingle_size": 3}
+        ]"#;
+        let filters: Vec<SearchTokenFilter> = serde_json::from_str(json).unwrap();
+        assert_eq!(
+            filters,
+            vec![
+                SearchTokenFilter::EdgeNgram {
+                    min_gram: 2,
+                    max_gram: 10
+                },
+                SearchTokenFilter::Shingle {
+                    min_shingle_size: 2,
+                    max_shingle_size: 3,
+                    output_unigrams: true
+                }
+            ]
+        );
+        assert_eq!(
+            SearchTokenizer::WhiteSpace.name_with_filters(&filters),
+            "whitespace_filters:edge_ngram_mingram:2_maxgram:10,shingle_minsize:2_maxsize:3_unigrams:true"
+        );
+    }
+
+    #[rstest]
+    #[case::edge_ngram(r#"{"type": "edge_ngram", "min_gram": 2, "max_gram": 10}"#, None)]
+    #[case::edge_ngram_zero(
+        r#"{"type": "edge_ngram", "min_gram": 0, "max_gram": 10}"#,
+        Some("min_gram must be greater than 0")
+    )]
+    #[case::edge_ngram_reversed(
+        r#"{"type": "edge_ngram", "min_gram": 5, "max_gram": 2}"#,
+        Some("min_gram must not be greater than max_gram")
+    )]
+    #[case::shingle(r#"{"type": "shingle", "max_shingle_size": 3}"#, None)]
+    #[case::shingle_unigram(
+        r#"{"type": "shingle", "min_shingle_size": 1}"#,
+        Some("min_shingle_size must be at least 2")
+    )]
+    #[case::shingle_reversed(
+        r#"{"type": "shingle", "min_shingle_size": 3, "max_shingle_size": 2}"#,
+        Some("min_shingle_size must not be greater than max_shingle_size")
+    )]
+    fn test_search_token_filter_validate(#[case] json: &str, #[case] expected: Option<&str>) {
+        let filter: SearchTokenFilter = serde_json::from_str(json).unwrap();
+        assert_eq!(filter.validate().err().as_deref(), expected);
+    }
+
     #[rstest]
     fn test_search_normalizer() {
         assert_eq!(SearchNormalizer::Lowercase.name(), "lowercase");
         assert_ne!(SearchNormalizer::Raw, SearchNormalizer::Lowercase);
+
+        let normalizer: SearchNormalizer = serde_json::from_str(r#""unicode_folding""#).unwrap();
+        assert_eq!(normalizer, SearchNormalizer::UnicodeFolding);
+        assert_eq!(normalizer.name(), "unicode_folding");
     }
 }
@@ -0,0 +1,119 @@
+use tantivy::tokenizer::{Token, TokenFilter, TokenStream, Tokenizer};
+use unicode_normalization::char::is_combining_mark;
+use unicode_normalization::UnicodeNormalization;
+
+/// A transformation applied to the whole text of each token. These are meant for the
+/// normalizers of fast fields, where the text of a field is a single token.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum TextNormalization {
+    /// Removes leading and trailing whitespace.
+    Trim,
+    /// Applies NFKD decomposition and strips combining marks, so that "Café" becomes "Cafe".
+    /// Unlike ASCII folding, characters without a decomposition are kept as-is.
+    UnicodeFolding,
+    /// Applies ICU's NFKC_Casefold normalization.
+    #[cfg(feature = "icu")]
+    IcuNfkcCasefold,
+}
+
+impl TextNormalization {
+    fn normalize(&self, text: &str) -> String {
+        match self {
+            TextNormalization::Trim => text.trim().to_string(),
+            TextNormalization::UnicodeFolding => text
+                .nfkd()
+                .filter(|c| !is_combining_mark(*c))
+                .nfc()
+                .collect(),
+            #[cfg(feature = "icu")]
+            TextNormalization::IcuNfkcCasefold => rust_icu_unorm2::UNormalizer::new_nfkc_casefold()
+                .and_then(|normalizer| normalizer.normalize(text))
+                .expect("NFKC_Casefold normalization should not fail"),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct NormalizationFilter(pub TextNormalization);
+
+impl TokenFilter for NormalizationFilter {
+    type Tokenizer<T: Tokenizer> = NormalizationFilterWrapper<T>;
+
+    fn transform<T: Tokenizer>(self, tokenizer: T) -> NormalizationFilterWrapper<T> {
+        NormalizationFilterWrapper {
+            normalization: self.0,
+            inner: tokenizer,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct NormalizationFilterWrapper<T> {
+    normalization: TextNormalization,
+    inner: T,
+}
+
+impl<T: Tokenizer> Tokenizer for NormalizationFilterWrapper<T> {
+    type TokenStream<'a> = NormalizationTokenStream<T::TokenStream<'a>>;
+
+    fn token_stream<'a>(&'a mut self, text: &'a str) -> Self::TokenStream<'a> {
+        NormalizationTokenStream {
+            normalization: self.normalization,
+            tail: self.inner.token_stream(text),
+        }
+    }
+}
+
+pub struct NormalizationTokenStream<T> {
+    normalization: TextNormalization,
+    tail: T,
+}
+
+impl<T: TokenStream> TokenStream for NormalizationTokenStream<T> {
+    fn advance(&mut self) -> bool {
+        if !self.tail.advance() {
+            return false;
+        }
+
+        let token = self.tail.token_mut();
+        token.text = self.normalization.normalize(&token.text);
+        true
+    }
+
+    fn token(&self) -> &Token {
+        self.tail.token()
+    }
+
+    fn token_mut(&mut self) -> &mut Token {
+        self.tail.token_mut()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::*;
+
+    #[rstest]
+    fn test_trim() {
+        let normalization = TextNormalization::Trim;
+        assert_eq!(normalization.normalize("  Café au lait \t"), "Café au lait");
+    }
+
+    #[rstest]
+    fn test_unicode_folding() {
+        let normalization = TextNormalization::UnicodeFolding;
+        assert_eq!(normalization.normalize("Café"), "Cafe");
+        assert_eq!(normalization.normalize("ﬁancé Ångström"), "fiance Angstrom");
+        // Characters without a decomposition are left alone.
+        assert_eq!(normalization.normalize("Straße 北京"), "Straße 北京");
+    }
+
+    #[cfg(feature = "icu")]
+    #[rstest]
+    fn test_icu_nfkc_casefold() {
+        let normalization = TextNormalization::IcuNfkcCasefold;
+        assert_eq!(normalization.normalize("Straße"), "strasse");
+        assert_eq!(normalization.normalize("ＣＡＦÉ"), "café");
+    }
+}
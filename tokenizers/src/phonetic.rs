@@ -0,0 +1,195 @@
+use once_cell::sync::Lazy;
+use rphonetic::{DoubleMetaphone, Encoder, Metaphone, Soundex};
+use serde::{Deserialize, Serialize};
+use tantivy::tokenizer::{Token, TokenFilter, TokenStream, Tokenizer};
+
+static SOUNDEX: Lazy<Soundex> = Lazy::new(Soundex::default);
+static METAPHONE: Lazy<Metaphone> = Lazy::new(Metaphone::default);
+static DOUBLE_METAPHONE: Lazy<DoubleMetaphone> = Lazy::new(DoubleMetaphone::default);
+
+/// The phonetic algorithm used to encode tokens.
+#[derive(Copy, Clone, Deserialize, Serialize, Debug, PartialEq, Eq, Hash)]
+pub enum PhoneticEncoder {
+    #[serde(rename = "soundex")]
+    Soundex,
+    #[serde(rename = "metaphone")]
+    Metaphone,
+    /// Emits both the primary and the alternate encoding, when they differ.
+    #[serde(rename = "double_metaphone")]
+    DoubleMetaphone,
+}
+
+impl PhoneticEncoder {
+    pub fn name(&self) -> &str {
+        match self {
+            PhoneticEncoder::Soundex => "soundex",
+            PhoneticEncoder::Metaphone => "metaphone",
+            PhoneticEncoder::DoubleMetaphone => "double_metaphone",
+        }
+    }
+
+    fn encode(&self, text: &str) -> Vec<String> {
+        let mut codes = match self {
+            PhoneticEncoder::Soundex => vec![SOUNDEX.encode(text)],
+            PhoneticEncoder::Metaphone => vec![METAPHONE.encode(text)],
+            PhoneticEncoder::DoubleMetaphone => {
+                let result = DOUBLE_METAPHONE.double_metaphone(text);
+                vec![result.primary(), result.alternate()]
+            }
+        };
+        codes.retain(|code| !code.is_empty());
+        codes.dedup();
+        codes
+    }
+}
+
+/// Replaces each token with its phonetic encoding, so that words that sound alike
+/// (e.g. "Smith" and "Smyth") are indexed as the same term. All codes produced for a
+/// token share its position. Tokens that can't be encoded, like numbers, are kept as-is.
+#[derive(Clone)]
+pub struct PhoneticFilter {
+    encoder: PhoneticEncoder,
+    keep_original: bool,
+}
+
+impl PhoneticFilter {
+    pub fn new(encoder: PhoneticEncoder, keep_original: bool) -> Self {
+        Self {
+            encoder,
+            keep_original,
+        }
+    }
+}
+
+impl TokenFilter for PhoneticFilter {
+    type Tokenizer<T: Tokenizer> = PhoneticFilterWrapper<T>;
+
+    fn transform<T: Tokenizer>(self, tokenizer: T) -> PhoneticFilterWrapper<T> {
+        PhoneticFilterWrapper {
+            encoder: self.encoder,
+            keep_original: self.keep_original,
+            inner: tokenizer,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct PhoneticFilterWrapper<T> {
+    encoder: PhoneticEncoder,
+    keep_original: bool,
+    inner: T,
+}
+
+impl<T: Tokenizer> Tokenizer for PhoneticFilterWrapper<T> {
+    type TokenStream<'a> = PhoneticTokenStream<T::TokenStream<'a>>;
+
+    fn token_stream<'a>(&'a mut self, text: &'a str) -> Self::TokenStream<'a> {
+        PhoneticTokenStream {
+            encoder: self.encoder,
+            keep_original: self.keep_original,
+            pending: Vec::new(),
+            tail: self.inner.token_stream(text),
+        }
+    }
+}
+
+pub struct PhoneticTokenStream<T> {
+    encoder: PhoneticEncoder,
+    keep_original: bool,
+    /// Codes for the current token that have yet to be emitted, in reverse order.
+    pending: Vec<String>,
+    tail: T,
+}
+
+impl<T: TokenStream> TokenStream for PhoneticTokenStream<T> {
+    fn advance(&mut self) -> bool {
+        if let Some(code) = self.pending.pop() {
+            self.tail.token_mut().text = code;
+            return true;
+        }
+
+        if !self.tail.advance() {
+            return false;
+        }
+
+        let original = self.tail.token().text.clone();
+        let mut codes = self.encoder.encode(&original);
+        if codes.is_empty() {
+            return true;
+        }
+        if self.keep_original && !codes.contains(&original) {
+            codes.insert(0, original);
+        }
+
+        codes.reverse();
+        self.tail.token_mut().text = codes.pop().expect("codes should not be empty");
+        self.pending = codes;
+
+        true
+    }
+
+    fn token(&self) -> &Token {
+        self.tail.token()
+    }
+
+    fn token_mut(&mut self) -> &mut Token {
+        self.tail.token_mut()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::*;
+    use tantivy::tokenizer::{LowerCaser, SimpleTokenizer, TextAnalyzer, Token};
+
+    fn test_helper(encoder: PhoneticEncoder, keep_original: bool, text: &str) -> Vec<Token> {
+        let mut analyzer = TextAnalyzer::builder(SimpleTokenizer::default())
+            .filter(LowerCaser)
+            .filter(PhoneticFilter::new(encoder, keep_original))
+            .build();
+        let mut token_stream = analyzer.token_stream(text);
+        let mut tokens: Vec<Token> = vec![];
+        while token_stream.advance() {
+            tokens.push(token_stream.token().clone());
+        }
+        tokens
+    }
+
+    fn texts(tokens: &[Token]) -> Vec<&str> {
+        tokens.iter().map(|token| token.text.as_str()).collect()
+    }
+
+    #[rstest]
+    fn test_soundex_filter() {
+        let tokens = test_helper(PhoneticEncoder::Soundex, false, "Smith Smyth Robert Rupert");
+        assert_eq!(texts(&tokens), vec!["S530", "S530", "R163", "R163"]);
+        assert_eq!(tokens[1].position, 1);
+        assert_eq!(tokens[1].offset_from, 6);
+        assert_eq!(tokens[1].offset_to, 11);
+    }
+
+    #[rstest]
+    fn test_metaphone_filter() {
+        let tokens = test_helper(PhoneticEncoder::Metaphone, false, "Smith Smyth");
+        assert_eq!(texts(&tokens), vec!["SM0", "SM0"]);
+    }
+
+    #[rstest]
+    fn test_double_metaphone_filter() {
+        let tokens = test_helper(PhoneticEncoder::DoubleMetaphone, false, "Smith Schmidt");
+        assert_eq!(texts(&tokens), vec!["SM0", "XMT", "XMT", "SMT"]);
+
+        // The primary and alternate codes are emitted at the position of the original token.
+        let positions: Vec<_> = tokens.iter().map(|token| token.position).collect();
+        assert_eq!(positions, vec![0, 0, 1, 1]);
+    }
+
+    #[rstest]
+    fn test_phonetic_filter_keep_original() {
+        let tokens = test_helper(PhoneticEncoder::Soundex, true, "Smith 1999");
+        assert_eq!(texts(&tokens), vec!["smith", "S530", "1999"]);
+        let positions: Vec<_> = tokens.iter().map(|token| token.position).collect();
+        assert_eq!(positions, vec![0, 0, 1]);
+    }
+}
@@ -0,0 +1,268 @@
+use std::collections::HashMap;
+use std::ffi::{c_char, c_void, CStr};
+use std::sync::{Arc, RwLock};
+
+use once_cell::sync::Lazy;
+use tantivy::tokenizer::{
+    PreTokenizedStream, PreTokenizedString, TextAnalyzer, TextAnalyzerBuilder, Token, Tokenizer,
+};
+use thiserror::Error;
+
+type TokenizerFactory = Arc<dyn Fn() -> TextAnalyzerBuilder + Send + Sync>;
+
+static CUSTOM_TOKENIZERS: Lazy<RwLock<HashMap<String, TokenizerFactory>>> =
+    Lazy::new(|| RwLock::new(HashMap::new()));
+
+/// The name of the Postgres rendezvous variable that `pg_search` publishes the address of
+/// `CUSTOM_TOKENIZER_REGISTRY` under.
+pub const CUSTOM_TOKENIZER_RENDEZVOUS: &str = "pg_search_custom_tokenizers";
+
+/// The version of `CustomTokenizerRegistry`, which changes whenever its layout or the
+/// signatures of its functions do.
+pub const CUSTOM_TOKENIZER_REGISTRY_VERSION: u32 = 1;
+
+/// Calls `emit` with `context` and the byte offsets of each token of the `len` bytes of UTF-8
+/// text at `text`, in order. Offsets that aren't on character boundaries are ignored.
+pub type CustomTokenizeFn = unsafe extern "C" fn(
+    text: *const u8,
+    len: usize,
+    emit: CustomTokenEmitFn,
+    context: *mut c_void,
+);
+
+pub type CustomTokenEmitFn =
+    unsafe extern "C" fn(context: *mut c_void, offset_from: usize, offset_to: usize);
+
+/// The registry as it's seen by other libraries, which can't call `register_custom_tokenizer`
+/// because they link their own copy of this crate, with a registry of its own. `pg_search`
+/// publishes the address of `CUSTOM_TOKENIZER_REGISTRY` in a rendezvous variable named
+/// `CUSTOM_TOKENIZER_RENDEZVOUS`, and a library that's loaded after it, from
+/// `shared_preload_libraries`, registers its tokenizers through it in its own `_PG_init`.
+#[repr(C)]
+pub struct CustomTokenizerRegistry {
+    /// Must be checked against `CUSTOM_TOKENIZER_REGISTRY_VERSION` before `register` is called.
+    pub version: u32,
+    /// Registers `tokenize` under the NUL-terminated `name`. Returns false if the name is
+    /// empty, isn't valid UTF-8, or is already registered.
+    pub register: unsafe extern "C" fn(name: *const c_char, tokenize: CustomTokenizeFn) -> bool,
+}
+
+pub static CUSTOM_TOKENIZER_REGISTRY: CustomTokenizerRegistry = CustomTokenizerRegistry {
+    version: CUSTOM_TOKENIZER_REGISTRY_VERSION,
+    register: register_extern_tokenizer,
+};
+
+unsafe extern "C" fn register_extern_tokenizer(
+    name: *const c_char,
+    tokenize: CustomTokenizeFn,
+) -> bool {
+    if name.is_null() {
+        return false;
+    }
+    let Ok(name) = CStr::from_ptr(name).to_str() else {
+        return false;
+    };
+    register_custom_tokenizer(name, ExternTokenizer { tokenize }).is_ok()
+}
+
+/// A tokenizer that another library registered through `CUSTOM_TOKENIZER_REGISTRY`.
+#[derive(Clone)]
+struct ExternTokenizer {
+    tokenize: CustomTokenizeFn,
+}
+
+unsafe extern "C" fn push_token_offsets(
+    context: *mut c_void,
+    offset_from: usize,
+    offset_to: usize,
+) {
+    let offsets = &mut *(context as *mut Vec<(usize, usize)>);
+    offsets.push((offset_from, offset_to));
+}
+
+impl Tokenizer for ExternTokenizer {
+    type TokenStream<'a> = PreTokenizedStream;
+
+    fn token_stream<'a>(&'a mut self, text: &'a str) -> Self::TokenStream<'a> {
+        let mut offsets: Vec<(usize, usize)> = vec![];
+        unsafe {
+            (self.tokenize)(
+                text.as_ptr(),
+                text.len(),
+                push_token_offsets,
+                &mut offsets as *mut Vec<(usize, usize)> as *mut c_void,
+            )
+        };
+
+        let tokens = offsets
+            .into_iter()
+            .filter(|(offset_from, offset_to)| {
+                offset_from < offset_to
+                    && text.is_char_boundary(*offset_from)
+                    && text.is_char_boundary(*offset_to)
+            })
+            .enumerate()
+            .map(|(position, (offset_from, offset_to))| Token {
+                offset_from,
+                offset_to,
+                position,
+                text: text[offset_from..offset_to].to_string(),
+                ..Token::default()
+            })
+            .collect();
+
+        PreTokenizedStream::from(PreTokenizedString {
+            text: text.to_string(),
+            tokens,
+        })
+    }
+}
+
+/// Registers a tokenizer under `name`, so that it can be used in a field's configuration
+/// as `{"type": "custom", "name": "<name>"}`.
+///
+/// The registry is local to the library this crate is linked into, and to the current process.
+/// Tokenizers should therefore be registered from code that runs in `pg_search`'s `_PG_init`,
+/// so that every backend and the index writer see the same tokenizers. Other libraries
+/// register theirs through `CUSTOM_TOKENIZER_REGISTRY` instead.
+pub fn register_custom_tokenizer<T: Tokenizer>(
+    name: &str,
+    tokenizer: T,
+) -> Result<(), CustomTokenizerError> {
+    if name.is_empty() {
+        return Err(CustomTokenizerError::EmptyName);
+    }
+
+    let mut tokenizers = CUSTOM_TOKENIZERS
+        .write()
+        .expect("custom tokenizer registry should not be poisoned");
+    if tokenizers.contains_key(name) {
+        return Err(CustomTokenizerError::AlreadyRegistered(name.to_string()));
+    }
+
+    let factory: TokenizerFactory =
+        Arc::new(move || TextAnalyzer::builder(tokenizer.clone()).dynamic());
+    tokenizers.insert(name.to_string(), factory);
+    Ok(())
+}
+
+/// The names of all registered custom tokenizers, in alphabetical order.
+pub fn custom_tokenizer_names() -> Vec<String> {
+    let tokenizers = CUSTOM_TOKENIZERS
+        .read()
+        .expect("custom tokenizer registry should not be poisoned");
+    let mut names: Vec<_> = tokenizers.keys().cloned().collect();
+    names.sort();
+    names
+}
+
+pub(crate) fn custom_text_analyzer_builder(
+    name: &str,
+) -> Result<TextAnalyzerBuilder, CustomTokenizerError> {
+    let tokenizers = CUSTOM_TOKENIZERS
+        .read()
+        .expect("custom tokenizer registry should not be poisoned");
+    tokenizers
+        .get(name)
+        .map(|factory| factory())
+        .ok_or_else(|| CustomTokenizerError::NotRegistered(name.to_string()))
+}
+
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum CustomTokenizerError {
+    #[error("custom tokenizer name must not be empty")]
+    EmptyName,
+
+    #[error("a custom tokenizer named '{0}' is already registered")]
+    AlreadyRegistered(String),
+
+    #[error("no custom tokenizer named '{0}' is registered")]
+    NotRegistered(String),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::*;
+    use tantivy::tokenizer::{SimpleTokenizer, TokenStream};
+
+    /// Splits text on dashes, as a library that registers a tokenizer from C would.
+    unsafe extern "C" fn split_on_dashes(
+        text: *const u8,
+        len: usize,
+        emit: CustomTokenEmitFn,
+        context: *mut c_void,
+    ) {
+        let text = std::slice::from_raw_parts(text, len);
+        let mut offset_from = 0;
+        for (offset, byte) in text.iter().enumerate() {
+            if *byte == b'-' {
+                emit(context, offset_from, offset);
+                offset_from = offset + 1;
+            }
+        }
+        emit(context, offset_from, len);
+        // Offsets that don't make up a token are ignored.
+        emit(context, len, len + 1);
+    }
+
+    #[rstest]
+    fn test_register_custom_tokenizer() {
+        register_custom_tokenizer("test_registry_simple", SimpleTokenizer::default()).unwrap();
+        assert!(custom_tokenizer_names().contains(&"test_registry_simple".to_string()));
+
+        let mut analyzer = custom_text_analyzer_builder("test_registry_simple")
+            .unwrap()
+            .build();
+        let mut token_stream = analyzer.token_stream("H2O NaCl");
+        let mut texts = vec![];
+        while token_stream.advance() {
+            texts.push(token_stream.token().text.clone());
+        }
+        assert_eq!(texts, vec!["H2O", "NaCl"]);
+
+        assert_eq!(
+            register_custom_tokenizer("test_registry_simple", SimpleTokenizer::default()),
+            Err(CustomTokenizerError::AlreadyRegistered(
+                "test_registry_simple".into()
+            ))
+        );
+        assert_eq!(
+            register_custom_tokenizer("", SimpleTokenizer::default()),
+            Err(CustomTokenizerError::EmptyName)
+        );
+        assert!(matches!(
+            custom_text_analyzer_builder("test_registry_missing"),
+            Err(CustomTokenizerError::NotRegistered(_))
+        ));
+    }
+
+    #[rstest]
+    fn test_register_extern_tokenizer() {
+        let name = CStr::from_bytes_with_nul(b"test_registry_extern\0").unwrap();
+        let registry = &CUSTOM_TOKENIZER_REGISTRY;
+        assert_eq!(registry.version, CUSTOM_TOKENIZER_REGISTRY_VERSION);
+        assert!(unsafe { (registry.register)(name.as_ptr(), split_on_dashes) });
+
+        let mut analyzer = custom_text_analyzer_builder("test_registry_extern")
+            .unwrap()
+            .build();
+        let mut token_stream = analyzer.token_stream("AB-12-ü");
+        let mut tokens = vec![];
+        while token_stream.advance() {
+            let token = token_stream.token();
+            tokens.push((token.text.clone(), token.offset_from, token.position));
+        }
+        assert_eq!(
+            tokens,
+            vec![
+                ("AB".to_string(), 0, 0),
+                ("12".to_string(), 3, 1),
+                ("ü".to_string(), 6, 2)
+            ]
+        );
+
+        assert!(!unsafe { (registry.register)(name.as_ptr(), split_on_dashes) });
+        assert!(!unsafe { (registry.register)(std::ptr::null(), split_on_dashes) });
+    }
+}
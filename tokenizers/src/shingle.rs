@@ -0,0 +1,215 @@
+use std::collections::VecDeque;
+
+use tantivy::tokenizer::{Token, TokenFilter, TokenStream, Tokenizer};
+use tantivy::TantivyError;
+
+const SHINGLE_SEPARATOR: &str = " ";
+
+/// Combines runs of `min_shingle_size` to `max_shingle_size` adjacent tokens into single
+/// tokens ("shingles"), so that documents containing a query's words next to each other
+/// score higher. A shingle is emitted at the position of its first token, and spans the
+/// offsets of all of its tokens.
+#[derive(Clone)]
+pub struct ShingleFilter {
+    min_shingle_size: usize,
+    max_shingle_size: usize,
+    output_unigrams: bool,
+}
+
+impl ShingleFilter {
+    pub fn new(
+        min_shingle_size: usize,
+        max_shingle_size: usize,
+        output_unigrams: bool,
+    ) -> tantivy::Result<Self> {
+        if min_shingle_size < 2 {
+            return Err(TantivyError::InvalidArgument(
+                "min_shingle_size must be at least 2".to_string(),
+            ));
+        }
+        if min_shingle_size > max_shingle_size {
+            return Err(TantivyError::InvalidArgument(
+                "min_shingle_size must not be greater than max_shingle_size".to_string(),
+            ));
+        }
+        Ok(Self {
+            min_shingle_size,
+            max_shingle_size,
+            output_unigrams,
+        })
+    }
+}
+
+impl TokenFilter for ShingleFilter {
+    type Tokenizer<T: Tokenizer> = ShingleFilterWrapper<T>;
+
+    fn transform<T: Tokenizer>(self, tokenizer: T) -> ShingleFilterWrapper<T> {
+        ShingleFilterWrapper {
+            filter: self,
+            inner: tokenizer,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct ShingleFilterWrapper<T> {
+    filter: ShingleFilter,
+    inner: T,
+}
+
+impl<T: Tokenizer> Tokenizer for ShingleFilterWrapper<T> {
+    type TokenStream<'a> = ShingleTokenStream<T::TokenStream<'a>>;
+
+    fn token_stream<'a>(&'a mut self, text: &'a str) -> Self::TokenStream<'a> {
+        ShingleTokenStream {
+            filter: self.filter.clone(),
+            window: VecDeque::with_capacity(self.filter.max_shingle_size),
+            pending: Vec::new(),
+            token: Token::default(),
+            tail: self.inner.token_stream(text),
+        }
+    }
+}
+
+pub struct ShingleTokenStream<T> {
+    filter: ShingleFilter,
+    /// The upcoming tokens of the base tokenizer, starting with the first token of the
+    /// next shingles to emit.
+    window: VecDeque<Token>,
+    /// Tokens starting at the front of the window that have yet to be emitted, in reverse order.
+    pending: Vec<Token>,
+    token: Token,
+    tail: T,
+}
+
+impl<T: TokenStream> ShingleTokenStream<T> {
+    fn shingle(tokens: &[Token]) -> Token {
+        let first = &tokens[0];
+        let last = &tokens[tokens.len() - 1];
+        let texts: Vec<&str> = tokens.iter().map(|token| token.text.as_str()).collect();
+        Token {
+            offset_from: first.offset_from,
+            offset_to: last.offset_to,
+            position: first.position,
+            text: texts.join(SHINGLE_SEPARATOR),
+            position_length: tokens.len(),
+        }
+    }
+}
+
+impl<T: TokenStream> TokenStream for ShingleTokenStream<T> {
+    fn advance(&mut self) -> bool {
+        loop {
+            if let Some(token) = self.pending.pop() {
+                self.token = token;
+                return true;
+            }
+
+            while self.window.len() < self.filter.max_shingle_size && self.tail.advance() {
+                self.window.push_back(self.tail.token().clone());
+            }
+            if self.window.is_empty() {
+                return false;
+            }
+
+            let window = self.window.make_contiguous();
+            let max_size = self.filter.max_shingle_size.min(window.len());
+            let mut tokens: Vec<Token> = (self.filter.min_shingle_size..=max_size)
+                .rev()
+                .map(|size| Self::shingle(&window[..size]))
+                .collect();
+
+            let unigram = self.window.pop_front().expect("window should not be empty");
+            if self.filter.output_unigrams {
+                tokens.push(unigram);
+            }
+            self.pending = tokens;
+        }
+    }
+
+    fn token(&self) -> &Token {
+        &self.token
+    }
+
+    fn token_mut(&mut self) -> &mut Token {
+        &mut self.token
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::*;
+    use tantivy::tokenizer::{LowerCaser, SimpleTokenizer, TextAnalyzer, Token};
+
+    fn test_helper(
+        min_shingle_size: usize,
+        max_shingle_size: usize,
+        output_unigrams: bool,
+        text: &str,
+    ) -> Vec<Token> {
+        let filter =
+            ShingleFilter::new(min_shingle_size, max_shingle_size, output_unigrams).unwrap();
+        let mut analyzer = TextAnalyzer::builder(SimpleTokenizer::default())
+            .filter(LowerCaser)
+            .filter(filter)
+            .build();
+        let mut token_stream = analyzer.token_stream(text);
+        let mut tokens: Vec<Token> = vec![];
+        while token_stream.advance() {
+            tokens.push(token_stream.token().clone());
+        }
+        tokens
+    }
+
+    fn texts(tokens: &[Token]) -> Vec<&str> {
+        tokens.iter().map(|token| token.text.as_str()).collect()
+    }
+
+    #[rstest]
+    fn test_shingle_filter() {
+        let tokens = test_helper(2, 2, true, "The Quick Brown fox");
+        assert_eq!(
+            texts(&tokens),
+            vec![
+                "the",
+                "the quick",
+                "quick",
+                "quick brown",
+                "brown",
+                "brown fox",
+                "fox"
+            ]
+        );
+
+        let shingle = &tokens[3];
+        assert_eq!(shingle.position, 1);
+        assert_eq!(shingle.position_length, 2);
+        assert_eq!(shingle.offset_from, 4);
+        assert_eq!(shingle.offset_to, 15);
+    }
+
+    #[rstest]
+    fn test_shingle_filter_without_unigrams() {
+        let tokens = test_helper(2, 3, false, "the quick brown fox");
+        assert_eq!(
+            texts(&tokens),
+            vec![
+                "the quick",
+                "the quick brown",
+                "quick brown",
+                "quick brown fox",
+                "brown fox"
+            ]
+        );
+
+        // A single token is too short to make a shingle.
+        assert!(test_helper(2, 3, false, "fox").is_empty());
+    }
+
+    #[rstest]
+    fn test_shingle_filter_invalid() {
+        assert!(ShingleFilter::new(1, 2, true).is_err());
+        assert!(ShingleFilter::new(3, 2, true).is_err());
+    }
+}